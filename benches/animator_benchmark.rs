@@ -0,0 +1,149 @@
+//! Times Animator::update_animation for a crowd of skinned characters against the previous
+//! per-frame evaluation, which found each node's channel by name and blended into a HashMap.
+//!
+//! cargo bench --bench animator_benchmark
+
+use glam::{vec3, Mat4, Quat, Vec3};
+use small_gl_core::animator::{AnimationRepeat, Animator};
+use small_gl_core::hash_map::HashMap;
+use small_gl_core::model_animation::{BoneData, ModelAnimation, NodeData};
+use small_gl_core::node_animation::{KeyPosition, KeyRotation, KeyScale, KeyframeBoundary, NodeAnimation};
+use small_gl_core::transform::Transform;
+use std::hint::black_box;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+const BONE_COUNT: usize = 64;
+const CHARACTER_COUNT: usize = 30;
+const FRAME_COUNT: usize = 300;
+const KEY_COUNT: usize = 60;
+
+fn main() {
+    let root_node = build_hierarchy(0);
+    let animation = build_animation();
+
+    let mut animators: Vec<Animator> = (0..CHARACTER_COUNT)
+        .map(|_| {
+            let mut animator = Animator::from_parts(
+                root_node.clone(),
+                Mat4::IDENTITY,
+                build_bone_data_map().into(),
+                vec![animation.clone()],
+            );
+            let clip = animator.animation_clip("benchmark", AnimationRepeat::Forever).unwrap();
+            animator.play_clip(&clip).unwrap();
+            animator
+        })
+        .collect();
+
+    let start = Instant::now();
+    for _ in 0..FRAME_COUNT {
+        for animator in animators.iter_mut() {
+            animator.update_animation(1.0 / 60.0);
+        }
+    }
+    let indexed = start.elapsed();
+
+    // each character has its own copy of the data, as the animators do
+    let characters: Vec<(ModelAnimation, HashMap<String, BoneData>)> =
+        (0..CHARACTER_COUNT).map(|_| (animation.clone(), build_bone_data_map())).collect();
+    let mut node_map: HashMap<Rc<str>, Transform> = HashMap::new();
+    let mut final_bones = vec![Mat4::IDENTITY; BONE_COUNT];
+
+    let start = Instant::now();
+    for frame in 0..FRAME_COUNT {
+        for (character_animation, bone_data_map) in characters.iter() {
+            node_map.clear();
+            let tick = (frame as f32 * 0.5) % (KEY_COUNT - 1) as f32;
+            let node_animations = character_animation.node_animations.borrow();
+            name_lookup_transforms(&root_node, &node_animations, &mut node_map, Transform::IDENTITY, tick);
+            for (name, transform) in node_map.iter() {
+                if let Some(bone_data) = bone_data_map.get(name.as_ref()) {
+                    final_bones[bone_data.bone_index as usize] = transform.mul_transform(bone_data.offset_transform).compute_matrix();
+                }
+            }
+            black_box(&final_bones);
+        }
+    }
+    let name_lookup = start.elapsed();
+
+    report("index bindings", indexed);
+    report("name lookups", name_lookup);
+    println!("speed up: {:.1}x", name_lookup.as_secs_f64() / indexed.as_secs_f64());
+}
+
+fn report(label: &str, elapsed: Duration) {
+    let frame_ms = elapsed.as_secs_f64() * 1000.0 / FRAME_COUNT as f64;
+    println!(
+        "{:>16}: {:.3} ms per frame for {} characters with {} bones",
+        label, frame_ms, CHARACTER_COUNT, BONE_COUNT
+    );
+}
+
+/// A chain of nodes, each the child of the previous one.
+fn build_hierarchy(index: usize) -> NodeData {
+    NodeData {
+        name: Rc::from(bone_name(index)),
+        transform: Transform::from_xyz(0.0, 1.0, 0.0),
+        children: if index + 1 < BONE_COUNT {
+            vec![build_hierarchy(index + 1)]
+        } else {
+            vec![]
+        },
+        meshes: Rc::new(vec![]),
+    }
+}
+
+fn build_bone_data_map() -> HashMap<String, BoneData> {
+    (0..BONE_COUNT)
+        .map(|index| (bone_name(index), BoneData::new(&bone_name(index), index as i32, Mat4::IDENTITY)))
+        .collect()
+}
+
+fn build_animation() -> ModelAnimation {
+    let node_animations = (0..BONE_COUNT)
+        .map(|index| NodeAnimation {
+            name: Rc::from(bone_name(index)),
+            positions: (0..KEY_COUNT)
+                .map(|key| KeyPosition::new(key as f32, vec3(0.0, 1.0, key as f32 * 0.01)))
+                .collect(),
+            rotations: (0..KEY_COUNT)
+                .map(|key| KeyRotation::new(key as f32, Quat::from_rotation_z(key as f32 * 0.01)))
+                .collect(),
+            quantized_rotations: vec![],
+            scales: vec![KeyScale::new(0.0, Vec3::ONE)],
+            boundary: KeyframeBoundary::Clamp,
+        })
+        .collect::<Vec<_>>();
+
+    ModelAnimation {
+        name: Rc::from("benchmark"),
+        duration: (KEY_COUNT - 1) as f32,
+        ticks_per_second: 30.0,
+        node_animations: node_animations.into(),
+        morph_animations: vec![],
+    }
+}
+
+fn bone_name(index: usize) -> String {
+    format!("mixamorig:Bone{}", index)
+}
+
+fn name_lookup_transforms(
+    node_data: &NodeData,
+    node_animations: &[NodeAnimation],
+    node_map: &mut HashMap<Rc<str>, Transform>,
+    parent_transform: Transform,
+    current_tick: f32,
+) {
+    let global_transform = match node_animations.iter().find(|node_anim| node_anim.name == node_data.name) {
+        Some(node_animation) => parent_transform.mul_transform(node_animation.get_animation_transform(current_tick)),
+        None => parent_transform.mul_transform(node_data.transform),
+    };
+
+    node_map.insert(node_data.name.clone(), global_transform);
+
+    for child_node in node_data.children.iter() {
+        name_lookup_transforms(child_node, node_animations, node_map, global_transform, current_tick);
+    }
+}
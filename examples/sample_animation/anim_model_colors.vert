@@ -12,9 +12,8 @@ uniform mat4 projection;
 uniform mat4 view;
 uniform mat4 model;
 
-const int MAX_BONES = 100;
 const int MAX_BONE_INFLUENCE = 4;
-uniform mat4 finalBonesMatrices[MAX_BONES];
+#include "bone_palette.glsl"
 
 //out vec2 TexCoords;
 flat out int fragColorId;
@@ -28,16 +27,16 @@ void main()
         if(boneIds[i] == -1) 
             continue;
 
-        if(boneIds[i] >= MAX_BONES) {
+        if(boneIds[i] >= boneCount()) {
             totalPosition = vec4(pos, 1.0f);
             break;
         }
 
-        vec4 localPosition = finalBonesMatrices[boneIds[i]] * vec4(pos, 1.0f);
+        vec4 localPosition = boneMatrix(boneIds[i]) * vec4(pos, 1.0f);
 
         totalPosition += localPosition * weights[i];
 
-        vec3 localNormal = mat3(finalBonesMatrices[boneIds[i]]) * norm;
+        vec3 localNormal = mat3(boneMatrix(boneIds[i])) * norm;
    }
 
     gl_Position =  projection * view * model * totalPosition;
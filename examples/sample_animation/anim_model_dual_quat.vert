@@ -0,0 +1,74 @@
+#version 330 core
+
+layout(location = 0) in vec3 pos;
+layout(location = 1) in vec3 norm;
+layout(location = 2) in vec2 tex;
+layout(location = 3) in vec3 tangent;
+layout(location = 4) in vec3 bitangent;
+layout(location = 5) in ivec4 boneIds;
+layout(location = 6) in vec4 weights;
+
+uniform mat4 projection;
+uniform mat4 view;
+uniform mat4 model;
+
+const int MAX_BONE_INFLUENCE = 4;
+
+// dual quaternion palette, set by Model::render with SkinningMethod::DualQuaternion
+#include "bone_palette.glsl"
+uniform mat4 nodeTransform;
+
+out vec2 TexCoords;
+
+vec3 rotate(vec4 q, vec3 v)
+{
+    return v + 2.0 * cross(q.xyz, cross(q.xyz, v) + q.w * v);
+}
+
+void main()
+{
+    vec4 blendReal = vec4(0.0f);
+    vec4 blendDual = vec4(0.0f);
+    vec4 firstReal = vec4(0.0f);
+    bool hasBones = false;
+
+    for(int i = 0 ; i < MAX_BONE_INFLUENCE ; i++)
+    {
+        if(boneIds[i] == -1 || boneIds[i] >= boneDualQuatCount())
+            continue;
+
+        vec4 real = boneDualQuatReal(boneIds[i]);
+        vec4 dual = boneDualQuatDual(boneIds[i]);
+
+        if(!hasBones) {
+            firstReal = real;
+            hasBones = true;
+        }
+
+        // q and -q are the same rotation, keep them in one hemisphere so they don't cancel out
+        float weight = dot(real, firstReal) < 0.0f ? -weights[i] : weights[i];
+        blendReal += real * weight;
+        blendDual += dual * weight;
+    }
+
+    vec4 totalPosition;
+    vec3 totalNormal;
+
+    if (hasBones && length(blendReal) > 0.0f) {
+        float len = length(blendReal);
+        blendReal /= len;
+        blendDual /= len;
+
+        vec3 translation = 2.0f * (blendReal.w * blendDual.xyz - blendDual.w * blendReal.xyz + cross(blendReal.xyz, blendDual.xyz));
+        totalPosition = vec4(rotate(blendReal, pos) + translation, 1.0f);
+        totalNormal = rotate(blendReal, norm);
+    } else {
+        // For verts without bones
+        totalPosition = nodeTransform * vec4(pos, 1.0f);
+        totalNormal = mat3(nodeTransform) * norm;
+    }
+
+    gl_Position =  projection * view * model * totalPosition;
+
+	TexCoords = tex;
+}
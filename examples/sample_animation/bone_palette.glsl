@@ -0,0 +1,34 @@
+// Bone transforms uploaded by BonePalette as a texture buffer, bound by Model::render.
+// Linear skinning stores four texels per bone, the columns of its matrix. Dual quaternion
+// skinning stores two, the real and dual parts.
+uniform samplerBuffer bonePalette;
+
+int boneCount()
+{
+    return textureSize(bonePalette) / 4;
+}
+
+mat4 boneMatrix(int boneId)
+{
+    return mat4(
+        texelFetch(bonePalette, boneId * 4),
+        texelFetch(bonePalette, boneId * 4 + 1),
+        texelFetch(bonePalette, boneId * 4 + 2),
+        texelFetch(bonePalette, boneId * 4 + 3)
+    );
+}
+
+int boneDualQuatCount()
+{
+    return textureSize(bonePalette) / 2;
+}
+
+vec4 boneDualQuatReal(int boneId)
+{
+    return texelFetch(bonePalette, boneId * 2);
+}
+
+vec4 boneDualQuatDual(int boneId)
+{
+    return texelFetch(bonePalette, boneId * 2 + 1);
+}
@@ -143,8 +143,8 @@ fn main() {
     let left = Rc::new(AnimationClip::new(209.0, 229.0, AnimationRepeat::Forever));
     let dying = Rc::new(AnimationClip::new(234.0, 293.0, AnimationRepeat::Once));
 
-    dancing_model.play_clip(&idle);
-    dancing_model.play_clip_with_transition(&forward, Duration::from_secs(6));
+    dancing_model.play_clip(&idle).unwrap();
+    dancing_model.play_clip_with_transition(&forward, Duration::from_secs(6)).unwrap();
 
     let model_path = "/Users/john/Dev_Rust/Dev/angry_gl_bots_rust/assets/Models/Bullet/Bullet.FBX";
     let bullet_model = ModelBuilder::new("bullet", model_path)
@@ -0,0 +1,55 @@
+#version 330 core
+
+layout(location = 0) in vec3 pos;
+layout(location = 1) in vec3 norm;
+layout(location = 2) in vec2 tex;
+layout(location = 3) in vec3 tangent;
+layout(location = 4) in vec3 bitangent;
+layout(location = 5) in ivec4 boneIds;
+layout(location = 6) in vec4 weights;
+
+// per instance, set by VertexAnimationRenderer::set_instances
+layout(location = 7) in mat4 instanceTransform;
+layout(location = 11) in vec2 instanceClip;
+
+uniform mat4 projection;
+uniform mat4 view;
+
+const int MAX_BONE_INFLUENCE = 4;
+
+// bone matrices baked with VertexAnimationFormat::BoneMatrices
+#include "vertex_animation.glsl"
+uniform int vatMeshId;
+
+out vec2 TexCoords;
+
+void main()
+{
+    int frameA;
+    int frameB;
+    float blend;
+    vatFrames(int(instanceClip.x), instanceClip.y, frameA, frameB, blend);
+
+    int boneCount = vatBoneCount();
+    vec4 totalPosition = vec4(0.0f);
+
+    for(int i = 0 ; i < MAX_BONE_INFLUENCE ; i++)
+    {
+        if(boneIds[i] == -1 || boneIds[i] >= boneCount)
+            continue;
+
+        mat4 boneMatrix = vatMatrix(frameA + boneIds[i] * 4) * (1.0f - blend) + vatMatrix(frameB + boneIds[i] * 4) * blend;
+        totalPosition += boneMatrix * vec4(pos, 1.0f) * weights[i];
+    }
+
+    // For verts without bones, the mesh's node matrices follow the bones in each frame
+    if (totalPosition == vec4(0.0f)) {
+        int nodeTexel = (boneCount + vatMeshId) * 4;
+        mat4 nodeTransform = vatMatrix(frameA + nodeTexel) * (1.0f - blend) + vatMatrix(frameB + nodeTexel) * blend;
+        totalPosition = nodeTransform * vec4(pos, 1.0f);
+    }
+
+    gl_Position = projection * view * instanceTransform * totalPosition;
+
+    TexCoords = tex;
+}
@@ -0,0 +1,36 @@
+#version 330 core
+
+layout(location = 0) in vec3 pos;
+layout(location = 1) in vec3 norm;
+layout(location = 2) in vec2 tex;
+
+// per instance, set by VertexAnimationRenderer::set_instances
+layout(location = 7) in mat4 instanceTransform;
+layout(location = 11) in vec2 instanceClip;
+
+uniform mat4 projection;
+uniform mat4 view;
+
+// skinned positions and normals baked with VertexAnimationFormat::VertexPositions
+#include "vertex_animation.glsl"
+uniform int vatMeshOffset;
+
+out vec2 TexCoords;
+out vec3 Normal;
+
+void main()
+{
+    int frameA;
+    int frameB;
+    float blend;
+    vatFrames(int(instanceClip.x), instanceClip.y, frameA, frameB, blend);
+
+    int vertexTexel = (vatMeshOffset + gl_VertexID) * 2;
+    vec3 position = mix(vatTexel(frameA + vertexTexel).xyz, vatTexel(frameB + vertexTexel).xyz, blend);
+    vec3 normal = mix(vatTexel(frameA + vertexTexel + 1).xyz, vatTexel(frameB + vertexTexel + 1).xyz, blend);
+
+    gl_Position = projection * view * instanceTransform * vec4(position, 1.0f);
+
+    Normal = normalize(mat3(instanceTransform) * normal);
+    TexCoords = tex;
+}
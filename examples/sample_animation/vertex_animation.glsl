@@ -0,0 +1,49 @@
+// Clips baked by VertexAnimationBake, bound by VertexAnimationRenderer::render. The texels run on across
+// rows of VAT_WIDTH: a header of clip count, texels per frame, frame rate and bone count, then one texel
+// per clip of its first texel, frame count, duration and whether it loops, followed by the frames.
+uniform sampler2D vatTexture;
+uniform float vatTime;
+
+const int VAT_WIDTH = 4096;
+
+vec4 vatTexel(int index)
+{
+    return texelFetch(vatTexture, ivec2(index % VAT_WIDTH, index / VAT_WIDTH), 0);
+}
+
+// The first texels of the frames either side of the clip's time, and how far it is between them.
+void vatFrames(int clip, float timeOffset, out int frameA, out int frameB, out float blend)
+{
+    vec4 header = vatTexel(0);
+    int frameStride = int(header.y);
+    float frameRate = header.z;
+
+    vec4 clipInfo = vatTexel(1 + clip);
+    int firstTexel = int(clipInfo.x);
+    int frameCount = int(clipInfo.y);
+    float duration = clipInfo.z;
+    bool looping = clipInfo.w > 0.5;
+
+    float time = vatTime + timeOffset;
+    if (looping && duration > 0.0f) {
+        time = mod(time, duration);
+    } else {
+        time = clamp(time, 0.0f, duration);
+    }
+
+    float frame = time * frameRate;
+    int index = min(int(frame), frameCount - 1);
+    frameA = firstTexel + index * frameStride;
+    frameB = firstTexel + min(index + 1, frameCount - 1) * frameStride;
+    blend = frame - float(index);
+}
+
+int vatBoneCount()
+{
+    return int(vatTexel(0).w);
+}
+
+mat4 vatMatrix(int texel)
+{
+    return mat4(vatTexel(texel), vatTexel(texel + 1), vatTexel(texel + 2), vatTexel(texel + 3));
+}
@@ -0,0 +1,282 @@
+use crate::model_animation::ModelAnimation;
+use crate::node_animation::{Interpolation, NodeAnimation, QuantizedKeyRotation, SmallestThree};
+use glam::Quat;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy)]
+pub struct CompressionSettings {
+    pub position_tolerance: f32,
+    /// In radians
+    pub rotation_tolerance: f32,
+    pub scale_tolerance: f32,
+    /// Stores the rotation keys as 48 bit smallest three quaternions
+    pub quantize_rotations: bool,
+}
+
+impl Default for CompressionSettings {
+    fn default() -> Self {
+        CompressionSettings {
+            position_tolerance: 0.001,
+            rotation_tolerance: 0.001,
+            scale_tolerance: 0.001,
+            quantize_rotations: false,
+        }
+    }
+}
+
+/// Key counts before and after compression, and the largest difference found at the original key times.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompressionReport {
+    pub keys_before: usize,
+    pub keys_after: usize,
+    pub max_position_error: f32,
+    /// In radians
+    pub max_rotation_error: f32,
+    pub max_scale_error: f32,
+}
+
+impl CompressionReport {
+    fn merge(&mut self, other: &CompressionReport) {
+        self.keys_before += other.keys_before;
+        self.keys_after += other.keys_after;
+        self.max_position_error = self.max_position_error.max(other.max_position_error);
+        self.max_rotation_error = self.max_rotation_error.max(other.max_rotation_error);
+        self.max_scale_error = self.max_scale_error.max(other.max_scale_error);
+    }
+}
+
+impl fmt::Display for CompressionReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let percent = if self.keys_before > 0 {
+            100.0 * self.keys_after as f32 / self.keys_before as f32
+        } else {
+            100.0
+        };
+        write!(
+            f,
+            "keys: {} -> {} ({:.1}%) max error position: {:.6} rotation: {:.6} rad scale: {:.6}",
+            self.keys_before, self.keys_after, percent, self.max_position_error, self.max_rotation_error, self.max_scale_error
+        )
+    }
+}
+
+/// Compresses every channel of the animation.
+pub fn compress_animation(model_animation: &ModelAnimation, settings: &CompressionSettings) -> CompressionReport {
+    let mut report = CompressionReport::default();
+    for node_animation in model_animation.node_animations.borrow_mut().iter_mut() {
+        report.merge(&compress_node_animation(node_animation, settings));
+    }
+    report
+}
+
+/// Removes the keys linear interpolation reconstructs within the tolerances and collapses constant
+/// channels to a single key. Channels with step or cubic spline keys are left as they are.
+pub fn compress_node_animation(node_animation: &mut NodeAnimation, settings: &CompressionSettings) -> CompressionReport {
+    let original = node_animation.clone();
+
+    if is_linear(node_animation.positions.iter().map(|key| &key.interpolation)) {
+        let kept = reduce_keys(
+            &node_animation
+                .positions
+                .iter()
+                .map(|key| (key.time_stamp, key.position))
+                .collect::<Vec<_>>(),
+            |a, b, t| a.lerp(b, t),
+            |a, b| a.distance(b),
+            settings.position_tolerance,
+        );
+        node_animation.positions = kept.into_iter().map(|index| node_animation.positions[index].clone()).collect();
+    }
+
+    if is_linear(node_animation.rotations.iter().map(|key| &key.interpolation)) {
+        let kept = reduce_keys(
+            &node_animation
+                .rotations
+                .iter()
+                .map(|key| (key.time_stamp, key.orientation))
+                .collect::<Vec<_>>(),
+            |a, b, t| a.slerp(b, t),
+            rotation_error,
+            settings.rotation_tolerance,
+        );
+        node_animation.rotations = kept.into_iter().map(|index| node_animation.rotations[index].clone()).collect();
+
+        if settings.quantize_rotations && !node_animation.rotations.is_empty() {
+            node_animation.quantized_rotations = node_animation
+                .rotations
+                .drain(..)
+                .map(|key| QuantizedKeyRotation {
+                    orientation: SmallestThree::from_quat(key.orientation),
+                    time_stamp: key.time_stamp,
+                })
+                .collect();
+        }
+    }
+
+    if is_linear(node_animation.scales.iter().map(|key| &key.interpolation)) {
+        let kept = reduce_keys(
+            &node_animation
+                .scales
+                .iter()
+                .map(|key| (key.time_stamp, key.scale))
+                .collect::<Vec<_>>(),
+            |a, b, t| a.lerp(b, t),
+            |a, b| a.distance(b),
+            settings.scale_tolerance,
+        );
+        node_animation.scales = kept.into_iter().map(|index| node_animation.scales[index].clone()).collect();
+    }
+
+    let mut report = CompressionReport {
+        keys_before: key_count(&original),
+        keys_after: key_count(node_animation),
+        ..CompressionReport::default()
+    };
+
+    // linear interpolation between keys means the largest errors are at the original key times
+    for time in original.key_times() {
+        let expected = original.get_animation_transform(time);
+        let compressed = node_animation.get_animation_transform(time);
+        report.max_position_error = report.max_position_error.max(expected.translation.distance(compressed.translation));
+        report.max_rotation_error = report
+            .max_rotation_error
+            .max(rotation_error(expected.rotation, compressed.rotation));
+        report.max_scale_error = report.max_scale_error.max(expected.scale.distance(compressed.scale));
+    }
+
+    report
+}
+
+fn key_count(node_animation: &NodeAnimation) -> usize {
+    node_animation.positions.len() + node_animation.rotations.len() + node_animation.quantized_rotations.len() + node_animation.scales.len()
+}
+
+fn is_linear<'a, T: 'a>(mut interpolations: impl Iterator<Item = &'a Interpolation<T>>) -> bool {
+    interpolations.all(|interpolation| matches!(interpolation, Interpolation::Linear))
+}
+
+/// Angle in radians between two rotations.
+fn rotation_error(a: Quat, b: Quat) -> f32 {
+    // atan2 rather than acos of the dot product, which is imprecise for small angles
+    let difference = a.inverse() * b;
+    2.0 * difference.xyz().length().atan2(difference.w.abs())
+}
+
+/// Indices of the keys to keep. A constant channel keeps only its first key. Otherwise each
+/// run of keys is replaced by its end keys for as long as interpolating between them
+/// reproduces the keys in between within the tolerance.
+fn reduce_keys<V: Copy>(
+    keys: &[(f32, V)],
+    interpolate: impl Fn(V, V, f32) -> V,
+    error: impl Fn(V, V) -> f32,
+    tolerance: f32,
+) -> Vec<usize> {
+    if keys.len() <= 1 {
+        return (0..keys.len()).collect();
+    }
+
+    let (_, first) = keys[0];
+    if keys.iter().all(|(_, value)| error(first, *value) <= tolerance) {
+        return vec![0];
+    }
+
+    let reconstructs = |from: usize, to: usize| {
+        let (from_time, from_value) = keys[from];
+        let (to_time, to_value) = keys[to];
+        keys[from + 1..to].iter().all(|(time, value)| {
+            let t = if to_time > from_time {
+                (time - from_time) / (to_time - from_time)
+            } else {
+                0.0
+            };
+            error(interpolate(from_value, to_value, t), *value) <= tolerance
+        })
+    };
+
+    let mut kept = vec![0];
+    let mut anchor = 0;
+    for end in 2..keys.len() {
+        if !reconstructs(anchor, end) {
+            anchor = end - 1;
+            kept.push(anchor);
+        }
+    }
+    kept.push(keys.len() - 1);
+    kept
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::animation_compression::{compress_node_animation, rotation_error, CompressionSettings};
+    use crate::node_animation::{KeyPosition, KeyRotation, KeyScale, KeyframeBoundary, NodeAnimation, SmallestThree};
+    use glam::{vec3, Quat, Vec3};
+    use rand::{thread_rng, Rng};
+    use std::rc::Rc;
+
+    /// 60 keys per channel: a straight line move, a curved turn and a constant scale.
+    fn baked_node_animation() -> NodeAnimation {
+        let times = (0..60).map(|key| key as f32);
+        NodeAnimation {
+            name: Rc::from("node"),
+            positions: times.clone().map(|time| KeyPosition::new(time, vec3(time, 1.0, 0.0))).collect(),
+            rotations: times
+                .clone()
+                .map(|time| KeyRotation::new(time, Quat::from_rotation_y((time * 0.1).sin())))
+                .collect(),
+            quantized_rotations: vec![],
+            scales: times.map(|time| KeyScale::new(time, Vec3::ONE)).collect(),
+            boundary: KeyframeBoundary::Clamp,
+        }
+    }
+
+    #[test]
+    fn test_reduces_keys_within_tolerance() {
+        let mut node_animation = baked_node_animation();
+        let original = node_animation.clone();
+        let settings = CompressionSettings::default();
+
+        let report = compress_node_animation(&mut node_animation, &settings);
+
+        assert_eq!(report.keys_before, 180);
+        assert_eq!(node_animation.positions.len(), 2);
+        assert_eq!(node_animation.scales.len(), 1);
+        assert!(node_animation.rotations.len() > 2 && node_animation.rotations.len() < 60);
+        assert_eq!(report.keys_after, 3 + node_animation.rotations.len());
+
+        assert!(report.max_position_error <= settings.position_tolerance);
+        assert!(report.max_rotation_error <= settings.rotation_tolerance);
+        assert_eq!(report.max_scale_error, 0.0);
+
+        // also within tolerance between the keys
+        for step in 0..590 {
+            let time = step as f32 * 0.1;
+            let expected = original.get_animation_transform(time);
+            let compressed = node_animation.get_animation_transform(time);
+            assert!(expected.translation.distance(compressed.translation) <= settings.position_tolerance);
+            assert!(rotation_error(expected.rotation, compressed.rotation) <= settings.rotation_tolerance * 1.01);
+        }
+        assert!(report.to_string().starts_with("keys: 180 -> "));
+    }
+
+    #[test]
+    fn test_quantized_rotations() {
+        for _ in 0..1000 {
+            let mut rng = thread_rng();
+            let axis = vec3(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)).normalize_or_zero();
+            let rotation = Quat::from_axis_angle(if axis == Vec3::ZERO { Vec3::Y } else { axis }, rng.gen_range(-6.0..6.0));
+            let decoded = SmallestThree::from_quat(rotation).to_quat();
+            assert!(rotation_error(rotation, decoded) < 0.0002, "{} {}", rotation, decoded);
+        }
+
+        let mut node_animation = baked_node_animation();
+        let settings = CompressionSettings {
+            quantize_rotations: true,
+            ..CompressionSettings::default()
+        };
+        let report = compress_node_animation(&mut node_animation, &settings);
+
+        assert!(node_animation.rotations.is_empty());
+        assert!(!node_animation.quantized_rotations.is_empty());
+        assert!(report.max_rotation_error <= settings.rotation_tolerance + 0.0002);
+    }
+}
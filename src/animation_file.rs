@@ -0,0 +1,233 @@
+use crate::animator::Animator;
+use crate::error::Error;
+use crate::error::Error::AnimationError;
+use crate::hash_map::HashMap;
+use crate::model_animation::{BoneData, BoneName, ModelAnimation, NodeData};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"SGCA";
+/// Bumped whenever a serialized type changes, as bincode can't skip or default fields. 2 added morph animations.
+const VERSION: u32 = 2;
+
+/// Animations saved independently of assimp, as readable RON or compact binary.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AnimationFile {
+    /// The node hierarchy the animations were authored on, needed to retarget them
+    #[serde(default)]
+    pub root_node: Option<NodeData>,
+    #[serde(default)]
+    pub bone_data_map: HashMap<BoneName, BoneData>,
+    pub animations: Vec<ModelAnimation>,
+}
+
+impl AnimationFile {
+    pub fn new(animations: Vec<ModelAnimation>) -> Self {
+        AnimationFile {
+            animations,
+            ..AnimationFile::default()
+        }
+    }
+
+    /// All of the animator's animations with the skeleton they play on.
+    pub fn from_animator(animator: &Animator) -> Self {
+        AnimationFile {
+            root_node: Some(animator.root_node.clone()),
+            bone_data_map: animator.bone_data_map.borrow().clone(),
+            animations: animator.model_animations.clone(),
+        }
+    }
+
+    pub fn to_ron(&self) -> Result<String, Error> {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()).map_err(|e| AnimationError(format!("animation file: {}", e)))
+    }
+
+    pub fn from_ron(ron_animations: &str) -> Result<Self, Error> {
+        ron::from_str(ron_animations).map_err(|e| AnimationError(format!("animation file: {}", e)))
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bincode::serialize_into(&mut bytes, self).map_err(|e| AnimationError(format!("animation file: {}", e)))?;
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() < 8 || &bytes[..4] != MAGIC {
+            return Err(AnimationError("not an animation file".to_string()));
+        }
+
+        let version = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+        if version != VERSION {
+            return Err(AnimationError(format!("unsupported animation file version: {}", version)));
+        }
+
+        bincode::deserialize(&bytes[8..]).map_err(|e| AnimationError(format!("animation file: {}", e)))
+    }
+
+    /// Saves as RON if the path has a `ron` extension, otherwise as binary.
+    pub fn save(&self, path: &str) -> Result<(), Error> {
+        if is_ron(path) {
+            std::fs::write(path, self.to_ron()?)?;
+        } else {
+            std::fs::write(path, self.to_bytes()?)?;
+        }
+        Ok(())
+    }
+
+    pub fn load(path: &str) -> Result<Self, Error> {
+        if is_ron(path) {
+            AnimationFile::from_ron(&std::fs::read_to_string(path)?)
+        } else {
+            AnimationFile::from_bytes(&std::fs::read(path)?)
+        }
+    }
+
+    /// True for the extensions save and load use, `ron` and `anim`.
+    pub fn is_animation_file(path: &str) -> bool {
+        matches!(
+            Path::new(path).extension().and_then(|extension| extension.to_str()),
+            Some("ron") | Some("anim")
+        )
+    }
+}
+
+fn is_ron(path: &str) -> bool {
+    Path::new(path).extension().is_some_and(|extension| extension == "ron")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::animation_compression::{compress_animation, CompressionSettings};
+    use crate::animation_file::AnimationFile;
+    use crate::error::Error;
+    use crate::hash_map::HashMap;
+    use crate::model_animation::{BoneData, ModelAnimation, NodeData};
+    use crate::node_animation::{Interpolation, KeyPosition, KeyRotation, KeyScale, KeyframeBoundary, NodeAnimation};
+    use crate::transform::Transform;
+    use glam::{vec3, Mat4, Quat, Vec3};
+    use std::rc::Rc;
+
+    fn test_file() -> AnimationFile {
+        let root_node = NodeData {
+            name: Rc::from("hips"),
+            transform: Transform::from_xyz(0.0, 1.0, 0.0),
+            children: vec![NodeData {
+                name: Rc::from("spine"),
+                transform: Transform::from_rotation(Quat::from_rotation_x(0.3)),
+                children: vec![],
+                meshes: Rc::new(vec![0, 2]),
+            }],
+            meshes: Rc::new(vec![]),
+        };
+
+        let mut bone_data_map = HashMap::new();
+        bone_data_map.insert("spine".to_string(), BoneData::new("spine", 0, Mat4::from_rotation_z(0.5)));
+
+        let turn = ModelAnimation {
+            name: Rc::from("turn"),
+            duration: 30.0,
+            ticks_per_second: 24.0,
+            node_animations: vec![NodeAnimation {
+                name: Rc::from("spine"),
+                positions: vec![
+                    KeyPosition {
+                        position: Vec3::ZERO,
+                        time_stamp: 0.0,
+                        interpolation: Interpolation::Step,
+                    },
+                    KeyPosition::new(15.0, vec3(0.1, 0.2, 0.3)),
+                    KeyPosition::new(30.0, vec3(1.0, 2.0, 3.0)),
+                ],
+                rotations: (0..31)
+                    .map(|key| KeyRotation::new(key as f32, Quat::from_rotation_y(key as f32 * 0.05)))
+                    .collect(),
+                quantized_rotations: vec![],
+                scales: vec![KeyScale {
+                    scale: Vec3::ONE,
+                    time_stamp: 0.0,
+                    interpolation: Interpolation::CubicSpline {
+                        in_tangent: Vec3::ZERO,
+                        out_tangent: Vec3::X,
+                    },
+                }],
+                boundary: KeyframeBoundary::Wrap,
+            }]
+            .into(),
+            morph_animations: vec![],
+        };
+
+        // a compressed copy with quantized rotations
+        let mut compressed = turn.clone();
+        compressed.name = Rc::from("turn_compressed");
+        let settings = CompressionSettings {
+            quantize_rotations: true,
+            ..CompressionSettings::default()
+        };
+        compress_animation(&compressed, &settings);
+
+        AnimationFile {
+            root_node: Some(root_node),
+            bone_data_map,
+            animations: vec![turn, compressed],
+        }
+    }
+
+    fn assert_same_sampling(loaded: &AnimationFile, original: &AnimationFile) {
+        assert_eq!(loaded.animations.len(), original.animations.len());
+        for (loaded, original) in loaded.animations.iter().zip(original.animations.iter()) {
+            assert_eq!(loaded.name, original.name);
+            assert_eq!(loaded.ticks_per_second, original.ticks_per_second);
+
+            let loaded_channels = loaded.node_animations.borrow();
+            let original_channels = original.node_animations.borrow();
+            for (loaded_channel, original_channel) in loaded_channels.iter().zip(original_channels.iter()) {
+                for step in -10..400 {
+                    let tick = step as f32 * 0.1;
+                    assert_eq!(
+                        loaded_channel.get_animation_transform(tick),
+                        original_channel.get_animation_transform(tick)
+                    );
+                }
+            }
+        }
+
+        let root_node = loaded.root_node.as_ref().unwrap();
+        assert_eq!(
+            root_node.children[0].transform,
+            original.root_node.as_ref().unwrap().children[0].transform
+        );
+        assert_eq!(root_node.children[0].meshes, Rc::new(vec![0, 2]));
+        assert_eq!(
+            loaded.bone_data_map["spine"].offset_transform,
+            original.bone_data_map["spine"].offset_transform
+        );
+    }
+
+    #[test]
+    fn test_ron_round_trip() {
+        let file = test_file();
+        let loaded = AnimationFile::from_ron(&file.to_ron().unwrap()).unwrap();
+        assert_same_sampling(&loaded, &file);
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        let file = test_file();
+        let bytes = file.to_bytes().unwrap();
+        let loaded = AnimationFile::from_bytes(&bytes).unwrap();
+        assert_same_sampling(&loaded, &file);
+
+        assert!(AnimationFile::from_bytes(b"not animations").is_err());
+        assert!(AnimationFile::from_bytes(&bytes[..bytes.len() / 2]).is_err());
+
+        // files from before morph animations are reported by version rather than failing to decode
+        let mut old_bytes = bytes.clone();
+        old_bytes[4..8].copy_from_slice(&1u32.to_le_bytes());
+        match AnimationFile::from_bytes(&old_bytes) {
+            Err(Error::AnimationError(message)) => assert!(message.contains("version: 1"), "{}", message),
+            result => panic!("expected version error, got: {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use crate::animator::PlayingAnimation;
+use crate::error::Error;
+use crate::error::Error::AnimationError;
+use crate::pose::{add_transform, transform_difference, Pose};
+use crate::skeleton::Skeleton;
+use crate::transform::Transform;
+
+/// Per skeleton node weights limiting which nodes a layer affects.
+#[derive(Debug, Clone)]
+pub struct BoneMask {
+    pub node_weights: Vec<f32>,
+}
+
+impl BoneMask {
+    /// Masks in the named bone and all of its descendants.
+    pub fn from_subtree(skeleton: &Skeleton, root_bone: &str) -> Result<Self, Error> {
+        let root_index = skeleton
+            .find_node(root_bone)
+            .ok_or_else(|| AnimationError(format!("bone mask bone not found: {}", root_bone)))?;
+
+        let mut node_weights = vec![0.0; skeleton.len()];
+        node_weights[root_index] = 1.0;
+
+        // parents come before their children, so a single pass marks the whole subtree
+        for (node_index, node) in skeleton.nodes.iter().enumerate().skip(root_index + 1) {
+            if let Some(parent_index) = node.parent {
+                node_weights[node_index] = node_weights[parent_index];
+            }
+        }
+
+        Ok(BoneMask { node_weights })
+    }
+
+    /// Masks in only the listed bones.
+    pub fn from_bones(skeleton: &Skeleton, bone_names: &[&str]) -> Result<Self, Error> {
+        let unknown: Vec<&str> = bone_names
+            .iter()
+            .filter(|name| skeleton.find_node(name).is_none())
+            .copied()
+            .collect();
+        if !unknown.is_empty() {
+            return Err(AnimationError(format!("bone mask bones not found: {:?}", unknown)));
+        }
+
+        let node_weights = skeleton
+            .nodes
+            .iter()
+            .map(|node| if bone_names.contains(&node.name.as_ref()) { 1.0 } else { 0.0 })
+            .collect();
+
+        Ok(BoneMask { node_weights })
+    }
+
+    pub fn weight(&self, node_index: usize) -> f32 {
+        self.node_weights[node_index]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LayerBlendMode {
+    /// Blends the layer's pose over the layers below it.
+    Override,
+    /// Adds the layer's difference from its pose at the reference tick to the layers below it.
+    Additive { reference_tick: f32 },
+}
+
+/// A clip played on top of the animator's base animation, limited by an optional bone mask.
+#[derive(Debug, Clone)]
+pub struct AnimationLayer {
+    pub animation: PlayingAnimation,
+    pub weight: f32,
+    /// None applies the layer to the whole skeleton
+    pub mask: Option<BoneMask>,
+    pub blend_mode: LayerBlendMode,
+    /// The clip sampled at the reference tick, for additive layers
+    pub reference_pose: Pose,
+}
+
+impl AnimationLayer {
+    /// The weight the layer is applied with to a node
+    pub fn node_weight(&self, node_index: usize) -> f32 {
+        match &self.mask {
+            Some(mask) => self.weight * mask.weight(node_index),
+            None => self.weight,
+        }
+    }
+}
+
+/// Adds the difference between the additive and reference transforms to the base transform, scaled by weight.
+pub fn add_transform_weighted(base: Transform, additive: Transform, reference: Transform, weight: f32) -> Transform {
+    add_transform(base, transform_difference(reference, additive), weight)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::animation_layer::LayerBlendMode;
+    use crate::animator::{AnimationClip, AnimationRepeat, Animator};
+    use crate::hash_map::HashMap;
+    use crate::model_animation::{ModelAnimation, NodeData};
+    use crate::node_animation::{KeyPosition, KeyRotation, KeyScale, KeyframeBoundary, NodeAnimation};
+    use crate::transform::Transform;
+    use glam::{vec3, Mat4, Quat, Vec3};
+    use std::rc::Rc;
+
+    fn node(name: &str, children: Vec<NodeData>) -> NodeData {
+        NodeData {
+            name: Rc::from(name),
+            transform: Transform::IDENTITY,
+            children,
+            meshes: Rc::new(vec![]),
+        }
+    }
+
+    /// Channels for the named nodes, moving from `from` to `to` over 10 ticks.
+    fn animation(name: &str, node_names: &[&str], from: Vec3, to: Vec3) -> ModelAnimation {
+        let node_animations = node_names
+            .iter()
+            .map(|node_name| NodeAnimation {
+                name: Rc::from(*node_name),
+                positions: vec![KeyPosition::new(0.0, from), KeyPosition::new(10.0, to)],
+                rotations: vec![KeyRotation::new(0.0, Quat::IDENTITY)],
+                quantized_rotations: vec![],
+                scales: vec![KeyScale::new(0.0, Vec3::ONE)],
+                boundary: KeyframeBoundary::Clamp,
+            })
+            .collect::<Vec<_>>();
+
+        ModelAnimation {
+            name: Rc::from(name),
+            duration: 10.0,
+            ticks_per_second: 10.0,
+            node_animations: node_animations.into(),
+            morph_animations: vec![],
+        }
+    }
+
+    fn test_animator() -> Animator {
+        let root_node = node("hips", vec![node("spine", vec![node("arm", vec![])]), node("leg", vec![])]);
+        let all_nodes = ["hips", "spine", "arm", "leg"];
+
+        let animations = vec![
+            animation("run", &all_nodes, Vec3::ZERO, vec3(10.0, 0.0, 0.0)),
+            animation("shoot", &all_nodes, Vec3::ZERO, vec3(0.0, 0.0, 10.0)),
+            animation("breathe", &["spine"], vec3(0.0, 1.0, 0.0), vec3(0.0, 3.0, 0.0)),
+        ];
+
+        let mut animator = Animator::from_parts(root_node, Mat4::IDENTITY, HashMap::new().into(), animations);
+        let run = animator.animation_clip("run", AnimationRepeat::Forever).unwrap();
+        animator.play_clip(&run).unwrap();
+        animator
+    }
+
+    fn local_translation(animator: &Animator, name: &str) -> Vec3 {
+        let node_index = animator.skeleton.find_node(name).unwrap();
+        animator.local_pose.borrow()[node_index].translation
+    }
+
+    #[test]
+    fn test_bone_masks() {
+        let animator = test_animator();
+
+        let upper_body = animator.bone_mask_from_subtree("spine").unwrap();
+        assert_eq!(upper_body.node_weights, vec![0.0, 1.0, 1.0, 0.0]);
+
+        let hands = animator.bone_mask_from_bones(&["arm", "hips"]).unwrap();
+        assert_eq!(hands.node_weights, vec![1.0, 0.0, 1.0, 0.0]);
+
+        assert!(animator.bone_mask_from_subtree("tail").is_err());
+        assert!(animator.bone_mask_from_bones(&["arm", "tail"]).is_err());
+    }
+
+    #[test]
+    fn test_override_layer_with_mask() {
+        let mut animator = test_animator();
+
+        let shoot = Rc::new(AnimationClip::from_animation("shoot", 0.0, 10.0, AnimationRepeat::Forever));
+        let upper_body = animator.bone_mask_from_subtree("spine").unwrap();
+        animator.add_layer(&shoot, 1.0, Some(upper_body), LayerBlendMode::Override).unwrap();
+
+        animator.update_animation(0.5);
+
+        // the legs run while the upper body shoots
+        assert_eq!(local_translation(&animator, "hips"), vec3(5.0, 0.0, 0.0));
+        assert_eq!(local_translation(&animator, "leg"), vec3(5.0, 0.0, 0.0));
+        assert_eq!(local_translation(&animator, "spine"), vec3(0.0, 0.0, 5.0));
+        assert_eq!(local_translation(&animator, "arm"), vec3(0.0, 0.0, 5.0));
+
+        animator.layers[0].weight = 0.5;
+        animator.update_animation(0.1);
+        assert_eq!(local_translation(&animator, "arm"), vec3(3.0, 0.0, 3.0));
+    }
+
+    #[test]
+    fn test_additive_layer() {
+        let mut animator = test_animator();
+
+        let breathe = Rc::new(AnimationClip::from_animation("breathe", 0.0, 10.0, AnimationRepeat::Forever));
+        animator
+            .add_layer(&breathe, 1.0, None, LayerBlendMode::Additive { reference_tick: 0.0 })
+            .unwrap();
+
+        animator.update_animation(0.5);
+
+        // breathe moves the spine up by 1.0 from its reference pose by tick 5, on top of the run
+        assert_eq!(local_translation(&animator, "spine"), vec3(5.0, 1.0, 0.0));
+        assert_eq!(local_translation(&animator, "leg"), vec3(5.0, 0.0, 0.0));
+
+        // at tick 8 breathe is 1.6 above its reference pose
+        animator.layers[0].weight = 0.5;
+        animator.update_animation(0.3);
+        assert!(local_translation(&animator, "spine").abs_diff_eq(vec3(8.0, 0.8, 0.0), 0.0001));
+    }
+}
@@ -0,0 +1,405 @@
+use crate::animator::{AnimationClip, Animator};
+use crate::error::Error;
+use crate::error::Error::AnimationError;
+use crate::hash_map::HashMap;
+use crate::transform::Transform;
+use serde::{Deserialize, Serialize};
+use std::rc::Rc;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ParameterValue {
+    Float(f32),
+    Bool(bool),
+    /// Set by gameplay and reset when a transition using it is taken.
+    Trigger(bool),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Condition {
+    Greater(String, f32),
+    Less(String, f32),
+    IsTrue(String),
+    IsFalse(String),
+    Triggered(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateDefinition {
+    pub name: String,
+    pub clip: AnimationClip,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransitionDefinition {
+    /// None allows the transition from any state.
+    #[serde(default)]
+    pub from: Option<String>,
+    pub to: String,
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    /// Crossfade time in seconds. Zero switches immediately.
+    #[serde(default)]
+    pub duration: f32,
+    /// Fraction of the from state's clip that must have played before the transition can be taken, measured in the
+    /// direction of playback so that 1.0 is the end of a reversed clip's play through too.
+    #[serde(default)]
+    pub exit_time: Option<f32>,
+}
+
+/// The serializable description of a state machine, usually authored as RON.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateMachineDefinition {
+    pub initial_state: String,
+    pub states: Vec<StateDefinition>,
+    #[serde(default)]
+    pub transitions: Vec<TransitionDefinition>,
+    #[serde(default)]
+    pub parameters: HashMap<String, ParameterValue>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AnimationState {
+    pub name: String,
+    pub clip: Rc<AnimationClip>,
+}
+
+#[derive(Debug, Clone)]
+pub struct StateTransition {
+    pub from: Option<usize>,
+    pub to: usize,
+    pub conditions: Vec<Condition>,
+    pub duration: f32,
+    pub exit_time: Option<f32>,
+}
+
+/// Picks the animator's clip from states, parameters and conditional transitions.
+#[derive(Debug, Clone)]
+pub struct AnimationStateMachine {
+    pub states: Vec<AnimationState>,
+    pub transitions: Vec<StateTransition>,
+    pub parameters: HashMap<String, ParameterValue>,
+    pub initial_state: usize,
+    current_state: Option<usize>,
+}
+
+impl AnimationStateMachine {
+    pub fn new(definition: StateMachineDefinition) -> Result<Self, Error> {
+        let states: Vec<AnimationState> = definition
+            .states
+            .into_iter()
+            .map(|state| AnimationState {
+                name: state.name,
+                clip: Rc::new(state.clip),
+            })
+            .collect();
+
+        let find_state = |name: &str| {
+            states
+                .iter()
+                .position(|state| state.name == name)
+                .ok_or_else(|| AnimationError(format!("state machine state not found: {}", name)))
+        };
+
+        let initial_state = find_state(&definition.initial_state)?;
+
+        let mut transitions = vec![];
+        for transition in definition.transitions {
+            let from = match &transition.from {
+                Some(name) => Some(find_state(name)?),
+                None => None,
+            };
+            transitions.push(StateTransition {
+                from,
+                to: find_state(&transition.to)?,
+                conditions: transition.conditions,
+                duration: transition.duration,
+                exit_time: transition.exit_time,
+            });
+        }
+
+        Ok(AnimationStateMachine {
+            states,
+            transitions,
+            parameters: definition.parameters,
+            initial_state,
+            current_state: None,
+        })
+    }
+
+    pub fn from_ron(ron_definition: &str) -> Result<Self, Error> {
+        let definition: StateMachineDefinition =
+            ron::from_str(ron_definition).map_err(|e| AnimationError(format!("state machine definition: {}", e)))?;
+        AnimationStateMachine::new(definition)
+    }
+
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let ron_definition = std::fs::read_to_string(path)?;
+        AnimationStateMachine::from_ron(&ron_definition)
+    }
+
+    pub fn set_float(&mut self, name: &str, value: f32) {
+        self.parameters.insert(name.to_string(), ParameterValue::Float(value));
+    }
+
+    pub fn set_bool(&mut self, name: &str, value: bool) {
+        self.parameters.insert(name.to_string(), ParameterValue::Bool(value));
+    }
+
+    pub fn set_trigger(&mut self, name: &str) {
+        self.parameters.insert(name.to_string(), ParameterValue::Trigger(true));
+    }
+
+    /// Name of the active state, None until the first update.
+    pub fn current_state(&self) -> Option<&str> {
+        self.current_state.map(|index| self.states[index].name.as_str())
+    }
+
+    /// Errors if a state's clip names an animation the animator doesn't have. Such states are never entered.
+    pub fn check_clips(&self, animator: &Animator) -> Result<(), Error> {
+        match self
+            .states
+            .iter()
+            .find(|state| animator.new_playing_animation(&state.clip).is_none())
+        {
+            Some(state) => Err(AnimationError(format!(
+                "state: {} plays an animation not in the model: {}",
+                state.name,
+                state.clip.animation_name.as_deref().unwrap_or_default()
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Takes at most one transition whose conditions hold, then updates the animator. Returns the animator's root motion.
+    /// A transition to a state whose clip can't be played is skipped, leaving the current state playing.
+    pub fn update(&mut self, animator: &mut Animator, delta_time: f32) -> Transform {
+        match self.current_state {
+            None => {
+                if animator.play_clip(&self.states[self.initial_state].clip).is_ok() {
+                    self.current_state = Some(self.initial_state);
+                }
+            }
+            Some(current_state) => {
+                if let Some(transition_index) = self.find_transition(animator, current_state) {
+                    self.take_transition(animator, transition_index);
+                }
+            }
+        }
+
+        animator.update_animation(delta_time)
+    }
+
+    /// The weight of each state currently contributing to the animator's pose.
+    pub fn state_weights(&self, animator: &Animator) -> Vec<(String, f32)> {
+        let mut state_weights: Vec<(String, f32)> = vec![];
+
+        for (clip, weight) in animator.clip_weights() {
+            if let Some(state) = self.states.iter().find(|state| Rc::ptr_eq(&state.clip, &clip)) {
+                match state_weights.iter_mut().find(|(name, _)| *name == state.name) {
+                    Some((_, total)) => *total += weight,
+                    None => state_weights.push((state.name.clone(), weight)),
+                }
+            }
+        }
+        state_weights
+    }
+
+    fn find_transition(&self, animator: &Animator, current_state: usize) -> Option<usize> {
+        self.transitions.iter().position(|transition| {
+            let from_matches = match transition.from {
+                Some(from) => from == current_state,
+                None => transition.to != current_state,
+            };
+
+            let exit_time_reached = match transition.exit_time {
+                Some(exit_time) => animator.current_animation.played_time() >= exit_time || animator.is_finished(),
+                None => true,
+            };
+
+            from_matches && exit_time_reached && transition.conditions.iter().all(|condition| self.condition_holds(condition))
+        })
+    }
+
+    fn take_transition(&mut self, animator: &mut Animator, transition_index: usize) {
+        let transition = &self.transitions[transition_index];
+        let clip = &self.states[transition.to].clip;
+
+        let played = if transition.duration > 0.0 {
+            animator.play_clip_with_transition(clip, Duration::from_secs_f32(transition.duration))
+        } else {
+            animator.play_clip(clip)
+        };
+        if played.is_err() {
+            return;
+        }
+        self.current_state = Some(transition.to);
+
+        for condition in transition.conditions.iter() {
+            if let Condition::Triggered(name) = condition {
+                self.parameters.insert(name.clone(), ParameterValue::Trigger(false));
+            }
+        }
+    }
+
+    fn condition_holds(&self, condition: &Condition) -> bool {
+        match condition {
+            Condition::Greater(name, threshold) => {
+                matches!(self.parameters.get(name), Some(ParameterValue::Float(value)) if value > threshold)
+            }
+            Condition::Less(name, threshold) => {
+                matches!(self.parameters.get(name), Some(ParameterValue::Float(value)) if value < threshold)
+            }
+            Condition::IsTrue(name) => matches!(self.parameters.get(name), Some(ParameterValue::Bool(true))),
+            Condition::IsFalse(name) => matches!(self.parameters.get(name), Some(ParameterValue::Bool(false))),
+            Condition::Triggered(name) => matches!(self.parameters.get(name), Some(ParameterValue::Trigger(true))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::animation_state_machine::AnimationStateMachine;
+    use crate::animator::Animator;
+    use crate::hash_map::HashMap;
+    use crate::model_animation::{ModelAnimation, NodeData};
+    use crate::node_animation::{KeyPosition, KeyframeBoundary, NodeAnimation};
+    use crate::transform::Transform;
+    use glam::{vec3, Mat4, Vec3};
+    use std::rc::Rc;
+
+    const DEFINITION: &str = r#"(
+        initial_state: "idle",
+        parameters: { "speed": Float(0.0), "attack": Trigger(false) },
+        states: [
+            (name: "idle", clip: (animation_name: Some("idle"), start_tick: 0.0, end_tick: 10.0, repeat: Forever)),
+            (name: "walk", clip: (animation_name: Some("walk"), start_tick: 0.0, end_tick: 10.0, repeat: Forever)),
+            (name: "attack", clip: (animation_name: Some("attack"), start_tick: 0.0, end_tick: 10.0, repeat: Once)),
+        ],
+        transitions: [
+            (from: Some("idle"), to: "walk", conditions: [Greater("speed", 0.5)], duration: 0.4),
+            (from: Some("walk"), to: "idle", conditions: [Less("speed", 0.5)], duration: 0.4),
+            (to: "attack", conditions: [Triggered("attack")]),
+            (from: Some("attack"), to: "idle", exit_time: Some(1.0), duration: 0.2),
+        ],
+    )"#;
+
+    fn test_animator() -> Animator {
+        let root_node = NodeData {
+            name: Rc::from("root"),
+            transform: Transform::IDENTITY,
+            children: vec![],
+            meshes: Rc::new(vec![]),
+        };
+
+        let animation = |name: &str| ModelAnimation {
+            name: Rc::from(name),
+            duration: 10.0,
+            ticks_per_second: 10.0,
+            node_animations: vec![NodeAnimation {
+                name: Rc::from("root"),
+                positions: vec![KeyPosition::new(0.0, Vec3::ZERO), KeyPosition::new(10.0, vec3(10.0, 0.0, 0.0))],
+                rotations: vec![],
+                quantized_rotations: vec![],
+                scales: vec![],
+                boundary: KeyframeBoundary::Clamp,
+            }]
+            .into(),
+            morph_animations: vec![],
+        };
+
+        let animations = vec![animation("idle"), animation("walk"), animation("attack")];
+        Animator::from_parts(root_node, Mat4::IDENTITY, HashMap::new().into(), animations)
+    }
+
+    fn assert_weights(state_machine: &AnimationStateMachine, animator: &Animator, expected: &[(&str, f32)]) {
+        let weights = state_machine.state_weights(animator);
+        assert_eq!(weights.len(), expected.len(), "weights: {:?}", weights);
+        for ((name, weight), (expected_name, expected_weight)) in weights.iter().zip(expected) {
+            assert_eq!(name, expected_name);
+            assert!((weight - expected_weight).abs() < 0.0001, "weights: {:?}", weights);
+        }
+    }
+
+    #[test]
+    fn test_step_state_machine() {
+        let mut animator = test_animator();
+        let mut state_machine = AnimationStateMachine::from_ron(DEFINITION).unwrap();
+
+        state_machine.update(&mut animator, 0.1);
+        assert_eq!(state_machine.current_state(), Some("idle"));
+        assert_weights(&state_machine, &animator, &[("idle", 1.0)]);
+
+        // crossfade to walk over 0.4 seconds
+        state_machine.set_float("speed", 1.0);
+        state_machine.update(&mut animator, 0.1);
+        assert_eq!(state_machine.current_state(), Some("walk"));
+        assert_weights(&state_machine, &animator, &[("walk", 0.25), ("idle", 0.75)]);
+
+        state_machine.update(&mut animator, 0.2);
+        assert_weights(&state_machine, &animator, &[("walk", 0.75), ("idle", 0.25)]);
+
+        state_machine.update(&mut animator, 0.2);
+        assert_weights(&state_machine, &animator, &[("walk", 1.0)]);
+
+        // the trigger cuts straight to attack from any state and is consumed
+        state_machine.set_trigger("attack");
+        state_machine.update(&mut animator, 0.5);
+        assert_eq!(state_machine.current_state(), Some("attack"));
+        assert_weights(&state_machine, &animator, &[("attack", 1.0)]);
+
+        // attack can only exit once it has finished
+        state_machine.update(&mut animator, 0.4);
+        assert_eq!(state_machine.current_state(), Some("attack"));
+        state_machine.update(&mut animator, 0.2);
+        assert!(animator.is_finished());
+        state_machine.update(&mut animator, 0.1);
+        assert_eq!(state_machine.current_state(), Some("idle"));
+        assert_weights(&state_machine, &animator, &[("idle", 0.5), ("attack", 0.5)]);
+    }
+
+    #[test]
+    fn test_exit_time_of_reversed_clip() {
+        let definition = DEFINITION
+            .replace("repeat: Once))", "repeat: Forever, speed: -1.0))")
+            .replace("exit_time: Some(1.0)", "exit_time: Some(0.5)");
+        let mut animator = test_animator();
+        let mut state_machine = AnimationStateMachine::from_ron(&definition).unwrap();
+
+        state_machine.update(&mut animator, 0.1);
+        state_machine.set_trigger("attack");
+        state_machine.update(&mut animator, 0.1);
+        assert_eq!(state_machine.current_state(), Some("attack"));
+        assert!(animator.current_animation.is_reversed());
+
+        // attack plays from tick 10 down, so it is only halfway through once it passes tick 5
+        state_machine.update(&mut animator, 0.3);
+        assert_eq!(state_machine.current_state(), Some("attack"));
+        state_machine.update(&mut animator, 0.3);
+        assert_eq!(state_machine.current_state(), Some("attack"));
+        assert!((animator.current_animation.current_tick - 3.0).abs() < 0.0001);
+        state_machine.update(&mut animator, 0.1);
+        assert_eq!(state_machine.current_state(), Some("idle"));
+    }
+
+    #[test]
+    fn test_state_with_unknown_animation_is_not_entered() {
+        let definition = DEFINITION.replace(r#"animation_name: Some("walk")"#, r#"animation_name: Some("wlak")"#);
+        let mut animator = test_animator();
+        let mut state_machine = AnimationStateMachine::from_ron(&definition).unwrap();
+        assert!(state_machine.check_clips(&animator).is_err());
+        assert!(AnimationStateMachine::from_ron(DEFINITION).unwrap().check_clips(&animator).is_ok());
+
+        state_machine.update(&mut animator, 0.1);
+        state_machine.set_float("speed", 1.0);
+        state_machine.update(&mut animator, 0.1);
+        assert_eq!(state_machine.current_state(), Some("idle"));
+        assert_weights(&state_machine, &animator, &[("idle", 1.0)]);
+    }
+
+    #[test]
+    fn test_unknown_state_is_an_error() {
+        let definition = DEFINITION.replace(r#"to: "walk""#, r#"to: "run""#);
+        assert!(AnimationStateMachine::from_ron(&definition).is_err());
+    }
+}
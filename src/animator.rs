@@ -1,42 +1,99 @@
+use crate::animation_layer::{add_transform_weighted, AnimationLayer, BoneMask, LayerBlendMode};
+use crate::dual_quat::DualQuat;
+use crate::error::Error;
+use crate::error::Error::AnimationError;
 use crate::hash_map::HashMap;
+use crate::ik::IkConstraint;
+use crate::inertialization::{Inertialization, PoseHistory};
 use crate::model_animation::{BoneData, BoneName, ModelAnimation, NodeData};
-use crate::node_animation::NodeAnimation;
+use crate::morph::MorphAnimation;
+use crate::pose::{write_skinning_matrices, Pose};
+use crate::root_motion::{blend_motion, relative_motion, repeat_motion, RootMotion};
+use crate::skeleton::Skeleton;
+use crate::spring_bone::SpringBoneChain;
 use crate::transform::Transform;
-use crate::utils::min;
-use glam::Mat4;
+use glam::{Mat4, Vec3};
+use log::debug;
 use russimp::node::Node;
 use russimp::scene::Scene;
-use std::cell::{Ref, RefCell, RefMut};
-use std::ops::Deref;
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 use std::time::Duration;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum AnimationRepeat {
     Once,
     Count(u32),
     Forever,
+    /// Plays forwards and backwards between the start and end ticks forever
+    PingPong,
 }
 
-#[derive(Debug, Clone)]
+/// A named point on a clip's timeline, such as a footstep or weapon hit frame.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnimationMarker {
+    pub name: String,
+    pub tick: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AnimationClip {
+    /// Name of the model animation the ticks refer to. None plays the model's first animation.
+    #[serde(default)]
+    pub animation_name: Option<String>,
     pub start_tick: f32,
     pub end_tick: f32,
     pub repeat: AnimationRepeat,
+    #[serde(default)]
+    pub markers: Vec<AnimationMarker>,
+    /// Multiplies the animation's ticks per second. Negative speeds play the clip in reverse.
+    #[serde(default = "default_speed")]
+    pub speed: f32,
+}
+
+fn default_speed() -> f32 {
+    1.0
 }
 
 impl AnimationClip {
     pub fn new(start_tick: f32, end_tick: f32, repeat: AnimationRepeat) -> Self {
         AnimationClip {
+            animation_name: None,
             start_tick,
             end_tick,
             repeat,
+            markers: vec![],
+            speed: 1.0,
         }
     }
+
+    pub fn from_animation(animation_name: impl Into<String>, start_tick: f32, end_tick: f32, repeat: AnimationRepeat) -> Self {
+        AnimationClip {
+            animation_name: Some(animation_name.into()),
+            start_tick,
+            end_tick,
+            repeat,
+            markers: vec![],
+            speed: 1.0,
+        }
+    }
+
+    pub fn with_marker(mut self, name: impl Into<String>, tick: f32) -> Self {
+        self.markers.push(AnimationMarker { name: name.into(), tick });
+        self
+    }
+
+    pub fn with_speed(mut self, speed: f32) -> Self {
+        self.speed = speed;
+        self
+    }
 }
 
 #[derive(Debug)]
 pub struct WeightedAnimation {
+    /// Name of the model animation to sample. None samples the model's first animation.
+    pub animation_name: Option<String>,
     pub weight: f32,
     pub start_tick: f32,
     pub end_tick: f32,
@@ -47,6 +104,7 @@ pub struct WeightedAnimation {
 impl WeightedAnimation {
     pub fn new(weight: f32, start_tick: f32, end_tick: f32, offset: f32, optional_start: f32) -> Self {
         WeightedAnimation {
+            animation_name: None,
             weight,
             start_tick,
             end_tick,
@@ -54,37 +112,305 @@ impl WeightedAnimation {
             optional_start, // used for non-looped animations
         }
     }
+
+    pub fn with_animation(mut self, animation_name: impl Into<String>) -> Self {
+        self.animation_name = Some(animation_name.into());
+        self
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct PlayingAnimation {
     pub animation_clip: Rc<AnimationClip>,
+    /// Index into the animator's model_animations
+    pub animation_index: usize,
     pub current_tick: f32,
     pub ticks_per_second: f32,
     pub repeat_completions: u32,
+    /// Multiplies the clip's speed for this playback only
+    pub speed: f32,
+    /// -1.0 while a PingPong clip is playing back towards where it started
+    pub direction: f32,
+    /// Tick ranges the last update moved through, in order, with the number of times each was moved through,
+    /// for measuring how far it moved
+    pub traversed: Vec<(f32, f32, u32)>,
+}
+
+/// Playback events, queued by the animator until drained.
+#[derive(Debug, Clone)]
+pub enum AnimationEvent {
+    /// The clip wrapped around to its start, or a PingPong clip turned around. `completions` is the number of times it has been played through.
+    /// Reported once per update however many times it looped.
+    Looped { clip: Rc<AnimationClip>, completions: u32 },
+    /// A Once or Count clip played to its end and is holding its last frame.
+    Finished { clip: Rc<AnimationClip> },
+    /// The clip's playback passed the marker this frame.
+    Marker { clip: Rc<AnimationClip>, marker: AnimationMarker },
 }
 
+/// Events kept for drain_events. Older events are dropped once the queue is full, so a model whose events
+/// are never drained doesn't grow without bound.
+pub const MAX_QUEUED_EVENTS: usize = 256;
+
 impl PlayingAnimation {
-    pub fn update(&mut self, delta_time: f32) {
+    pub fn new(animation_clip: Rc<AnimationClip>, animation_index: usize, ticks_per_second: f32) -> Self {
+        PlayingAnimation {
+            animation_clip,
+            animation_index,
+            current_tick: -1.0,
+            ticks_per_second,
+            repeat_completions: 0,
+            speed: 1.0,
+            direction: 1.0,
+            traversed: vec![],
+        }
+    }
+
+    /// Ticks per second including the clip and playback speeds and the ping pong direction.
+    pub fn tick_rate(&self) -> f32 {
+        self.ticks_per_second * self.animation_clip.speed * self.speed * self.direction
+    }
+
+    /// True when playback is moving from the end tick towards the start tick.
+    pub fn is_reversed(&self) -> bool {
+        self.tick_rate() < 0.0
+    }
+
+    pub fn update(&mut self, delta_time: f32, events: &mut Vec<AnimationEvent>) {
+        let start_tick = self.animation_clip.start_tick;
+        let end_tick = self.animation_clip.end_tick;
+
         if self.current_tick < 0.0 {
-            self.current_tick = self.animation_clip.start_tick;
+            self.current_tick = if self.is_reversed() { end_tick } else { start_tick };
         }
 
-        self.current_tick += self.ticks_per_second * delta_time;
+        self.traversed.clear();
+
+        if self.is_finished() || end_tick <= start_tick {
+            return;
+        }
+
+        let ping_pong = matches!(self.animation_clip.repeat, AnimationRepeat::PingPong);
+        let tick_range = end_tick - start_tick;
+
+        // the tick is advanced rather than recalculated from the elapsed time, so speed changes don't jump
+        let mut tick = self.current_tick.clamp(start_tick, end_tick);
+        let movement = self.tick_rate() * delta_time;
+        if movement == 0.0 || !movement.is_finite() {
+            return;
+        }
+        let mut forward = movement > 0.0;
+        // markers at the tick playback starts from were reported by the previous update, unless it just turned around
+        let mut include_from_tick = true;
+
+        // a tick left on the boundary it now moves towards, after the speed changed sign, was counted when it got
+        // there, so it wraps or turns around without another completion
+        if tick == if forward { end_tick } else { start_tick } {
+            if ping_pong {
+                self.direction = -self.direction;
+                forward = !forward;
+                include_from_tick = false;
+            } else {
+                tick = if forward { start_tick } else { end_tick };
+            }
+        }
+
+        let distance = movement.abs();
+        let boundary = if forward { end_tick } else { start_tick };
+        let other_boundary = if forward { start_tick } else { end_tick };
+        let to_boundary = (boundary - tick).abs();
+
+        if distance < to_boundary {
+            let to_tick = if forward { tick + distance } else { tick - distance };
+            self.traverse(tick, to_tick, include_from_tick, false, 1, events);
+            self.current_tick = to_tick;
+            return;
+        }
 
-        if self.current_tick > self.animation_clip.end_tick {
-            match self.animation_clip.repeat {
-                AnimationRepeat::Once => {
-                    self.current_tick = self.animation_clip.end_tick;
+        self.traverse(tick, boundary, include_from_tick, true, 1, events);
+
+        // the boundaries crossed are counted rather than stepped through, so a long frame or a short clip stays cheap
+        let beyond = distance - to_boundary;
+        let rest = beyond.rem_euclid(tick_range);
+        let whole_passes = ((beyond - rest) / tick_range).round() as u32;
+        let crossings = whole_passes.saturating_add(1);
+
+        if let Some(plays_left) = self.plays_left() {
+            if crossings >= plays_left {
+                if plays_left > 1 {
+                    self.traverse(other_boundary, boundary, true, true, plays_left - 1, events);
                 }
-                AnimationRepeat::Count(_) => {}
-                AnimationRepeat::Forever => {
-                    self.current_tick = self.animation_clip.start_tick;
+                self.repeat_completions += plays_left;
+                self.current_tick = boundary;
+
+                if plays_left > 1 {
+                    events.push(AnimationEvent::Looped {
+                        clip: self.animation_clip.clone(),
+                        completions: self.repeat_completions - 1,
+                    });
                 }
+                events.push(AnimationEvent::Finished {
+                    clip: self.animation_clip.clone(),
+                });
+                return;
+            }
+        }
+
+        self.repeat_completions = self.repeat_completions.saturating_add(crossings);
+
+        let from_tick = if ping_pong {
+            // each crossing turns playback around, and pairs of whole passes there and back cancel out
+            if crossings % 2 == 1 {
+                self.direction = -self.direction;
+                forward = !forward;
+            }
+            include_from_tick = false;
+            if whole_passes % 2 == 1 {
+                self.traverse(boundary, other_boundary, false, true, 1, events);
+                other_boundary
+            } else {
+                boundary
+            }
+        } else {
+            if whole_passes > 0 {
+                self.traverse(other_boundary, boundary, true, true, whole_passes, events);
+            }
+            include_from_tick = true;
+            other_boundary
+        };
+
+        let to_tick = if forward { from_tick + rest } else { from_tick - rest };
+        if rest > 0.0 {
+            self.traverse(from_tick, to_tick, include_from_tick, false, 1, events);
+        }
+        self.current_tick = to_tick;
+
+        events.push(AnimationEvent::Looped {
+            clip: self.animation_clip.clone(),
+            completions: self.repeat_completions,
+        });
+    }
+
+    /// Records passes moves from from_tick to to_tick and reports the markers passed, once however many passes.
+    /// Either end is included when asked, so a marker is reported once however the updates divide up the clip.
+    fn traverse(
+        &mut self,
+        from_tick: f32,
+        to_tick: f32,
+        include_from_tick: bool,
+        include_to_tick: bool,
+        passes: u32,
+        events: &mut Vec<AnimationEvent>,
+    ) {
+        if from_tick != to_tick {
+            self.traversed.push((from_tick, to_tick, passes));
+        }
+
+        let (low, high) = if from_tick <= to_tick {
+            (from_tick, to_tick)
+        } else {
+            (to_tick, from_tick)
+        };
+        for marker in self.animation_clip.markers.iter() {
+            let passed = (marker.tick > low && marker.tick < high)
+                || (include_from_tick && marker.tick == from_tick)
+                || (include_to_tick && marker.tick == to_tick);
+
+            if passed {
+                events.push(AnimationEvent::Marker {
+                    clip: self.animation_clip.clone(),
+                    marker: marker.clone(),
+                });
             }
-            // in ticks
         }
     }
+
+    fn play_count(&self) -> u32 {
+        match self.animation_clip.repeat {
+            AnimationRepeat::Once => 1,
+            AnimationRepeat::Count(count) => count.max(1),
+            AnimationRepeat::Forever | AnimationRepeat::PingPong => u32::MAX,
+        }
+    }
+
+    /// Plays until a Once or Count clip finishes, None for Forever and PingPong clips.
+    fn plays_left(&self) -> Option<u32> {
+        match self.animation_clip.repeat {
+            AnimationRepeat::Once | AnimationRepeat::Count(_) => Some(self.play_count().saturating_sub(self.repeat_completions)),
+            AnimationRepeat::Forever | AnimationRepeat::PingPong => None,
+        }
+    }
+
+    /// True once a Once or Count clip has played to its end. Forever and PingPong clips never finish.
+    pub fn is_finished(&self) -> bool {
+        self.plays_left() == Some(0)
+    }
+
+    /// Position in the current play through, from 0.0 at the start tick to 1.0 at the end tick.
+    pub fn normalized_time(&self) -> f32 {
+        let tick_range = self.animation_clip.end_tick - self.animation_clip.start_tick;
+        if self.current_tick < 0.0 || tick_range <= 0.0 {
+            return 0.0;
+        }
+        ((self.current_tick - self.animation_clip.start_tick) / tick_range).clamp(0.0, 1.0)
+    }
+
+    /// How far through the current play through playback has got, from 0.0 where it started to 1.0 where it
+    /// will finish or turn around. The same as normalized_time unless the clip is playing in reverse.
+    pub fn played_time(&self) -> f32 {
+        if self.current_tick < 0.0 || !self.is_reversed() {
+            self.normalized_time()
+        } else {
+            1.0 - self.normalized_time()
+        }
+    }
+
+    /// Seconds until the clip finishes at its current speed. Forever and PingPong clips never finish,
+    /// so this is the time left until they loop or turn around.
+    pub fn remaining_time(&self) -> f32 {
+        let tick_rate = self.tick_rate().abs();
+        if tick_rate <= 0.0 || self.is_finished() {
+            return 0.0;
+        }
+
+        let tick_range = self.animation_clip.end_tick - self.animation_clip.start_tick;
+        let remaining_plays = match self.animation_clip.repeat {
+            AnimationRepeat::Once | AnimationRepeat::Forever | AnimationRepeat::PingPong => 0,
+            AnimationRepeat::Count(count) => count.max(1).saturating_sub(self.repeat_completions + 1),
+        };
+
+        let remaining_in_play = (1.0 - self.played_time()) * tick_range;
+
+        (remaining_in_play + remaining_plays as f32 * tick_range) / tick_rate
+    }
+}
+
+/// How play_clip_with_transition blends from the current animation to the new one.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum TransitionMode {
+    /// Keeps sampling the animations being faded out, lowering their weights linearly
+    #[default]
+    Crossfade,
+    /// Samples only the new animation, decaying the difference from the pose at the switch
+    Inertialization,
+}
+
+/// How the bone transforms are prepared for the vertex shader.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum SkinningMethod {
+    /// Blends bone matrices, final_bone_matrices
+    #[default]
+    Linear,
+    /// Blends dual quaternions, final_bone_dual_quats, so twisting joints keep their volume.
+    /// The bone matrices are still updated.
+    DualQuaternion,
+}
+
+/// An animation blended with others by weight in place of the current animation, such as a blend space sample.
+#[derive(Debug, Clone)]
+pub struct BlendedAnimation {
+    pub animation: PlayingAnimation,
+    pub weight: f32,
 }
 
 /// An animation that is being faded out as part of a transition (from Bevy)
@@ -98,36 +424,61 @@ pub struct AnimationTransition {
     pub animation: PlayingAnimation,
 }
 
-#[derive(Debug, Clone)]
-pub struct NodeTransform {
-    pub transform: Transform,
-    pub meshes: Rc<Vec<u32>>,
-}
-
-impl NodeTransform {
-    pub fn new(transform: Transform, meshes_vec: &Rc<Vec<u32>>) -> Self {
-        NodeTransform {
-            transform,
-            meshes: meshes_vec.clone(),
-        }
-    }
-}
-
 #[derive(Debug, Clone)]
 pub struct Animator {
     pub root_node: NodeData,
     pub global_inverse_transform: Mat4,
     pub bone_data_map: RefCell<HashMap<BoneName, BoneData>>,
+    pub skeleton: Skeleton,
 
-    pub model_animation: ModelAnimation, // maybe should be vec?
+    pub model_animations: Vec<ModelAnimation>,
+    /// For each model animation, the channel that animates each skeleton node
+    channel_bindings: Vec<Vec<Option<usize>>>,
+    /// For each model animation, the node whose meshes each morph channel animates and the channel's target count
+    morph_bindings: Vec<Vec<Option<(usize, usize)>>>,
 
     pub current_animation: PlayingAnimation,
     pub transitions: RefCell<Vec<AnimationTransition>>,
+    pub transition_mode: TransitionMode,
+    /// When not empty, poses the model by weight in place of the current animation and transitions
+    pub blended_animations: Vec<BlendedAnimation>,
+    /// The offset being decayed after an inertialized switch
+    inertialization: Option<Inertialization>,
+    /// Recorded by update_animation in any transition mode, for the offset and velocity at a switch
+    pose_history: PoseHistory,
+    /// Set when play_weight_animations or apply_pose replaces the pose, leaving pose_history behind it
+    pose_replaced: Cell<bool>,
+    /// Holds at most MAX_QUEUED_EVENTS, the newest
+    pub events: RefCell<Vec<AnimationEvent>>,
+    /// Applied in order on top of the current animation and transitions
+    pub layers: Vec<AnimationLayer>,
+    /// When set, the motion of a node is removed from the pose and returned by update_animation
+    pub root_motion: Option<RootMotion>,
+    /// Solved in order on the model space pose before the final transforms are calculated
+    pub ik_constraints: Vec<IkConstraint>,
+    /// Simulated in order after IK by update_animation
+    pub spring_bones: Vec<SpringBoneChain>,
 
-    pub node_transforms: RefCell<HashMap<Rc<str>, NodeTransform>>,
+    /// Blended transform of each skeleton node relative to its parent
+    pub local_pose: RefCell<Pose>,
+    /// Transform of each skeleton node in model space
+    pub node_transforms: RefCell<Vec<Transform>>,
+    /// Scratch space for sampling the animations being blended and layers
+    scratch_poses: RefCell<Vec<Pose>>,
+    /// Scratch space for the clip weights of the current animation and transitions
+    scratch_weights: RefCell<Vec<f32>>,
 
     pub final_bone_matrices: RefCell<Vec<Mat4>>,
     pub final_node_matrices: RefCell<Vec<Mat4>>,
+    /// Updated alongside final_bone_matrices when using dual quaternion skinning
+    pub final_bone_dual_quats: RefCell<Vec<DualQuat>>,
+    pub skinning_method: SkinningMethod,
+
+    /// Morph target weights of each mesh from the last update, indexed like final_node_matrices. Empty for
+    /// meshes that no animation or manual weight drives, which keep their targets' default weights.
+    pub morph_weights: RefCell<Vec<Vec<f32>>>,
+    /// Weights set by set_morph_weight, by mesh and target index, which replace the animated weights
+    morph_weight_overrides: HashMap<(u32, usize), f32>,
 }
 
 impl Animator {
@@ -136,105 +487,290 @@ impl Animator {
         let global_inverse_transform = root.transformation.inverse();
         let root_node = read_hierarchy_data(&root);
 
-        let model_animation = ModelAnimation::new(scene);
+        let model_animations = ModelAnimation::from_scene(scene);
 
-        let mut final_bone_matrices = Vec::with_capacity(100);
-        let mut final_node_matrices = Vec::with_capacity(50);
+        let animator = Animator::from_parts(root_node, global_inverse_transform, bone_data_map, model_animations);
 
-        for i in 0..100 {
-            final_bone_matrices.push(Mat4::IDENTITY);
-            if i < 50 {
-                final_node_matrices.push(Mat4::IDENTITY);
-            }
+        // meshes not referenced by any node still get a node matrix
+        let mut final_node_matrices = animator.final_node_matrices.borrow_mut();
+        if final_node_matrices.len() < scene.meshes.len() {
+            final_node_matrices.resize(scene.meshes.len(), Mat4::IDENTITY);
+            animator.morph_weights.borrow_mut().resize(scene.meshes.len(), vec![]);
         }
+        drop(final_node_matrices);
 
-        let animation_clip = AnimationClip {
-            start_tick: 0.0,
-            end_tick: model_animation.duration,
-            repeat: AnimationRepeat::Forever,
-        };
+        animator
+    }
 
-        let current_animation = PlayingAnimation {
-            animation_clip: Rc::new(animation_clip),
-            current_tick: -1.0,
-            ticks_per_second: model_animation.ticks_per_second,
-            repeat_completions: 0,
-        };
+    /// Builds an animator from already converted node, bone and animation data.
+    pub fn from_parts(
+        root_node: NodeData,
+        global_inverse_transform: Mat4,
+        bone_data_map: RefCell<HashMap<BoneName, BoneData>>,
+        model_animations: Vec<ModelAnimation>,
+    ) -> Self {
+        let (duration, ticks_per_second) = model_animations
+            .first()
+            .map_or((0.0, 0.0), |animation| (animation.duration, animation.ticks_per_second));
+
+        let animation_clip = AnimationClip::new(0.0, duration, AnimationRepeat::Forever);
+
+        let current_animation = PlayingAnimation::new(Rc::new(animation_clip), 0, ticks_per_second);
+
+        let skeleton = Skeleton::new(&root_node, &bone_data_map.borrow());
+
+        // sized for the highest bone id and mesh index in use
+        let bone_count = bone_data_map
+            .borrow()
+            .values()
+            .map(|bone_data| bone_data.bone_index + 1)
+            .max()
+            .unwrap_or(0)
+            .max(0) as usize;
+        let mesh_count = skeleton
+            .nodes
+            .iter()
+            .flat_map(|node| node.meshes.iter())
+            .map(|mesh_index| *mesh_index as usize + 1)
+            .max()
+            .unwrap_or(0);
+
+        let channel_bindings = model_animations
+            .iter()
+            .map(|model_animation| skeleton.bind_channels(&model_animation.node_animations.borrow()))
+            .collect();
+        let morph_bindings = model_animations
+            .iter()
+            .map(|model_animation| bind_morph_channels(&skeleton, &model_animation.morph_animations))
+            .collect();
+
+        let rest_pose = Pose::rest(&skeleton);
 
         Animator {
             root_node,
             global_inverse_transform,
             bone_data_map,
-            model_animation,
+            skeleton,
+            model_animations,
+            channel_bindings,
+            morph_bindings,
             current_animation,
             transitions: vec![].into(),
-            node_transforms: HashMap::new().into(),
-            final_bone_matrices: final_bone_matrices.into(),
-            final_node_matrices: final_node_matrices.into(),
+            transition_mode: TransitionMode::Crossfade,
+            blended_animations: vec![],
+            inertialization: None,
+            pose_history: PoseHistory::new(&rest_pose),
+            pose_replaced: Cell::new(false),
+            events: vec![].into(),
+            layers: vec![],
+            root_motion: None,
+            ik_constraints: vec![],
+            spring_bones: vec![],
+            node_transforms: rest_pose.transforms.clone().into(),
+            local_pose: rest_pose.clone().into(),
+            scratch_poses: vec![rest_pose].into(),
+            scratch_weights: vec![].into(),
+            final_bone_matrices: vec![Mat4::IDENTITY; bone_count].into(),
+            final_node_matrices: vec![Mat4::IDENTITY; mesh_count].into(),
+            final_bone_dual_quats: vec![DualQuat::IDENTITY; bone_count].into(),
+            skinning_method: SkinningMethod::Linear,
+            morph_weights: vec![vec![]; mesh_count].into(),
+            morph_weight_overrides: HashMap::new(),
         }
     }
 
-    pub fn play_clip(&mut self, clip: &Rc<AnimationClip>) {
-        self.current_animation = PlayingAnimation {
-            animation_clip: clip.clone(),
-            current_tick: -1.0,
-            ticks_per_second: self.model_animation.ticks_per_second,
-            repeat_completions: 0,
+    /// Names of all the model's animations, in the order they were loaded.
+    pub fn animation_names(&self) -> Vec<Rc<str>> {
+        self.model_animations.iter().map(|animation| animation.name.clone()).collect()
+    }
+
+    pub fn get_animation(&self, animation_name: &str) -> Option<&ModelAnimation> {
+        self.model_animations
+            .iter()
+            .find(|animation| animation.name.as_ref() == animation_name)
+    }
+
+    /// Returns a clip covering the whole of the named animation.
+    pub fn animation_clip(&self, animation_name: &str, repeat: AnimationRepeat) -> Option<Rc<AnimationClip>> {
+        self.get_animation(animation_name)
+            .map(|animation| Rc::new(AnimationClip::from_animation(animation_name, 0.0, animation.duration, repeat)))
+    }
+
+    /// Adds an animation whose channels target this animator's node hierarchy. Fails, listing the
+    /// channel names, if any channel doesn't match a node.
+    pub fn add_animation(&mut self, model_animation: ModelAnimation) -> Result<(), Error> {
+        self.check_animation(&model_animation)?;
+
+        debug!("added animation: {}", model_animation.name);
+        let channel_binding = self.skeleton.bind_channels(&model_animation.node_animations.borrow());
+        self.channel_bindings.push(channel_binding);
+        self.morph_bindings
+            .push(bind_morph_channels(&self.skeleton, &model_animation.morph_animations));
+        self.model_animations.push(model_animation);
+        Ok(())
+    }
+
+    /// Adds all of the animations, or none of them if any would fail add_animation or they share a name.
+    pub fn add_animations(&mut self, model_animations: Vec<ModelAnimation>) -> Result<(), Error> {
+        for (index, model_animation) in model_animations.iter().enumerate() {
+            self.check_animation(model_animation)?;
+            if model_animations[..index].iter().any(|other| other.name == model_animation.name) {
+                return Err(AnimationError(format!("animation already exists: {}", model_animation.name)));
+            }
+        }
+
+        for model_animation in model_animations {
+            self.add_animation(model_animation)?;
         }
+        Ok(())
     }
 
-    pub fn play_weight_animations(&mut self, weighted_animation: &[WeightedAnimation], frame_time: f32) {
-        {
-            let mut node_map = self.node_transforms.borrow_mut();
-            let node_animations = self.model_animation.node_animations.borrow();
+    fn check_animation(&self, model_animation: &ModelAnimation) -> Result<(), Error> {
+        if self.get_animation(&model_animation.name).is_some() {
+            return Err(AnimationError(format!("animation already exists: {}", model_animation.name)));
+        }
 
-            // reset node transforms
-            node_map.clear();
+        let unmatched: Vec<Rc<str>> = model_animation
+            .node_animations
+            .borrow()
+            .iter()
+            .filter(|node_animation| self.skeleton.find_node(&node_animation.name).is_none())
+            .map(|node_animation| node_animation.name.clone())
+            .collect();
 
-            let inverse_transform = Transform::from_matrix(self.global_inverse_transform);
+        if !unmatched.is_empty() {
+            return Err(AnimationError(format!(
+                "animation: {} has channels for bones not in the model: {:?}",
+                model_animation.name, unmatched
+            )));
+        }
+        Ok(())
+    }
 
-            for weighted in weighted_animation {
-                if weighted.weight == 0.0 {
-                    continue;
+    /// Fails if the clip's animation isn't in the model, leaving the current animation playing.
+    pub fn play_clip(&mut self, clip: &Rc<AnimationClip>) -> Result<(), Error> {
+        self.current_animation = self.clip_animation(clip)?;
+        self.inertialization = None;
+        self.blended_animations.clear();
+        Ok(())
+    }
+
+    /// Sets the speed of the current animation on top of its clip's speed. Animations being faded out keep theirs.
+    pub fn set_speed(&mut self, speed: f32) {
+        self.current_animation.speed = speed;
+    }
+
+    /// Index of the named model animation, or the first animation for None.
+    fn find_animation_index(&self, animation_name: &Option<String>) -> Option<usize> {
+        match animation_name {
+            None => Some(0),
+            Some(name) => {
+                let index = self.model_animations.iter().position(|animation| animation.name.as_ref() == name);
+                if index.is_none() {
+                    debug!("animation not found: {}", name);
                 }
+                index
+            }
+        }
+    }
 
-                let tick_range = weighted.end_tick - weighted.start_tick;
+    /// A playback of the clip from its start, or None if its animation isn't found.
+    pub fn new_playing_animation(&self, clip: &Rc<AnimationClip>) -> Option<PlayingAnimation> {
+        let animation_index = self.find_animation_index(&clip.animation_name)?;
 
-                let mut target_anim_ticks = if weighted.optional_start > 0.0 {
-                    let tick = (frame_time - weighted.optional_start) * self.model_animation.ticks_per_second + weighted.offset;
-                    min(tick, tick_range)
-                } else {
-                    (frame_time * self.model_animation.ticks_per_second + weighted.offset) % tick_range
-                };
+        let ticks_per_second = self
+            .model_animations
+            .get(animation_index)
+            .map_or(0.0, |animation| animation.ticks_per_second);
 
-                target_anim_ticks += weighted.start_tick;
+        Some(PlayingAnimation::new(clip.clone(), animation_index, ticks_per_second))
+    }
 
-                if target_anim_ticks < (weighted.start_tick - 0.01) || target_anim_ticks > (weighted.end_tick + 0.01) {
-                    panic!("target_anim_ticks out of range: {}", target_anim_ticks);
-                }
+    fn clip_animation(&self, clip: &Rc<AnimationClip>) -> Result<PlayingAnimation, Error> {
+        self.new_playing_animation(clip).ok_or_else(|| {
+            AnimationError(format!(
+                "animation not found: {}",
+                clip.animation_name.as_deref().unwrap_or_default()
+            ))
+        })
+    }
+
+    /// Poses the model with the weighted animations at the frame time. The weights are normalized, so
+    /// only their ratios matter, and the order of the animations doesn't change the result.
+    pub fn play_weight_animations(&mut self, weighted_animation: &[WeightedAnimation], frame_time: f32) {
+        let mut scratch_poses = self.scratch_poses.borrow_mut();
+        let mut weights = self.scratch_weights.borrow_mut();
+        weights.clear();
 
-                calculate_transform_maps(
-                    &self.root_node,
-                    &node_animations,
-                    &mut node_map,
-                    inverse_transform,
-                    target_anim_ticks,
-                    weighted.weight,
-                );
+        for (animation_index, tick, weight) in weighted_animation
+            .iter()
+            .filter_map(|weighted| self.weighted_sample(weighted, frame_time))
+        {
+            if scratch_poses.len() <= weights.len() {
+                scratch_poses.push(Pose::rest(&self.skeleton));
             }
+            self.sample_animation(&mut scratch_poses[weights.len()], animation_index, tick);
+            weights.push(weight);
         }
 
+        self.local_pose.borrow_mut().set_blend_poses(&scratch_poses, &weights);
+        self.pose_replaced.set(true);
+        let total_weight: f32 = weights.iter().sum();
+        drop(scratch_poses);
+        drop(weights);
+
+        self.update_morph_weights(
+            weighted_animation
+                .iter()
+                .filter_map(|weighted| self.weighted_sample(weighted, frame_time))
+                .map(|(animation_index, tick, weight)| (animation_index, tick, weight / total_weight)),
+        );
+
+        self.update_node_transforms();
+        self.update_ik();
         self.update_final_transforms();
     }
 
-    pub fn play_clip_with_transition(&mut self, clip: &Rc<AnimationClip>, transition_duration: Duration) {
-        let mut animation = PlayingAnimation {
-            animation_clip: clip.clone(),
-            current_tick: -1.0,
-            ticks_per_second: self.model_animation.ticks_per_second,
-            repeat_completions: 0,
+    /// The animation index, tick and weight a weighted animation samples at the frame time, or None when it has no
+    /// weight or animation. Ticks outside the weighted animation's range are clamped to it.
+    fn weighted_sample(&self, weighted: &WeightedAnimation, frame_time: f32) -> Option<(usize, f32, f32)> {
+        if weighted.weight <= 0.0 {
+            return None;
+        }
+
+        let animation_index = self
+            .find_animation_index(&weighted.animation_name)
+            .filter(|animation_index| *animation_index < self.model_animations.len())?;
+        let ticks_per_second = self.model_animations[animation_index].ticks_per_second;
+
+        let tick_range = weighted.end_tick - weighted.start_tick;
+
+        let target_anim_ticks = if weighted.optional_start > 0.0 {
+            (frame_time - weighted.optional_start) * ticks_per_second + weighted.offset
+        } else if tick_range > 0.0 {
+            (frame_time * ticks_per_second + weighted.offset).rem_euclid(tick_range)
+        } else {
+            0.0
         };
 
+        let tick = (weighted.start_tick + target_anim_ticks).clamp(weighted.start_tick, weighted.end_tick.max(weighted.start_tick));
+        Some((animation_index, tick, weighted.weight))
+    }
+
+    /// Fails like play_clip.
+    pub fn play_clip_with_transition(&mut self, clip: &Rc<AnimationClip>, transition_duration: Duration) -> Result<(), Error> {
+        let mut animation = self.clip_animation(clip)?;
+
+        if self.transition_mode == TransitionMode::Inertialization {
+            self.inertialize(animation, transition_duration);
+            return Ok(());
+        }
+
+        // a blend being left is faded out from its most heavily weighted animation
+        if let Some(heaviest) = self.blended_animations.drain(..).max_by(|a, b| a.weight.total_cmp(&b.weight)) {
+            self.current_animation = heaviest.animation;
+        }
+
         std::mem::swap(&mut animation, &mut self.current_animation);
 
         let transition = AnimationTransition {
@@ -244,72 +780,540 @@ impl Animator {
         };
 
         self.transitions.borrow_mut().push(transition);
+        Ok(())
+    }
+
+    /// Switches to the animation, recording the difference from the last pose for update_inertialization to decay.
+    fn inertialize(&mut self, animation: PlayingAnimation, transition_duration: Duration) {
+        self.restart_stale_pose_history();
+        let delta_time = self.pose_history.delta_time;
+        let duration = transition_duration.as_secs_f32();
+
+        if duration > 0.0 {
+            let clip = animation.animation_clip.clone();
+            let tick = if animation.is_reversed() { clip.end_tick } else { clip.start_tick };
+            let next_tick = (tick + animation.tick_rate() * delta_time).clamp(clip.start_tick, clip.end_tick);
+
+            let mut target = Pose::rest(&self.skeleton);
+            let mut target_next = Pose::rest(&self.skeleton);
+            self.sample_animation(&mut target, animation.animation_index, tick);
+            self.sample_animation(&mut target_next, animation.animation_index, next_tick);
+
+            self.inertialization = Some(Inertialization::new(
+                &self.pose_history.last,
+                &self.pose_history.previous,
+                &target,
+                &target_next,
+                delta_time,
+                duration,
+            ));
+        }
+
+        self.transitions.borrow_mut().clear();
+        self.blended_animations.clear();
+        self.current_animation = animation;
     }
 
-    pub fn update_animation(&mut self, delta_time: f32) {
-        self.current_animation.update(delta_time);
-        self.update_transitions(delta_time);
-        self.update_node_map(delta_time);
+    /// Advances the animations and updates the pose. Returns the root motion since the last update,
+    /// or the identity transform when root motion isn't enabled.
+    pub fn update_animation(&mut self, delta_time: f32) -> Transform {
+        if self.blended_animations.is_empty() {
+            self.current_animation.update(delta_time, &mut self.events.borrow_mut());
+            self.update_transitions(delta_time);
+        } else {
+            self.update_blended_animations(delta_time);
+        }
+        self.update_local_pose();
+        self.update_inertialization(delta_time);
+        self.update_playing_morph_weights();
+        let root_motion = self.update_root_motion();
+        self.update_layers(delta_time);
+        self.remove_root_motion();
+        self.update_node_transforms();
+        self.update_ik();
+        self.update_spring_bones(delta_time);
         self.update_final_transforms();
+        self.trim_events();
+        root_motion
+    }
+
+    fn trim_events(&self) {
+        let mut events = self.events.borrow_mut();
+        if events.len() > MAX_QUEUED_EVENTS {
+            let excess = events.len() - MAX_QUEUED_EVENTS;
+            events.drain(..excess);
+        }
+    }
+
+    /// Switches skinning method, updating the final transforms for the current pose.
+    pub fn set_skinning_method(&mut self, skinning_method: SkinningMethod) {
+        self.skinning_method = skinning_method;
+        self.update_final_transforms();
+    }
+
+    /// Holds a mesh's morph target at the weight, replacing any animation of it, until cleared.
+    /// For lip-sync and other weights driven from outside the animations.
+    pub fn set_morph_weight(&mut self, mesh_index: u32, target_index: usize, weight: f32) {
+        self.morph_weight_overrides.insert((mesh_index, target_index), weight);
+        mesh_morph_weights(&mut self.morph_weights.borrow_mut(), mesh_index, target_index + 1)[target_index] = weight;
+    }
+
+    /// Returns a morph target set by set_morph_weight to its animated weight from the next update.
+    pub fn clear_morph_weight(&mut self, mesh_index: u32, target_index: usize) {
+        self.morph_weight_overrides.remove(&(mesh_index, target_index));
+    }
+
+    pub fn clear_morph_weights(&mut self) {
+        self.morph_weight_overrides.clear();
+    }
+
+    /// Extracts root motion from the named node. `translation_axes` has 1.0 for each axis to extract.
+    pub fn set_root_motion(&mut self, node_name: &str, translation_axes: Vec3, extract_yaw: bool) -> Result<(), Error> {
+        let node_index = self
+            .skeleton
+            .find_node(node_name)
+            .ok_or_else(|| AnimationError(format!("root motion node not found: {}", node_name)))?;
+
+        self.root_motion = Some(RootMotion {
+            node_index,
+            translation_axes,
+            extract_yaw,
+        });
+        Ok(())
+    }
+
+    /// The root motion of the animations posing the model, blended by their weights.
+    fn update_root_motion(&self) -> Transform {
+        let root_motion = match &self.root_motion {
+            Some(root_motion) => root_motion,
+            None => return Transform::IDENTITY,
+        };
+
+        let transitions = self.transitions.borrow();
+        let mut weights = self.scratch_weights.borrow_mut();
+        self.write_source_weights(&mut weights);
+
+        blend_motion(
+            self.sources(&transitions)
+                .zip(weights.iter())
+                .map(|(animation, weight)| (self.animation_root_motion(root_motion, animation), *weight)),
+        )
+    }
+
+    /// How far the animation's last update moved the root motion node, through each loop or turn around.
+    fn animation_root_motion(&self, root_motion: &RootMotion, animation: &PlayingAnimation) -> Transform {
+        let sample = |tick: f32| root_motion.extract(self.sample_node(animation.animation_index, root_motion.node_index, tick));
+
+        animation
+            .traversed
+            .iter()
+            .fold(Transform::IDENTITY, |motion, &(from_tick, to_tick, passes)| {
+                motion.mul_transform(repeat_motion(relative_motion(sample(from_tick), sample(to_tick)), passes))
+            })
+    }
+
+    fn remove_root_motion(&self) {
+        if let Some(root_motion) = &self.root_motion {
+            let mut local_pose = self.local_pose.borrow_mut();
+            let node_index = root_motion.node_index;
+            local_pose[node_index] = root_motion.remove(local_pose[node_index]);
+        }
     }
 
     fn update_transitions(&mut self, delta_time: f32) {
-        self.transitions.borrow_mut().retain_mut(|animation| {
-            animation.current_weight -= animation.weight_decline_per_sec * delta_time;
-            animation.current_weight > 0.0
+        let mut events = self.events.borrow_mut();
+        self.transitions.borrow_mut().retain_mut(|transition| {
+            transition.current_weight -= transition.weight_decline_per_sec * delta_time;
+            if transition.current_weight <= 0.0 {
+                return false;
+            }
+            transition.animation.update(delta_time, &mut events);
+            true
         })
     }
 
-    fn update_node_map(&mut self, delta_time: f32) {
-        self.node_transforms.borrow_mut().clear();
+    fn update_blended_animations(&mut self, delta_time: f32) {
+        let mut events = self.events.borrow_mut();
+        for blended in self.blended_animations.iter_mut() {
+            blended.animation.update(delta_time, &mut events);
+        }
+    }
 
-        let mut transitions = self.transitions.borrow_mut();
-        let mut node_map = self.node_transforms.borrow_mut();
-        let node_animations = self.model_animation.node_animations.borrow();
+    /// The animations posing the model, in the order of write_source_weights. These are the blended animations
+    /// when there are any, otherwise the current animation and those being faded out.
+    fn sources<'a>(&'a self, transitions: &'a [AnimationTransition]) -> impl Iterator<Item = &'a PlayingAnimation> {
+        let blending = !self.blended_animations.is_empty();
+        let playing = std::iter::once(&self.current_animation).chain(transitions.iter().map(|transition| &transition.animation));
 
-        let inverse_transform = Transform::from_matrix(self.global_inverse_transform);
+        self.blended_animations
+            .iter()
+            .map(|blended| &blended.animation)
+            .chain(playing.filter(move |_| !blending))
+    }
+
+    /// Replaces weights with the weight of each of the sources. Blended animation weights are normalized.
+    fn write_source_weights(&self, weights: &mut Vec<f32>) {
+        if self.blended_animations.is_empty() {
+            self.write_clip_weights(weights);
+        } else {
+            let total_weight: f32 = self.blended_animations.iter().map(|blended| blended.weight.max(0.0)).sum();
+            weights.clear();
+            weights.extend(self.blended_animations.iter().map(|blended| {
+                if total_weight > 0.0 {
+                    blended.weight.max(0.0) / total_weight
+                } else {
+                    0.0
+                }
+            }));
+        }
+    }
+
+    /// Adds the decaying offset from an inertialized switch to the pose, and records the pose for the next switch.
+    fn update_inertialization(&mut self, delta_time: f32) {
+        let finished = match &mut self.inertialization {
+            Some(inertialization) => {
+                inertialization.update(delta_time);
+                inertialization.apply(&mut self.local_pose.borrow_mut());
+                inertialization.is_finished()
+            }
+            None => false,
+        };
+        if finished {
+            self.inertialization = None;
+        }
+
+        self.restart_stale_pose_history();
+        if delta_time > 0.0 {
+            self.pose_history.record(&self.local_pose.borrow(), delta_time);
+        }
+    }
 
-        // First for current animation at weight 1.0
-        calculate_transform_maps(
-            &self.root_node,
-            &node_animations,
-            &mut node_map,
-            inverse_transform,
-            self.current_animation.current_tick,
-            1.0,
+    /// Starts the history again from the current pose, without a velocity, if the pose was replaced since it was recorded.
+    fn restart_stale_pose_history(&mut self) {
+        if self.pose_replaced.replace(false) {
+            self.pose_history = PoseHistory::new(&self.local_pose.borrow());
+        }
+    }
+
+    /// Blends the morph weights of the animations posing the model by their weights.
+    fn update_playing_morph_weights(&self) {
+        let transitions = self.transitions.borrow();
+        let mut weights = self.scratch_weights.borrow_mut();
+        self.write_source_weights(&mut weights);
+
+        self.update_morph_weights(
+            self.sources(&transitions)
+                .zip(weights.iter())
+                .map(|(animation, weight)| (animation.animation_index, animation.current_tick, *weight)),
         );
+    }
+
+    /// Sums the morph weight channels of the animations, given as animation index, tick and weight, for the
+    /// meshes of the nodes they're bound to. Then replaces any weights set by set_morph_weight.
+    fn update_morph_weights(&self, morph_samples: impl Iterator<Item = (usize, f32, f32)>) {
+        let mut morph_weights = self.morph_weights.borrow_mut();
+
+        let has_morph_channels = self.morph_bindings.iter().any(|morph_binding| !morph_binding.is_empty());
+        if !has_morph_channels && self.morph_weight_overrides.is_empty() && morph_weights.iter().all(Vec::is_empty) {
+            return;
+        }
+
+        // cleared rather than replaced, so the weights keep their allocations
+        morph_weights.iter_mut().for_each(|weights| weights.clear());
+
+        for (animation_index, tick, weight) in morph_samples {
+            let (model_animation, morph_binding) =
+                match (self.model_animations.get(animation_index), self.morph_bindings.get(animation_index)) {
+                    (Some(model_animation), Some(morph_binding)) => (model_animation, morph_binding),
+                    _ => continue,
+                };
 
-        for transition in transitions.iter_mut() {
-            transition.animation.update(delta_time);
-            calculate_transform_maps(
-                &self.root_node,
-                &node_animations,
-                &mut node_map,
-                inverse_transform,
-                transition.animation.current_tick,
-                transition.current_weight,
+            for (morph_animation, binding) in model_animation.morph_animations.iter().zip(morph_binding.iter()) {
+                let (node_index, target_count) = match binding {
+                    Some(binding) => *binding,
+                    None => continue,
+                };
+
+                for mesh_index in self.skeleton.nodes[node_index].meshes.iter() {
+                    let weights = mesh_morph_weights(&mut morph_weights, *mesh_index, target_count);
+                    morph_animation.accumulate_weights(tick, weight, weights);
+                }
+            }
+        }
+
+        for ((mesh_index, target_index), weight) in self.morph_weight_overrides.iter() {
+            mesh_morph_weights(&mut morph_weights, *mesh_index, target_index + 1)[*target_index] = *weight;
+        }
+    }
+
+    /// Blends the animations posing the model by their weights.
+    fn update_local_pose(&self) {
+        let transitions = self.transitions.borrow();
+        if transitions.is_empty() && self.blended_animations.is_empty() {
+            let animation = &self.current_animation;
+            self.sample_animation(&mut self.local_pose.borrow_mut(), animation.animation_index, animation.current_tick);
+            return;
+        }
+
+        let mut scratch_poses = self.scratch_poses.borrow_mut();
+        let mut weights = self.scratch_weights.borrow_mut();
+        self.write_source_weights(&mut weights);
+        while scratch_poses.len() < weights.len() {
+            scratch_poses.push(Pose::rest(&self.skeleton));
+        }
+
+        for (pose, animation) in scratch_poses.iter_mut().zip(self.sources(&transitions)) {
+            self.sample_animation(pose, animation.animation_index, animation.current_tick);
+        }
+
+        self.local_pose.borrow_mut().set_blend_poses(&scratch_poses, &weights);
+    }
+
+    /// Adds a layer playing the clip over the current animation and returns its index in layers.
+    pub fn add_layer(
+        &mut self,
+        clip: &Rc<AnimationClip>,
+        weight: f32,
+        mask: Option<BoneMask>,
+        blend_mode: LayerBlendMode,
+    ) -> Result<usize, Error> {
+        let animation = self
+            .new_playing_animation(clip)
+            .ok_or_else(|| AnimationError(format!("layer animation not found: {:?}", clip.animation_name)))?;
+
+        let mut reference_pose = Pose::rest(&self.skeleton);
+        if let LayerBlendMode::Additive { reference_tick } = blend_mode {
+            self.sample_animation(&mut reference_pose, animation.animation_index, reference_tick);
+        }
+
+        self.layers.push(AnimationLayer {
+            animation,
+            weight,
+            mask,
+            blend_mode,
+            reference_pose,
+        });
+        Ok(self.layers.len() - 1)
+    }
+
+    pub fn bone_mask_from_subtree(&self, root_bone: &str) -> Result<BoneMask, Error> {
+        BoneMask::from_subtree(&self.skeleton, root_bone)
+    }
+
+    pub fn bone_mask_from_bones(&self, bone_names: &[&str]) -> Result<BoneMask, Error> {
+        BoneMask::from_bones(&self.skeleton, bone_names)
+    }
+
+    fn update_layers(&mut self, delta_time: f32) {
+        for layer in self.layers.iter_mut() {
+            layer.animation.update(delta_time, &mut self.events.borrow_mut());
+        }
+
+        let mut local_pose = self.local_pose.borrow_mut();
+        let mut scratch_poses = self.scratch_poses.borrow_mut();
+        let layer_pose = &mut scratch_poses[0];
+
+        for layer in self.layers.iter() {
+            if layer.weight <= 0.0 {
+                continue;
+            }
+
+            let animation_index = layer.animation.animation_index;
+            self.sample_animation(layer_pose, animation_index, layer.animation.current_tick);
+
+            let channel_binding = match self.channel_bindings.get(animation_index) {
+                Some(channel_binding) => channel_binding,
+                None => continue,
+            };
+
+            // only the nodes the layer's animation has channels for are affected
+            for (node_index, channel) in channel_binding.iter().enumerate() {
+                let weight = layer.node_weight(node_index);
+                if channel.is_none() || weight <= 0.0 {
+                    continue;
+                }
+
+                local_pose[node_index] = match layer.blend_mode {
+                    LayerBlendMode::Override => local_pose[node_index].mul_transform_weighted(layer_pose[node_index], weight),
+                    LayerBlendMode::Additive { .. } => add_transform_weighted(
+                        local_pose[node_index],
+                        layer_pose[node_index],
+                        layer.reference_pose[node_index],
+                        weight,
+                    ),
+                };
+            }
+        }
+    }
+
+    /// Samples an animation into the pose. Nodes without a channel use their rest transform.
+    fn sample_animation(&self, pose: &mut Pose, animation_index: usize, current_tick: f32) {
+        match (
+            self.model_animations.get(animation_index),
+            self.channel_bindings.get(animation_index),
+        ) {
+            (Some(model_animation), Some(channel_binding)) => pose.sample_channels(
+                &self.skeleton,
+                &model_animation.node_animations.borrow(),
+                channel_binding,
+                current_tick,
+            ),
+            _ => *pose = Pose::rest(&self.skeleton),
+        }
+    }
+
+    /// Samples a single skeleton node of an animation.
+    fn sample_node(&self, animation_index: usize, node_index: usize, current_tick: f32) -> Transform {
+        let channel = self
+            .channel_bindings
+            .get(animation_index)
+            .and_then(|channel_binding| channel_binding[node_index]);
+
+        match channel {
+            Some(channel) => self.model_animations[animation_index].node_animations.borrow()[channel].get_animation_transform(current_tick),
+            None => self.skeleton.nodes[node_index].transform,
+        }
+    }
+
+    /// Accumulates the local pose down the hierarchy into model space.
+    fn update_node_transforms(&self) {
+        let inverse_transform = Transform::from_matrix(self.global_inverse_transform);
+        self.local_pose
+            .borrow()
+            .write_model_space(&self.skeleton, inverse_transform, &mut self.node_transforms.borrow_mut());
+    }
+
+    fn update_ik(&self) {
+        if self.ik_constraints.is_empty() {
+            return;
+        }
+
+        let mut local_pose = self.local_pose.borrow_mut();
+        let mut node_transforms = self.node_transforms.borrow_mut();
+        let root_parent = Transform::from_matrix(self.global_inverse_transform);
+
+        for constraint in self.ik_constraints.iter() {
+            constraint.apply(&self.skeleton, root_parent, &mut local_pose.transforms, &mut node_transforms);
+        }
+    }
+
+    fn update_spring_bones(&mut self, delta_time: f32) {
+        if self.spring_bones.is_empty() {
+            return;
+        }
+
+        let mut local_pose = self.local_pose.borrow_mut();
+        let mut node_transforms = self.node_transforms.borrow_mut();
+        let root_parent = Transform::from_matrix(self.global_inverse_transform);
+
+        for chain in self.spring_bones.iter_mut() {
+            chain.update(
+                delta_time,
+                &self.skeleton,
+                root_parent,
+                &mut local_pose.transforms,
+                &mut node_transforms,
             );
         }
     }
 
     fn update_final_transforms(&self) {
-        let bone_data_map = self.bone_data_map.borrow();
+        let node_transforms = self.node_transforms.borrow();
 
         let mut final_bones = self.final_bone_matrices.borrow_mut();
         let mut final_node = self.final_node_matrices.borrow_mut();
 
-        for (node_name, node_transform) in self.node_transforms.borrow_mut().iter() {
-            if let Some(bone_data) = bone_data_map.get(node_name.deref()) {
-                let index = bone_data.bone_index as usize;
-                let transform_matrix = node_transform.transform.mul_transform(bone_data.offset_transform).compute_matrix();
-                final_bones[index] = transform_matrix;
+        write_skinning_matrices(&self.skeleton, &node_transforms, &mut final_bones);
+
+        if self.skinning_method == SkinningMethod::DualQuaternion {
+            let mut final_dual_quats = self.final_bone_dual_quats.borrow_mut();
+            for (node, node_transform) in self.skeleton.nodes.iter().zip(node_transforms.iter()) {
+                if let Some(bone_index) = node.bone_index {
+                    final_dual_quats[bone_index] = DualQuat::from_transform(node_transform.mul_transform(node.offset_transform));
+                }
             }
+        }
 
-            for mesh_index in node_transform.meshes.iter() {
-                final_node[*mesh_index as usize] = node_transform.transform.compute_matrix();
+        for (node, node_transform) in self.skeleton.nodes.iter().zip(node_transforms.iter()) {
+            for mesh_index in node.meshes.iter() {
+                final_node[*mesh_index as usize] = node_transform.compute_matrix();
             }
         }
     }
+
+    /// The effective weight of the current animation and each animation being faded out, current first.
+    pub fn clip_weights(&self) -> Vec<(Rc<AnimationClip>, f32)> {
+        let mut weights = vec![];
+        self.write_clip_weights(&mut weights);
+
+        let transitions = self.transitions.borrow();
+        let animations = std::iter::once(&self.current_animation).chain(transitions.iter().map(|transition| &transition.animation));
+        animations.map(|animation| animation.animation_clip.clone()).zip(weights).collect()
+    }
+
+    /// Replaces weights with the clip_weights weights, without allocating once it has grown to fit.
+    fn write_clip_weights(&self, weights: &mut Vec<f32>) {
+        let transitions = self.transitions.borrow();
+
+        // later transitions are blended over earlier ones, so each scales down everything before it
+        weights.clear();
+        weights.push(1.0);
+        for transition in transitions.iter() {
+            for weight in weights.iter_mut() {
+                *weight *= 1.0 - transition.current_weight;
+            }
+            weights.push(transition.current_weight);
+        }
+    }
+
+    /// True once the current Once or Count clip has played to its end.
+    pub fn is_finished(&self) -> bool {
+        self.current_animation.is_finished()
+    }
+
+    /// Takes the looped, finished and marker events queued since the last call, in the order they happened.
+    /// Only the newest MAX_QUEUED_EVENTS are kept between calls.
+    pub fn drain_events(&self) -> Vec<AnimationEvent> {
+        std::mem::take(&mut self.events.borrow_mut())
+    }
+
+    /// Samples the clip's animation at the tick, without changing the animator's pose.
+    pub fn sample_pose(&self, clip: &AnimationClip, tick: f32) -> Option<Pose> {
+        let animation_index = self.find_animation_index(&clip.animation_name)?;
+        let mut pose = Pose::rest(&self.skeleton);
+        self.sample_animation(&mut pose, animation_index, tick);
+        Some(pose)
+    }
+
+    /// The local pose from the last update.
+    pub fn pose(&self) -> Pose {
+        self.local_pose.borrow().clone()
+    }
+
+    /// Replaces the pose with one blended elsewhere, then applies IK and updates the final transforms.
+    pub fn apply_pose(&self, pose: &Pose) -> Result<(), Error> {
+        if pose.len() != self.skeleton.len() {
+            return Err(AnimationError(format!(
+                "pose has {} transforms for a skeleton of {} nodes",
+                pose.len(),
+                self.skeleton.len()
+            )));
+        }
+
+        self.local_pose.borrow_mut().transforms.copy_from_slice(&pose.transforms);
+        self.pose_replaced.set(true);
+        self.update_node_transforms();
+        self.update_ik();
+        self.update_final_transforms();
+        Ok(())
+    }
+
+    /// Model space transform of the named node from the last update.
+    pub fn node_transform(&self, node_name: &str) -> Option<Transform> {
+        self.skeleton
+            .find_node(node_name)
+            .map(|node_index| self.node_transforms.borrow()[node_index])
+    }
 }
 
 /// Converts scene Node tree to local NodeData tree. Converting all the transforms to column major form.
@@ -330,45 +1334,701 @@ fn read_hierarchy_data(source: &Rc<Node>) -> NodeData {
     node_data
 }
 
-pub fn calculate_transform_maps(
-    node_data: &NodeData,
-    node_animations: &Ref<Vec<NodeAnimation>>,
-    node_map: &mut RefMut<HashMap<Rc<str>, NodeTransform>>,
-    parent_transform: Transform,
-    current_tick: f32,
-    weight: f32,
-) {
-    let global_transformation = calculate_transform(node_data, node_animations, node_map, parent_transform, current_tick, weight);
+/// For each morph channel, the node it is named after and the number of targets it has weights for.
+fn bind_morph_channels(skeleton: &Skeleton, morph_animations: &[MorphAnimation]) -> Vec<Option<(usize, usize)>> {
+    morph_animations
+        .iter()
+        .map(|morph_animation| {
+            skeleton
+                .find_node(&morph_animation.name)
+                .map(|node_index| (node_index, morph_animation.target_count()))
+        })
+        .collect()
+}
 
-    for child_node in node_data.children.iter() {
-        calculate_transform_maps(child_node, node_animations, node_map, global_transformation, current_tick, weight);
+/// The mesh's morph weights, grown to at least target_count weights.
+fn mesh_morph_weights(morph_weights: &mut Vec<Vec<f32>>, mesh_index: u32, target_count: usize) -> &mut Vec<f32> {
+    let mesh_index = mesh_index as usize;
+    if morph_weights.len() <= mesh_index {
+        morph_weights.resize(mesh_index + 1, vec![]);
     }
+
+    let weights = &mut morph_weights[mesh_index];
+    if weights.len() < target_count {
+        weights.resize(target_count, 0.0);
+    }
+    weights
 }
 
-fn calculate_transform(
-    node_data: &NodeData,
-    node_animations: &Ref<Vec<NodeAnimation>>,
-    node_map: &mut RefMut<HashMap<Rc<str>, NodeTransform>>,
-    parent_transform: Transform,
-    current_tick: f32,
-    weight: f32,
-) -> Transform {
-    let some_node_animation = node_animations.iter().find(|node_anim| node_anim.name == node_data.name);
-
-    let global_transform = match some_node_animation {
-        Some(node_animation) => {
-            let node_transform = node_animation.get_animation_transform(current_tick);
-            parent_transform.mul_transform(node_transform)
-        }
-        None => parent_transform.mul_transform(node_data.transform),
+#[cfg(test)]
+mod tests {
+    use crate::animator::{
+        AnimationClip, AnimationEvent, AnimationMarker, AnimationRepeat, Animator, TransitionMode, WeightedAnimation, MAX_QUEUED_EVENTS,
     };
+    use crate::error::Error;
+    use crate::hash_map::HashMap;
+    use crate::model::ModelBuilder;
+    use crate::model_animation::{BoneData, ModelAnimation, NodeData};
+    use crate::morph::{KeyMorphWeights, MorphAnimation};
+    use crate::node_animation::{KeyPosition, KeyRotation, KeyScale, KeyframeBoundary, NodeAnimation};
+    use crate::pose::Pose;
+    use crate::transform::Transform;
+    use glam::{vec3, Mat4, Quat, Vec3};
+    use std::f32::consts::{FRAC_PI_2, FRAC_PI_4};
+    use std::rc::Rc;
+    use std::time::Duration;
 
-    node_map
-        .entry_ref(node_data.name.as_ref())
-        .and_modify(|n| {
-            n.transform = n.transform.mul_transform_weighted(global_transform, weight);
-        })
-        .or_insert(NodeTransform::new(global_transform, &node_data.meshes));
+    /// An animation that moves the "root" node along x by one unit per tick.
+    fn moving_animation(name: &str, duration: f32, ticks_per_second: f32) -> ModelAnimation {
+        let node_animation = NodeAnimation {
+            name: Rc::from("root"),
+            positions: vec![
+                KeyPosition::new(0.0, Vec3::ZERO),
+                KeyPosition::new(duration, vec3(duration, 0.0, 0.0)),
+            ],
+            rotations: vec![KeyRotation::new(0.0, Quat::IDENTITY)],
+            quantized_rotations: vec![],
+            scales: vec![KeyScale::new(0.0, Vec3::ONE)],
+            boundary: KeyframeBoundary::Clamp,
+        };
+
+        ModelAnimation {
+            name: Rc::from(name),
+            duration,
+            ticks_per_second,
+            node_animations: vec![node_animation].into(),
+            morph_animations: vec![],
+        }
+    }
+
+    fn test_animator() -> Animator {
+        let root_node = NodeData {
+            name: Rc::from("root"),
+            transform: Transform::IDENTITY,
+            children: vec![],
+            meshes: Rc::new(vec![]),
+        };
+
+        let animations = vec![moving_animation("idle", 20.0, 10.0), moving_animation("walk", 60.0, 30.0)];
+
+        Animator::from_parts(root_node, Mat4::IDENTITY, HashMap::new().into(), animations)
+    }
+
+    fn root_translation_x(animator: &Animator) -> f32 {
+        animator.node_transform("root").unwrap().translation.x
+    }
+
+    #[test]
+    fn test_palettes_sized_from_bones_and_meshes() {
+        let root_node = NodeData {
+            name: Rc::from("root"),
+            transform: Transform::IDENTITY,
+            children: vec![],
+            meshes: Rc::new(vec![0, 60]),
+        };
+
+        let mut bone_data_map = HashMap::new();
+        bone_data_map.insert("root".to_string(), BoneData::new("root", 149, Mat4::IDENTITY));
+
+        let mut animator = Animator::from_parts(
+            root_node,
+            Mat4::IDENTITY,
+            bone_data_map.into(),
+            vec![moving_animation("idle", 20.0, 10.0)],
+        );
+        assert_eq!(animator.final_bone_matrices.borrow().len(), 150);
+        assert_eq!(animator.final_node_matrices.borrow().len(), 61);
+
+        animator.update_animation(0.5);
+        assert_eq!(
+            animator.final_bone_matrices.borrow()[149],
+            Mat4::from_translation(vec3(5.0, 0.0, 0.0))
+        );
+        assert_eq!(
+            animator.final_node_matrices.borrow()[60],
+            Mat4::from_translation(vec3(5.0, 0.0, 0.0))
+        );
+    }
+
+    #[test]
+    fn test_lists_all_animations() {
+        let mut animator = test_animator();
+
+        let names: Vec<String> = animator.animation_names().iter().map(|name| name.to_string()).collect();
+        assert_eq!(names, vec!["idle", "walk"]);
+
+        assert!(animator.animation_clip("run", AnimationRepeat::Forever).is_none());
+
+        // a clip naming a missing animation is rejected and the current animation keeps playing
+        let idle = animator.animation_clip("idle", AnimationRepeat::Forever).unwrap();
+        animator.play_clip(&idle).unwrap();
+        let typo = Rc::new(AnimationClip::from_animation("wlak", 0.0, 60.0, AnimationRepeat::Forever));
+        match animator.play_clip(&typo) {
+            Err(Error::AnimationError(message)) => assert!(message.contains("wlak"), "{}", message),
+            result => panic!("expected animation not found, got: {:?}", result),
+        }
+        assert!(animator.play_clip_with_transition(&typo, Duration::from_secs(1)).is_err());
+        assert!(Rc::ptr_eq(&animator.current_animation.animation_clip, &idle));
+        assert!(animator.transitions.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_loads_every_animation_in_gltf() {
+        let scene = ModelBuilder::load_russimp_scene(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/clip_library.gltf")).unwrap();
+        let mut animator = Animator::new(&scene, HashMap::new().into());
+
+        let names: Vec<String> = animator.animation_names().iter().map(|name| name.to_string()).collect();
+        assert_eq!(names, vec!["idle", "walk", "wave"]);
+
+        // each moves the root to its own end position over a second
+        for (name, end) in [
+            ("idle", vec3(0.0, 1.0, 0.0)),
+            ("walk", vec3(2.0, 0.0, 0.0)),
+            ("wave", vec3(0.0, 0.0, 3.0)),
+        ] {
+            let clip = animator.animation_clip(name, AnimationRepeat::Once).unwrap();
+            animator.play_clip(&clip).unwrap();
+            animator.update_animation(0.5);
+            let translation = animator.node_transform("root").unwrap().translation;
+            assert!(translation.abs_diff_eq(end * 0.5, 0.0001), "{}: {}", name, translation);
+        }
+    }
+
+    #[test]
+    fn test_clips_use_their_own_timing() {
+        let mut animator = test_animator();
+
+        let idle = animator.animation_clip("idle", AnimationRepeat::Forever).unwrap();
+        assert_eq!(idle.end_tick, 20.0);
+        animator.play_clip(&idle).unwrap();
+        animator.update_animation(0.5);
+        assert_eq!(animator.current_animation.current_tick, 5.0);
+        assert!((root_translation_x(&animator) - 5.0).abs() < 0.0001);
+
+        let walk = animator.animation_clip("walk", AnimationRepeat::Forever).unwrap();
+        assert_eq!(walk.end_tick, 60.0);
+        animator.play_clip(&walk).unwrap();
+        animator.update_animation(0.5);
+        assert_eq!(animator.current_animation.animation_index, 1);
+        assert_eq!(animator.current_animation.current_tick, 15.0);
+        assert!((root_translation_x(&animator) - 15.0).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_add_animation_reports_unmatched_bones() {
+        let mut animator = test_animator();
+
+        animator.add_animation(moving_animation("run", 30.0, 30.0)).unwrap();
+        assert_eq!(animator.animation_names().len(), 3);
+
+        let duplicate = animator.add_animation(moving_animation("run", 30.0, 30.0));
+        assert!(duplicate.is_err());
+
+        let strange = moving_animation("strange", 30.0, 30.0);
+        strange.node_animations.borrow_mut()[0].name = Rc::from("mixamorig:Tail");
+        match animator.add_animation(strange) {
+            Err(Error::AnimationError(message)) => assert!(message.contains("mixamorig:Tail")),
+            result => panic!("expected unmatched bone error, got: {:?}", result),
+        }
+        assert_eq!(animator.animation_names().len(), 3);
+
+        // a failing batch adds none of its animations
+        let strange = moving_animation("strange", 30.0, 30.0);
+        strange.node_animations.borrow_mut()[0].name = Rc::from("mixamorig:Tail");
+        assert!(animator.add_animations(vec![moving_animation("jog", 30.0, 30.0), strange]).is_err());
+        let repeated = vec![moving_animation("jog", 30.0, 30.0), moving_animation("jog", 30.0, 30.0)];
+        assert!(animator.add_animations(repeated).is_err());
+        assert_eq!(animator.animation_names().len(), 3);
+
+        let batch = vec![moving_animation("jog", 30.0, 30.0), moving_animation("sprint", 30.0, 30.0)];
+        animator.add_animations(batch).unwrap();
+        assert_eq!(animator.animation_names().len(), 5);
+    }
+
+    #[test]
+    fn test_count_repeat_loops_then_holds() {
+        let mut animator = test_animator();
+
+        // idle runs at 10 ticks per second over 20 ticks, so each play through takes 2 seconds
+        let clip = Rc::new(AnimationClip::from_animation("idle", 0.0, 20.0, AnimationRepeat::Count(3)));
+        animator.play_clip(&clip).unwrap();
+
+        animator.update_animation(1.0);
+        assert_eq!(animator.current_animation.normalized_time(), 0.5);
+        assert_eq!(animator.current_animation.remaining_time(), 5.0);
+
+        let mut looped = 0;
+        let mut finished = 0;
+        for _ in 0..10 {
+            animator.update_animation(0.5);
+            for event in animator.drain_events() {
+                match event {
+                    AnimationEvent::Looped {
+                        clip: event_clip,
+                        completions,
+                    } => {
+                        assert!(Rc::ptr_eq(&event_clip, &clip));
+                        looped += 1;
+                        assert_eq!(completions, looped);
+                    }
+                    AnimationEvent::Finished { .. } => finished += 1,
+                    AnimationEvent::Marker { .. } => {}
+                }
+            }
+        }
+
+        assert_eq!(looped, 2);
+        assert_eq!(finished, 1);
+        assert!(animator.is_finished());
+        assert_eq!(animator.current_animation.current_tick, 20.0);
+        assert_eq!(animator.current_animation.remaining_time(), 0.0);
+        assert!((root_translation_x(&animator) - 20.0).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_once_finishes_and_forever_keeps_looping() {
+        let mut animator = test_animator();
 
-    global_transform
+        let once = Rc::new(AnimationClip::from_animation("idle", 0.0, 20.0, AnimationRepeat::Once));
+        animator.play_clip(&once).unwrap();
+        animator.update_animation(1.9);
+        assert!(!animator.is_finished());
+        animator.update_animation(0.2);
+        assert!(animator.is_finished());
+        assert_eq!(animator.drain_events().len(), 1);
+
+        let forever = Rc::new(AnimationClip::from_animation("idle", 0.0, 20.0, AnimationRepeat::Forever));
+        animator.play_clip(&forever).unwrap();
+        animator.update_animation(2.5);
+        assert!(!animator.is_finished());
+        assert!((animator.current_animation.current_tick - 5.0).abs() < 0.0001);
+        assert!(matches!(
+            animator.drain_events()[..],
+            [AnimationEvent::Looped { completions: 1, .. }]
+        ));
+
+        // left undrained, the queue keeps only the newest events
+        for _ in 0..MAX_QUEUED_EVENTS + 10 {
+            animator.update_animation(2.0);
+        }
+        let events = animator.drain_events();
+        assert_eq!(events.len(), MAX_QUEUED_EVENTS);
+        assert!(matches!(events.last(), Some(AnimationEvent::Looped { completions, .. }) if *completions == MAX_QUEUED_EVENTS as u32 + 11));
+    }
+
+    #[test]
+    fn test_long_frames_count_loops_without_stepping() {
+        let mut animator = test_animator();
+        animator.set_root_motion("root", Vec3::X, false).unwrap();
+
+        // idle plays 20 ticks in 2 seconds, so this is 50000 loops and 2.5 ticks
+        let forever = animator.animation_clip("idle", AnimationRepeat::Forever).unwrap();
+        animator.play_clip(&forever).unwrap();
+        animator.update_animation(100_000.25);
+        assert!((animator.current_animation.current_tick - 2.5).abs() < 0.01);
+        assert_eq!(animator.current_animation.repeat_completions, 50_000);
+        assert!(matches!(
+            animator.drain_events()[..],
+            [AnimationEvent::Looped { completions: 50_000, .. }]
+        ));
+
+        // the root motion of the whole loops is included
+        animator.play_clip(&forever).unwrap();
+        let motion = animator.update_animation(10.25);
+        assert!((motion.translation.x - 102.5).abs() < 0.001);
+
+        let count = Rc::new(AnimationClip::from_animation("idle", 0.0, 20.0, AnimationRepeat::Count(3)));
+        animator.play_clip(&count).unwrap();
+        animator.drain_events();
+        animator.update_animation(1000.0);
+        assert!(animator.is_finished());
+        assert_eq!(animator.current_animation.repeat_completions, 3);
+        assert_eq!(animator.current_animation.current_tick, 20.0);
+        assert!(matches!(
+            animator.drain_events()[..],
+            [AnimationEvent::Looped { completions: 2, .. }, AnimationEvent::Finished { .. }]
+        ));
+
+        // reversing at the start tick wraps to the end without counting a completion
+        animator.play_clip(&forever).unwrap();
+        animator.update_animation(0.0);
+        animator.set_speed(-1.0);
+        animator.update_animation(0.5);
+        assert_eq!(animator.current_animation.current_tick, 15.0);
+        assert_eq!(animator.current_animation.repeat_completions, 0);
+    }
+
+    fn marker_names(animator: &Animator) -> Vec<String> {
+        animator
+            .drain_events()
+            .into_iter()
+            .filter_map(|event| match event {
+                AnimationEvent::Marker { marker, .. } => Some(marker.name),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_markers_across_loops_and_transitions() {
+        let mut animator = test_animator();
+
+        let walk = Rc::new(
+            AnimationClip::from_animation("walk", 0.0, 60.0, AnimationRepeat::Forever)
+                .with_marker("left_foot", 0.0)
+                .with_marker("right_foot", 30.0)
+                .with_marker("end", 60.0),
+        );
+        animator.play_clip(&walk).unwrap();
+
+        // walk runs at 30 ticks per second
+        animator.update_animation(0.5);
+        assert_eq!(marker_names(&animator), vec!["left_foot"]);
+        animator.update_animation(0.6);
+        assert_eq!(marker_names(&animator), vec!["right_foot"]);
+
+        // crossing the loop boundary, ticks 33 to 78, reports the end of this loop and the start of the next
+        animator.update_animation(1.5);
+        assert_eq!(marker_names(&animator), vec!["end", "left_foot"]);
+
+        // markers of a clip being faded out are still reported
+        let idle = Rc::new(AnimationClip::from_animation("idle", 0.0, 20.0, AnimationRepeat::Forever).with_marker("breath", 5.0));
+        animator.play_clip_with_transition(&idle, Duration::from_secs(2)).unwrap();
+        animator.update_animation(0.6);
+        assert_eq!(marker_names(&animator), vec!["breath", "right_foot"]);
+    }
+
+    #[test]
+    fn test_apply_custom_blended_pose() {
+        let animator = test_animator();
+
+        let idle = animator.animation_clip("idle", AnimationRepeat::Forever).unwrap();
+        let walk = animator.animation_clip("walk", AnimationRepeat::Forever).unwrap();
+        let idle_pose = animator.sample_pose(&idle, 10.0).unwrap();
+        let walk_pose = animator.sample_pose(&walk, 40.0).unwrap();
+
+        animator.apply_pose(&Pose::blend(&idle_pose, &walk_pose, 0.25)).unwrap();
+        assert!((root_translation_x(&animator) - 17.5).abs() < 0.0001);
+        assert_eq!(animator.pose()[0].translation.x, 17.5);
+
+        assert!(animator.apply_pose(&Pose { transforms: vec![] }).is_err());
+    }
+
+    #[test]
+    fn test_weight_animations_are_normalized_in_any_order() {
+        let mut animator = test_animator();
+
+        // idle at tick 4, walk at tick 10 and walk at tick 40
+        let entries = [
+            WeightedAnimation::new(2.0, 0.0, 20.0, 4.0, 0.0).with_animation("idle"),
+            WeightedAnimation::new(1.0, 0.0, 60.0, 10.0, 0.0).with_animation("walk"),
+            WeightedAnimation::new(1.0, 0.0, 60.0, 40.0, 0.0).with_animation("walk"),
+        ];
+
+        for order in [[0, 1, 2], [2, 1, 0], [1, 2, 0]] {
+            let permuted: Vec<WeightedAnimation> = order
+                .iter()
+                .map(|index| {
+                    let entry = &entries[*index];
+                    WeightedAnimation::new(entry.weight, entry.start_tick, entry.end_tick, entry.offset, 0.0)
+                        .with_animation(entry.animation_name.clone().unwrap())
+                })
+                .collect();
+
+            animator.play_weight_animations(&permuted, 0.0);
+            assert!((root_translation_x(&animator) - 14.5).abs() < 0.0001);
+        }
+
+        // ticks beyond the range are clamped rather than panicking
+        animator.play_weight_animations(&[WeightedAnimation::new(1.0, 0.0, 20.0, 0.0, 2.0).with_animation("idle")], 1.0);
+        assert_eq!(root_translation_x(&animator), 0.0);
+        animator.play_weight_animations(&[WeightedAnimation::new(1.0, 0.0, 20.0, 0.0, 1.0).with_animation("idle")], 9.0);
+        assert_eq!(root_translation_x(&animator), 20.0);
+    }
+
+    #[test]
+    fn test_speed_and_reverse_playback() {
+        let mut animator = test_animator();
+        animator.set_root_motion("root", Vec3::X, false).unwrap();
+
+        // idle runs at 10 ticks per second, so at -2.0 speed it plays from tick 20 back to 0 in one second
+        let reverse = Rc::new(
+            AnimationClip::from_animation("idle", 0.0, 20.0, AnimationRepeat::Once)
+                .with_speed(-2.0)
+                .with_marker("start", 0.0)
+                .with_marker("middle", 10.0),
+        );
+        animator.play_clip(&reverse).unwrap();
+
+        let motion = animator.update_animation(0.25);
+        assert_eq!(animator.current_animation.current_tick, 15.0);
+        assert!((motion.translation.x + 5.0).abs() < 0.0001);
+        assert_eq!(animator.current_animation.remaining_time(), 0.75);
+        assert!(marker_names(&animator).is_empty());
+
+        // changing speed carries on from the current tick
+        animator.set_speed(0.5);
+        animator.update_animation(0.6);
+        assert!((animator.current_animation.current_tick - 9.0).abs() < 0.0001);
+        assert_eq!(marker_names(&animator), vec!["middle"]);
+
+        animator.update_animation(2.0);
+        assert!(animator.is_finished());
+        assert_eq!(animator.current_animation.current_tick, 0.0);
+        let events = animator.drain_events();
+        assert!(matches!(
+            events[..],
+            [AnimationEvent::Marker { .. }, AnimationEvent::Finished { .. }]
+        ));
+
+        // an animation being faded out keeps its own speed
+        let forward = Rc::new(AnimationClip::from_animation("idle", 0.0, 20.0, AnimationRepeat::Forever).with_speed(3.0));
+        animator.play_clip(&forward).unwrap();
+        animator.play_clip_with_transition(&reverse, Duration::from_secs(1)).unwrap();
+        animator.update_animation(0.5);
+        assert_eq!(animator.transitions.borrow()[0].animation.current_tick, 15.0);
+        assert_eq!(animator.current_animation.current_tick, 10.0);
+    }
+
+    #[test]
+    fn test_ping_pong_bounces_between_ticks() {
+        let mut animator = test_animator();
+        animator.set_root_motion("root", Vec3::X, false).unwrap();
+
+        let clip = Rc::new(AnimationClip::from_animation("idle", 0.0, 20.0, AnimationRepeat::PingPong).with_marker("end", 20.0));
+        animator.play_clip(&clip).unwrap();
+
+        // ticks 0 to 20 and back to 15
+        let motion = animator.update_animation(2.5);
+        assert!((animator.current_animation.current_tick - 15.0).abs() < 0.0001);
+        assert!(animator.current_animation.is_reversed());
+        assert!((motion.translation.x - 15.0).abs() < 0.0001);
+        let events = animator.drain_events();
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[1], AnimationEvent::Looped { completions: 1, .. }));
+
+        // back to 0 and forwards again to 5, never finishing
+        let motion = animator.update_animation(2.0);
+        assert!((animator.current_animation.current_tick - 5.0).abs() < 0.0001);
+        assert!(!animator.current_animation.is_reversed());
+        assert!((motion.translation.x + 10.0).abs() < 0.0001);
+        assert!(!animator.is_finished());
+        assert!(marker_names(&animator).is_empty());
+    }
+
+    #[test]
+    fn test_morph_weights_animated_and_set_manually() {
+        let root_node = NodeData {
+            name: Rc::from("root"),
+            transform: Transform::IDENTITY,
+            children: vec![],
+            meshes: Rc::new(vec![2]),
+        };
+
+        // crossfades the root node's mesh from its second target to its first over 10 ticks
+        let mut talk = moving_animation("talk", 10.0, 10.0);
+        talk.morph_animations = vec![MorphAnimation {
+            name: Rc::from("root"),
+            keys: vec![
+                KeyMorphWeights::new(0.0, vec![0.0, 1.0]),
+                KeyMorphWeights::new(10.0, vec![1.0, 0.0]),
+            ],
+        }];
+
+        let mut animator = Animator::from_parts(root_node, Mat4::IDENTITY, HashMap::new().into(), vec![talk]);
+        let clip = animator.animation_clip("talk", AnimationRepeat::Once).unwrap();
+        animator.play_clip(&clip).unwrap();
+
+        let assert_weights = |animator: &Animator, expected: [f32; 2]| {
+            let morph_weights = animator.morph_weights.borrow();
+            assert!(morph_weights[0].is_empty());
+            assert_eq!(morph_weights[2].len(), 2);
+            for (weight, expected) in morph_weights[2].iter().zip(expected) {
+                assert!((weight - expected).abs() < 0.0001, "{:?}", morph_weights[2]);
+            }
+        };
+
+        animator.update_animation(0.5);
+        assert_weights(&animator, [0.5, 0.5]);
+
+        // a manual weight replaces the animated one until cleared
+        animator.set_morph_weight(2, 1, 0.9);
+        assert_weights(&animator, [0.5, 0.9]);
+        animator.update_animation(0.2);
+        assert_weights(&animator, [0.7, 0.9]);
+
+        animator.clear_morph_weight(2, 1);
+        animator.update_animation(0.1);
+        assert_weights(&animator, [0.8, 0.2]);
+    }
+
+    #[test]
+    fn test_inertialized_transition() {
+        let mut animator = test_animator();
+        animator.transition_mode = TransitionMode::Inertialization;
+
+        let idle = animator.animation_clip("idle", AnimationRepeat::Forever).unwrap();
+        let walk = animator.animation_clip("walk", AnimationRepeat::Forever).unwrap();
+        animator.play_clip(&idle).unwrap();
+        animator.update_animation(0.5);
+        animator.update_animation(0.1);
+        assert!((root_translation_x(&animator) - 6.0).abs() < 0.0001);
+
+        // walk starts back at 0, but the pose carries on from where idle left it, moving the same way
+        animator.play_clip_with_transition(&walk, Duration::from_millis(250)).unwrap();
+        assert!(animator.transitions.borrow().is_empty());
+        animator.update_animation(0.0);
+        assert!((root_translation_x(&animator) - 6.0).abs() < 0.0001);
+        animator.update_animation(0.001);
+        let x = root_translation_x(&animator);
+        assert!(x > 6.0 && x < 6.02, "{}", x);
+
+        // only walk once the transition is over
+        animator.update_animation(0.249);
+        assert!((root_translation_x(&animator) - 7.5).abs() < 0.0001);
+
+        // switching before there is a velocity holds the pose still instead of cutting to walk
+        let mut animator = test_animator();
+        animator.transition_mode = TransitionMode::Inertialization;
+        animator.play_clip(&idle).unwrap();
+        animator.update_animation(0.5);
+        animator.play_clip_with_transition(&walk, Duration::from_millis(250)).unwrap();
+        animator.update_animation(0.0);
+        assert!((root_translation_x(&animator) - 5.0).abs() < 0.0001);
+        animator.update_animation(0.001);
+        let x = root_translation_x(&animator);
+        assert!(x > 5.0 && x < 5.05, "{}", x);
+    }
+
+    #[test]
+    fn test_inertialization_starts_from_the_latest_pose() {
+        let mut animator = test_animator();
+        let idle = animator.animation_clip("idle", AnimationRepeat::Forever).unwrap();
+        let walk = animator.animation_clip("walk", AnimationRepeat::Forever).unwrap();
+
+        // the history is kept while crossfading, so switching modes carries on moving the same way
+        animator.play_clip(&idle).unwrap();
+        animator.update_animation(0.5);
+        animator.update_animation(0.1);
+        animator.transition_mode = TransitionMode::Inertialization;
+        animator.play_clip_with_transition(&walk, Duration::from_millis(250)).unwrap();
+        animator.update_animation(0.0);
+        assert!((root_translation_x(&animator) - 6.0).abs() < 0.0001);
+        animator.update_animation(0.001);
+        let x = root_translation_x(&animator);
+        assert!(x > 6.0 && x < 6.02, "{}", x);
+
+        // poses written outside of update_animation are where the next switch starts from
+        animator.play_weight_animations(&[WeightedAnimation::new(1.0, 0.0, 20.0, 0.0, 0.0).with_animation("idle")], 1.0);
+        assert!((root_translation_x(&animator) - 10.0).abs() < 0.0001);
+        animator.play_clip_with_transition(&idle, Duration::from_millis(250)).unwrap();
+        animator.update_animation(0.0);
+        assert!((root_translation_x(&animator) - 10.0).abs() < 0.0001);
+
+        let mut pose = animator.pose();
+        pose.transforms[0].translation.x = 100.0;
+        animator.apply_pose(&pose).unwrap();
+        animator.play_clip_with_transition(&walk, Duration::from_millis(250)).unwrap();
+        animator.update_animation(0.0);
+        assert!((root_translation_x(&animator) - 100.0).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_root_motion_across_loops_and_transitions() {
+        let mut animator = test_animator();
+        animator.set_root_motion("root", vec3(1.0, 0.0, 1.0), false).unwrap();
+        assert!(animator.set_root_motion("hips", Vec3::ONE, false).is_err());
+
+        let walk = animator.animation_clip("walk", AnimationRepeat::Forever).unwrap();
+        animator.play_clip(&walk).unwrap();
+
+        // walk moves one unit per tick at 30 ticks per second, which is removed from the pose
+        let motion = animator.update_animation(0.5);
+        assert!((motion.translation.x - 15.0).abs() < 0.0001);
+        assert!(root_translation_x(&animator).abs() < 0.0001);
+
+        // ticks 15 to 90 wrap around the 60 tick loop once
+        let motion = animator.update_animation(2.5);
+        assert!((animator.current_animation.current_tick - 30.0).abs() < 0.0001);
+        assert!((motion.translation.x - 75.0).abs() < 0.0001);
+
+        // halfway through the crossfade walk moves 15 and idle 5
+        let idle = animator.animation_clip("idle", AnimationRepeat::Forever).unwrap();
+        animator.play_clip_with_transition(&idle, Duration::from_secs(1)).unwrap();
+        let motion = animator.update_animation(0.5);
+        assert!((motion.translation.x - 10.0).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_root_motion_yaw() {
+        let turn = ModelAnimation {
+            name: Rc::from("turn"),
+            duration: 10.0,
+            ticks_per_second: 10.0,
+            node_animations: vec![NodeAnimation {
+                name: Rc::from("root"),
+                positions: vec![KeyPosition::new(0.0, Vec3::ZERO), KeyPosition::new(10.0, vec3(0.0, 1.0, 10.0))],
+                rotations: vec![
+                    KeyRotation::new(0.0, Quat::IDENTITY),
+                    KeyRotation::new(10.0, Quat::from_rotation_y(FRAC_PI_2)),
+                ],
+                quantized_rotations: vec![],
+                scales: vec![KeyScale::new(0.0, Vec3::ONE)],
+                boundary: KeyframeBoundary::Clamp,
+            }]
+            .into(),
+            morph_animations: vec![],
+        };
+
+        let mut animator = test_animator();
+        animator.add_animation(turn).unwrap();
+        animator.set_root_motion("root", vec3(1.0, 0.0, 1.0), true).unwrap();
+        let clip = animator.animation_clip("turn", AnimationRepeat::Once).unwrap();
+        animator.play_clip(&clip).unwrap();
+
+        let motion = animator.update_animation(0.5);
+        assert!(motion.translation.abs_diff_eq(vec3(0.0, 0.0, 5.0), 0.0001));
+        assert!(motion.rotation.abs_diff_eq(Quat::from_rotation_y(FRAC_PI_4), 0.0001));
+
+        // the second half moves forward in the frame turned by the first half
+        let motion = animator.update_animation(0.5);
+        let forward = Quat::from_rotation_y(-FRAC_PI_4) * vec3(0.0, 0.0, 5.0);
+        assert!(motion.translation.abs_diff_eq(forward, 0.0001));
+
+        // only the height and none of the yaw is left in the pose
+        let root = animator.node_transform("root").unwrap();
+        assert!(root.translation.abs_diff_eq(vec3(0.0, 1.0, 0.0), 0.0001));
+        assert!(root.rotation.abs_diff_eq(Quat::IDENTITY, 0.0001));
+    }
+
+    #[test]
+    fn test_clips_from_ron() {
+        let ron_clips = r#"[
+            (
+                animation_name: Some("walk"),
+                start_tick: 0.0,
+                end_tick: 60.0,
+                repeat: Forever,
+                markers: [(name: "left_foot", tick: 0.0), (name: "right_foot", tick: 30.0)],
+            ),
+            (start_tick: 55.0, end_tick: 130.0, repeat: Count(2)),
+        ]"#;
+
+        let clips: Vec<AnimationClip> = ron::from_str(ron_clips).unwrap();
+
+        assert_eq!(clips[0].animation_name.as_deref(), Some("walk"));
+        assert_eq!(
+            clips[0].markers[1],
+            AnimationMarker {
+                name: "right_foot".to_string(),
+                tick: 30.0
+            }
+        );
+        assert!(clips[1].animation_name.is_none());
+        assert!(clips[1].markers.is_empty());
+
+        let serialized = ron::to_string(&clips[0]).unwrap();
+        let deserialized: AnimationClip = ron::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.markers, clips[0].markers);
+    }
 }
@@ -0,0 +1,484 @@
+use crate::animator::{AnimationClip, Animator, BlendedAnimation};
+use crate::error::Error;
+use crate::error::Error::AnimationError;
+use crate::transform::Transform;
+use glam::{vec2, Vec2};
+use std::rc::Rc;
+
+/// A clip placed at a position in the blend space.
+#[derive(Debug, Clone)]
+pub struct BlendSample {
+    pub position: Vec2,
+    pub clip: Rc<AnimationClip>,
+}
+
+#[derive(Debug, Clone)]
+pub enum BlendSpaceLayout {
+    /// Indices of the samples sorted along the x axis.
+    Line(Vec<usize>),
+    /// Delaunay triangulation of the sample positions.
+    Triangles(Vec<[usize; 3]>),
+}
+
+/// Blends sample clips by a parameter such as speed or a movement direction. The clips are played
+/// phase synced, all at the same normalized time, so foot plants line up while blending.
+#[derive(Debug, Clone)]
+pub struct BlendSpace {
+    pub samples: Vec<BlendSample>,
+    pub layout: BlendSpaceLayout,
+    /// Shared normalized time of the samples, from 0.0 to 1.0
+    pub phase: f32,
+}
+
+impl BlendSpace {
+    /// Samples placed along a single axis, such as speed. The samples keep the order they are given in.
+    pub fn new_1d(samples: Vec<(f32, Rc<AnimationClip>)>) -> Result<Self, Error> {
+        if samples.is_empty() {
+            return Err(AnimationError("blend space has no samples".to_string()));
+        }
+
+        let samples: Vec<BlendSample> = samples
+            .into_iter()
+            .map(|(position, clip)| BlendSample {
+                position: vec2(position, 0.0),
+                clip,
+            })
+            .collect();
+        check_positions(&samples)?;
+
+        let mut order: Vec<usize> = (0..samples.len()).collect();
+        order.sort_by(|a, b| samples[*a].position.x.total_cmp(&samples[*b].position.x));
+
+        Ok(BlendSpace {
+            samples,
+            layout: BlendSpaceLayout::Line(order),
+            phase: 0.0,
+        })
+    }
+
+    /// Samples placed in a plane, such as a movement direction scaled by speed. The samples keep the order they are given in.
+    pub fn new_2d(samples: Vec<(Vec2, Rc<AnimationClip>)>) -> Result<Self, Error> {
+        let samples: Vec<BlendSample> = samples.into_iter().map(|(position, clip)| BlendSample { position, clip }).collect();
+        check_positions(&samples)?;
+
+        let positions: Vec<Vec2> = samples.iter().map(|sample| sample.position).collect();
+        let triangles = triangulate(&positions);
+
+        if triangles.is_empty() {
+            return Err(AnimationError(
+                "2D blend space needs at least three samples that are not on a line".to_string(),
+            ));
+        }
+
+        Ok(BlendSpace {
+            samples,
+            layout: BlendSpaceLayout::Triangles(triangles),
+            phase: 0.0,
+        })
+    }
+
+    /// Normalized weight of each sample for the parameter, in the order the samples were given. Parameters outside the samples are clamped
+    /// to the nearest point covered by them. 1D blend spaces only use parameter.x.
+    pub fn weights(&self, parameter: Vec2) -> Vec<f32> {
+        let mut weights = vec![0.0; self.samples.len()];
+
+        match &self.layout {
+            BlendSpaceLayout::Line(order) => {
+                let x = parameter.x;
+                let last = order.len() - 1;
+                match order.iter().position(|index| self.samples[*index].position.x > x) {
+                    Some(0) => weights[order[0]] = 1.0,
+                    None => weights[order[last]] = 1.0,
+                    Some(position) => {
+                        let (below, above) = (order[position - 1], order[position]);
+                        let from = self.samples[below].position.x;
+                        let to = self.samples[above].position.x;
+                        let t = (x - from) / (to - from);
+                        weights[below] = 1.0 - t;
+                        weights[above] = t;
+                    }
+                }
+            }
+            BlendSpaceLayout::Triangles(triangles) => {
+                for triangle in triangles.iter() {
+                    let [a, b, c] = triangle.map(|index| self.samples[index].position);
+                    let barycentric = barycentric(parameter, a, b, c);
+                    if barycentric.iter().all(|weight| *weight >= -1e-5) {
+                        for (index, weight) in triangle.iter().zip(barycentric) {
+                            weights[*index] = weight.max(0.0);
+                        }
+                        return normalize(weights);
+                    }
+                }
+
+                // outside the triangles the nearest point is on an edge
+                let mut nearest = (f32::MAX, 0, 0, 0.0);
+                for triangle in triangles.iter() {
+                    for (from, to) in [(triangle[0], triangle[1]), (triangle[1], triangle[2]), (triangle[2], triangle[0])] {
+                        let a = self.samples[from].position;
+                        let b = self.samples[to].position;
+                        let t = ((parameter - a).dot(b - a) / (b - a).length_squared()).clamp(0.0, 1.0);
+                        let distance = parameter.distance_squared(a.lerp(b, t));
+                        if distance < nearest.0 {
+                            nearest = (distance, from, to, t);
+                        }
+                    }
+                }
+                let (_, from, to, t) = nearest;
+                weights[from] = 1.0 - t;
+                weights[to] = t;
+            }
+        }
+
+        weights
+    }
+
+    /// Advances the shared phase and updates the animator with the blended samples, in place of its
+    /// update_animation, returning the root motion. The phase advances at the weighted average of the
+    /// samples' durations. The samples are played by the animator, so its root motion, layers, events,
+    /// IK and spring bones apply, until another clip is played.
+    pub fn update(&mut self, animator: &mut Animator, parameter: Vec2, delta_time: f32) -> Transform {
+        let weights = self.weights(parameter);
+
+        let mut duration = 0.0;
+        for (sample, weight) in self.samples.iter().zip(weights.iter()) {
+            duration += weight * clip_duration(animator, &sample.clip);
+        }
+
+        if !self.is_playing(animator) {
+            animator.blended_animations = self
+                .samples
+                .iter()
+                .filter_map(|sample| animator.new_playing_animation(&sample.clip))
+                .map(|animation| BlendedAnimation { animation, weight: 0.0 })
+                .collect();
+        }
+
+        // each sample starts the update at the shared phase, moving at the speed that keeps it there
+        for blended in animator.blended_animations.iter_mut() {
+            let sample_index = match self.sample_index(&blended.animation.animation_clip) {
+                Some(sample_index) => sample_index,
+                None => continue,
+            };
+            let clip = &self.samples[sample_index].clip;
+            let tick_range = clip.end_tick - clip.start_tick;
+            let animation = &mut blended.animation;
+
+            blended.weight = weights[sample_index];
+            if !animation.is_finished() {
+                animation.current_tick = clip.start_tick + self.phase * tick_range;
+            }
+
+            let tick_rate = animation.ticks_per_second * clip.speed;
+            animation.speed = if duration > 0.0 && tick_rate != 0.0 {
+                tick_range / duration / tick_rate
+            } else {
+                0.0
+            };
+        }
+
+        if duration > 0.0 {
+            self.phase = (self.phase + delta_time / duration).fract();
+        }
+
+        animator.update_animation(delta_time)
+    }
+
+    /// True when the animator is playing this blend space's samples.
+    fn is_playing(&self, animator: &Animator) -> bool {
+        !animator.blended_animations.is_empty()
+            && animator
+                .blended_animations
+                .iter()
+                .all(|blended| self.sample_index(&blended.animation.animation_clip).is_some())
+    }
+
+    fn sample_index(&self, clip: &Rc<AnimationClip>) -> Option<usize> {
+        self.samples.iter().position(|sample| Rc::ptr_eq(&sample.clip, clip))
+    }
+}
+
+/// Seconds the clip takes to play through once.
+/// Fails if a sample position isn't finite or two samples share a position.
+fn check_positions(samples: &[BlendSample]) -> Result<(), Error> {
+    for (index, sample) in samples.iter().enumerate() {
+        if !sample.position.is_finite() {
+            return Err(AnimationError(format!(
+                "blend space sample position is not finite: {}",
+                sample.position
+            )));
+        }
+        if samples[..index].iter().any(|other| other.position == sample.position) {
+            return Err(AnimationError(format!("blend space samples share a position: {}", sample.position)));
+        }
+    }
+    Ok(())
+}
+
+fn clip_duration(animator: &Animator, clip: &AnimationClip) -> f32 {
+    let ticks_per_second = match &clip.animation_name {
+        Some(name) => animator.get_animation(name).map(|animation| animation.ticks_per_second),
+        None => animator.model_animations.first().map(|animation| animation.ticks_per_second),
+    };
+
+    match ticks_per_second {
+        Some(ticks_per_second) if ticks_per_second > 0.0 => (clip.end_tick - clip.start_tick) / ticks_per_second,
+        _ => 0.0,
+    }
+}
+
+fn normalize(mut weights: Vec<f32>) -> Vec<f32> {
+    let total: f32 = weights.iter().sum();
+    if total > 0.0 {
+        weights.iter_mut().for_each(|weight| *weight /= total);
+    }
+    weights
+}
+
+fn barycentric(point: Vec2, a: Vec2, b: Vec2, c: Vec2) -> [f32; 3] {
+    let v0 = b - a;
+    let v1 = c - a;
+    let v2 = point - a;
+
+    let d00 = v0.dot(v0);
+    let d01 = v0.dot(v1);
+    let d11 = v1.dot(v1);
+    let d20 = v2.dot(v0);
+    let d21 = v2.dot(v1);
+
+    let denominator = d00 * d11 - d01 * d01;
+    let v = (d11 * d20 - d01 * d21) / denominator;
+    let w = (d00 * d21 - d01 * d20) / denominator;
+    [1.0 - v - w, v, w]
+}
+
+fn cross(a: Vec2, b: Vec2, c: Vec2) -> f32 {
+    (b - a).perp_dot(c - a)
+}
+
+/// True if point is strictly inside the circumcircle of the triangle a, b, c.
+fn in_circumcircle(point: Vec2, a: Vec2, b: Vec2, c: Vec2) -> bool {
+    let a = a - point;
+    let b = b - point;
+    let c = c - point;
+
+    let determinant = a.length_squared() * b.perp_dot(c) - b.length_squared() * a.perp_dot(c) + c.length_squared() * a.perp_dot(b);
+
+    if cross(Vec2::ZERO, b - a, c - a) > 0.0 {
+        determinant > 0.0
+    } else {
+        determinant < 0.0
+    }
+}
+
+/// Bowyer-Watson Delaunay triangulation. Returns triangles as indices into points.
+fn triangulate(points: &[Vec2]) -> Vec<[usize; 3]> {
+    if points.len() < 3 {
+        return vec![];
+    }
+
+    let min = points.iter().fold(Vec2::MAX, |min, point| min.min(*point));
+    let max = points.iter().fold(Vec2::MIN, |max, point| max.max(*point));
+    let size = (max - min).max_element().max(1.0) * 100.0;
+    let center = (min + max) * 0.5;
+
+    // a super triangle containing all the points, removed at the end
+    let count = points.len();
+    let mut vertices = points.to_vec();
+    vertices.push(center + vec2(-size, -size));
+    vertices.push(center + vec2(size, -size));
+    vertices.push(center + vec2(0.0, size));
+
+    let mut triangles: Vec<[usize; 3]> = vec![[count, count + 1, count + 2]];
+
+    for (point_index, point) in points.iter().enumerate() {
+        let (bad, good): (Vec<[usize; 3]>, Vec<[usize; 3]>) = triangles
+            .into_iter()
+            .partition(|[a, b, c]| in_circumcircle(*point, vertices[*a], vertices[*b], vertices[*c]));
+
+        // the boundary of the hole left by the bad triangles are the edges they don't share
+        let edges: Vec<(usize, usize)> = bad.iter().flat_map(|[a, b, c]| [(*a, *b), (*b, *c), (*c, *a)]).collect();
+        let boundary = edges
+            .iter()
+            .filter(|(a, b)| edges.iter().filter(|(c, d)| (a == c && b == d) || (a == d && b == c)).count() == 1);
+
+        triangles = good;
+        for (a, b) in boundary {
+            triangles.push([*a, *b, point_index]);
+        }
+    }
+
+    triangles
+        .into_iter()
+        .filter(|triangle| triangle.iter().all(|index| *index < count))
+        .filter(|[a, b, c]| cross(points[*a], points[*b], points[*c]).abs() > 1e-6)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::animator::{AnimationClip, AnimationEvent, AnimationRepeat, Animator};
+    use crate::blend_space::BlendSpace;
+    use crate::hash_map::HashMap;
+    use crate::model_animation::{ModelAnimation, NodeData};
+    use crate::node_animation::{KeyPosition, KeyframeBoundary, NodeAnimation};
+    use crate::transform::Transform;
+    use glam::{vec2, vec3, Mat4, Vec2, Vec3};
+    use std::rc::Rc;
+
+    fn clip(name: &str) -> Rc<AnimationClip> {
+        Rc::new(AnimationClip::from_animation(name, 0.0, 10.0, AnimationRepeat::Forever))
+    }
+
+    fn assert_weights(weights: Vec<f32>, expected: &[f32]) {
+        assert_eq!(weights.len(), expected.len());
+        for (weight, expected_weight) in weights.iter().zip(expected) {
+            assert!((weight - expected_weight).abs() < 0.0001, "weights: {:?}", weights);
+        }
+    }
+
+    #[test]
+    fn test_1d_weights() {
+        let blend_space = BlendSpace::new_1d(vec![(4.0, clip("run")), (0.0, clip("idle")), (1.5, clip("walk"))]).unwrap();
+
+        // the weights are in the order the samples were given, not sorted by position
+        assert_weights(blend_space.weights(vec2(-1.0, 0.0)), &[0.0, 1.0, 0.0]);
+        assert_weights(blend_space.weights(vec2(0.75, 0.0)), &[0.0, 0.5, 0.5]);
+        assert_weights(blend_space.weights(vec2(3.5, 0.0)), &[0.8, 0.0, 0.2]);
+        assert_weights(blend_space.weights(vec2(9.0, 0.0)), &[1.0, 0.0, 0.0]);
+        assert_eq!(blend_space.samples[0].clip.animation_name.as_deref(), Some("run"));
+
+        assert!(BlendSpace::new_1d(vec![(1.0, clip("walk")), (1.0, clip("run"))]).is_err());
+        assert!(BlendSpace::new_1d(vec![(1.0, clip("walk")), (f32::NAN, clip("run"))]).is_err());
+    }
+
+    #[test]
+    fn test_2d_weights() {
+        let blend_space = BlendSpace::new_2d(vec![
+            (Vec2::ZERO, clip("idle")),
+            (vec2(0.0, 1.0), clip("forward")),
+            (vec2(0.0, -1.0), clip("back")),
+            (vec2(-1.0, 0.0), clip("left")),
+            (vec2(1.0, 0.0), clip("right")),
+        ])
+        .unwrap();
+
+        let triangle = || {
+            vec![
+                (Vec2::ZERO, clip("idle")),
+                (vec2(0.0, 1.0), clip("forward")),
+                (vec2(1.0, 0.0), clip("right")),
+            ]
+        };
+        let mut duplicate = triangle();
+        duplicate.push((vec2(0.0, 1.0), clip("run")));
+        assert!(BlendSpace::new_2d(duplicate).is_err());
+        let mut infinite = triangle();
+        infinite.push((vec2(f32::INFINITY, 0.0), clip("run")));
+        assert!(BlendSpace::new_2d(infinite).is_err());
+        assert!(BlendSpace::new_2d(triangle()).is_ok());
+
+        assert_weights(blend_space.weights(Vec2::ZERO), &[1.0, 0.0, 0.0, 0.0, 0.0]);
+        assert_weights(blend_space.weights(vec2(0.0, 1.0)), &[0.0, 1.0, 0.0, 0.0, 0.0]);
+        assert_weights(blend_space.weights(vec2(0.25, 0.5)), &[0.25, 0.5, 0.0, 0.0, 0.25]);
+
+        // beyond the samples the weights are clamped to the nearest edge
+        assert_weights(blend_space.weights(vec2(2.0, 2.0)), &[0.0, 0.5, 0.0, 0.0, 0.5]);
+        assert_weights(blend_space.weights(vec2(0.0, -3.0)), &[0.0, 0.0, 1.0, 0.0, 0.0]);
+
+        let on_a_line = vec![
+            (Vec2::ZERO, clip("idle")),
+            (vec2(1.0, 0.0), clip("walk")),
+            (vec2(2.0, 0.0), clip("run")),
+        ];
+        assert!(BlendSpace::new_2d(on_a_line).is_err());
+    }
+
+    /// An animation that moves the "root" node from 0.0 to 1.0 along x over its duration.
+    fn normalized_animation(name: &str, duration: f32) -> ModelAnimation {
+        ModelAnimation {
+            name: Rc::from(name),
+            duration,
+            ticks_per_second: 10.0,
+            node_animations: vec![NodeAnimation {
+                name: Rc::from("root"),
+                positions: vec![KeyPosition::new(0.0, Vec3::ZERO), KeyPosition::new(duration, vec3(1.0, 0.0, 0.0))],
+                rotations: vec![],
+                quantized_rotations: vec![],
+                scales: vec![],
+                boundary: KeyframeBoundary::Clamp,
+            }]
+            .into(),
+            morph_animations: vec![],
+        }
+    }
+
+    #[test]
+    fn test_phase_synced_playback() {
+        let root_node = NodeData {
+            name: Rc::from("root"),
+            transform: Transform::IDENTITY,
+            children: vec![],
+            meshes: Rc::new(vec![]),
+        };
+        let animations = vec![normalized_animation("walk", 20.0), normalized_animation("run", 10.0)];
+        let mut animator = Animator::from_parts(root_node, Mat4::IDENTITY, HashMap::new().into(), animations);
+
+        let walk = Rc::new(AnimationClip::from_animation("walk", 0.0, 20.0, AnimationRepeat::Forever));
+        let run = Rc::new(AnimationClip::from_animation("run", 0.0, 10.0, AnimationRepeat::Forever));
+        let mut blend_space = BlendSpace::new_1d(vec![(1.0, walk), (3.0, run)]).unwrap();
+
+        // walk takes 2 seconds and run 1, so halfway between them a cycle takes 1.5 seconds
+        blend_space.update(&mut animator, vec2(2.0, 0.0), 0.3);
+        assert!((blend_space.phase - 0.2).abs() < 0.0001);
+
+        // both clips are at the same normalized time, so the blend is too
+        let root = animator.node_transform("root").unwrap();
+        assert!((root.translation.x - 0.2).abs() < 0.0001);
+
+        blend_space.update(&mut animator, vec2(3.0, 0.0), 0.5);
+        assert!((blend_space.phase - 0.7).abs() < 0.0001);
+        let root = animator.node_transform("root").unwrap();
+        assert!((root.translation.x - 0.7).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_blend_goes_through_update_animation() {
+        let root_node = NodeData {
+            name: Rc::from("root"),
+            transform: Transform::IDENTITY,
+            children: vec![],
+            meshes: Rc::new(vec![]),
+        };
+        let animations = vec![normalized_animation("walk", 20.0), normalized_animation("run", 10.0)];
+        let mut animator = Animator::from_parts(root_node, Mat4::IDENTITY, HashMap::new().into(), animations);
+        animator.set_root_motion("root", Vec3::X, false).unwrap();
+
+        let walk = Rc::new(AnimationClip::from_animation("walk", 0.0, 20.0, AnimationRepeat::Forever));
+        let run = Rc::new(AnimationClip::from_animation("run", 0.0, 10.0, AnimationRepeat::Forever).with_marker("step", 10.0));
+        let mut blend_space = BlendSpace::new_1d(vec![(1.0, walk), (3.0, run.clone())]).unwrap();
+
+        // root motion is extracted from the blend, and running to the end tick and past it loops
+        let motion = blend_space.update(&mut animator, vec2(3.0, 0.0), 0.6);
+        assert!((motion.translation.x - 0.6).abs() < 0.0001);
+        let motion = blend_space.update(&mut animator, vec2(3.0, 0.0), 0.4);
+        assert!((motion.translation.x - 0.4).abs() < 0.0001);
+        assert!(blend_space.phase.abs() < 0.0001);
+        let motion = blend_space.update(&mut animator, vec2(3.0, 0.0), 0.25);
+        assert!((motion.translation.x - 0.25).abs() < 0.0001);
+        assert!(animator.node_transform("root").unwrap().translation.x.abs() < 0.0001);
+
+        let events = animator.drain_events();
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, AnimationEvent::Marker { marker, .. } if marker.name == "step")));
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, AnimationEvent::Looped { clip, .. } if Rc::ptr_eq(clip, &run))));
+
+        // playing a clip leaves the blend space
+        let idle = Rc::new(AnimationClip::from_animation("walk", 0.0, 20.0, AnimationRepeat::Forever));
+        animator.play_clip(&idle).unwrap();
+        assert!(animator.blended_animations.is_empty());
+    }
+}
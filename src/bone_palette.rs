@@ -0,0 +1,98 @@
+use crate::dual_quat::DualQuat;
+use crate::gl;
+use crate::gl::{GLsizeiptr, GLuint, GLvoid};
+use crate::shader::Shader;
+use glam::{Mat4, Vec4};
+use std::cell::{Cell, RefCell};
+use std::mem;
+
+/// Texture unit the palette is bound to, above those used by mesh textures.
+pub const BONE_PALETTE_TEXTURE_UNIT: u32 = 15;
+
+/// Bone transforms in a texture buffer, uploaded in one call per frame and read in the vertex shader
+/// with the functions in examples/sample_animation/bone_palette.glsl. Unlike a uniform array it has no
+/// fixed bone limit. Four texels per bone matrix, or two per dual quaternion.
+#[derive(Debug, Default)]
+pub struct BonePalette {
+    buffer: Cell<GLuint>,
+    texture: Cell<GLuint>,
+    /// Size of the buffer's storage in texels, which the shader reads with textureSize to check bone ids
+    texel_count: Cell<usize>,
+    /// Scratch space for the texels of each upload
+    scratch_texels: RefCell<Vec<Vec4>>,
+}
+
+impl BonePalette {
+    pub fn new() -> Self {
+        BonePalette::default()
+    }
+
+    pub fn upload_matrices(&self, matrices: &[Mat4]) {
+        let mut texels = self.scratch_texels.borrow_mut();
+        texels.clear();
+        texels.extend(
+            matrices
+                .iter()
+                .flat_map(|matrix| [matrix.x_axis, matrix.y_axis, matrix.z_axis, matrix.w_axis]),
+        );
+        self.upload_texels(&texels);
+    }
+
+    pub fn upload_dual_quats(&self, dual_quats: &[DualQuat]) {
+        let mut texels = self.scratch_texels.borrow_mut();
+        texels.clear();
+        texels.extend(dual_quats.iter().flat_map(|dual_quat| dual_quat.to_vec4s()));
+        self.upload_texels(&texels);
+    }
+
+    /// Reallocates the buffer whenever the number of texels changes, so textureSize is always the live count.
+    fn upload_texels(&self, texels: &[Vec4]) {
+        // a buffer texture can't be empty, so no bones upload a single zero texel
+        let texels = if texels.is_empty() { &[Vec4::ZERO][..] } else { texels };
+        let size = mem::size_of_val(texels) as GLsizeiptr;
+
+        unsafe {
+            if self.buffer.get() == 0 {
+                let mut buffer = 0;
+                let mut texture = 0;
+                gl::GenBuffers(1, &mut buffer);
+                gl::GenTextures(1, &mut texture);
+                self.buffer.set(buffer);
+                self.texture.set(texture);
+            }
+
+            gl::BindBuffer(gl::TEXTURE_BUFFER, self.buffer.get());
+            if texels.len() != self.texel_count.get() {
+                gl::BufferData(gl::TEXTURE_BUFFER, size, texels.as_ptr() as *const GLvoid, gl::DYNAMIC_DRAW);
+                self.texel_count.set(texels.len());
+
+                gl::BindTexture(gl::TEXTURE_BUFFER, self.texture.get());
+                gl::TexBuffer(gl::TEXTURE_BUFFER, gl::RGBA32F, self.buffer.get());
+                gl::BindTexture(gl::TEXTURE_BUFFER, 0);
+            } else {
+                gl::BufferSubData(gl::TEXTURE_BUFFER, 0, size, texels.as_ptr() as *const GLvoid);
+            }
+            gl::BindBuffer(gl::TEXTURE_BUFFER, 0);
+        }
+    }
+
+    /// Binds the palette to BONE_PALETTE_TEXTURE_UNIT and points the shader's `bonePalette` sampler at it.
+    pub fn bind(&self, shader: &Shader) {
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0 + BONE_PALETTE_TEXTURE_UNIT);
+            gl::BindTexture(gl::TEXTURE_BUFFER, self.texture.get());
+        }
+        shader.set_int("bonePalette", BONE_PALETTE_TEXTURE_UNIT as i32);
+    }
+}
+
+impl Drop for BonePalette {
+    fn drop(&mut self) {
+        if self.buffer.get() != 0 {
+            unsafe {
+                gl::DeleteTextures(1, &self.texture.get());
+                gl::DeleteBuffers(1, &self.buffer.get());
+            }
+        }
+    }
+}
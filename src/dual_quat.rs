@@ -0,0 +1,118 @@
+use crate::transform::Transform;
+use glam::{Quat, Vec3, Vec4};
+
+/// A rigid transform as a unit dual quaternion. Blending these instead of matrices keeps twisting
+/// joints from collapsing. Scale can't be represented and is dropped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DualQuat {
+    /// The rotation
+    pub real: Quat,
+    /// Half the translation multiplied by the rotation
+    pub dual: Quat,
+}
+
+impl DualQuat {
+    pub const IDENTITY: Self = DualQuat {
+        real: Quat::IDENTITY,
+        dual: Quat::from_xyzw(0.0, 0.0, 0.0, 0.0),
+    };
+
+    pub fn from_rotation_translation(rotation: Quat, translation: Vec3) -> Self {
+        let translation = Quat::from_xyzw(translation.x, translation.y, translation.z, 0.0);
+        DualQuat {
+            real: rotation,
+            dual: (translation * rotation) * 0.5,
+        }
+    }
+
+    pub fn from_transform(transform: Transform) -> Self {
+        DualQuat::from_rotation_translation(transform.rotation, transform.translation)
+    }
+
+    pub fn translation(&self) -> Vec3 {
+        let translation = (self.dual * self.real.conjugate()) * 2.0;
+        Vec3::new(translation.x, translation.y, translation.z)
+    }
+
+    pub fn transform_point(&self, point: Vec3) -> Vec3 {
+        self.real * point + self.translation()
+    }
+
+    pub fn transform_vector(&self, vector: Vec3) -> Vec3 {
+        self.real * vector
+    }
+
+    /// The real and dual parts as xyzw vectors, the layout the skinning shader reads.
+    pub fn to_vec4s(&self) -> [Vec4; 2] {
+        [Vec4::from(self.real), Vec4::from(self.dual)]
+    }
+
+    /// Weighted blend, flipping each into the first's hemisphere before normalizing. None when the weights cancel out.
+    pub fn blend(dual_quats: &[(DualQuat, f32)]) -> Option<DualQuat> {
+        let (first, _) = dual_quats.first()?;
+        let reference = Vec4::from(first.real);
+
+        let mut real = Vec4::ZERO;
+        let mut dual = Vec4::ZERO;
+        for (dual_quat, weight) in dual_quats.iter() {
+            let weight = if Vec4::from(dual_quat.real).dot(reference) < 0.0 {
+                -weight
+            } else {
+                *weight
+            };
+            real += Vec4::from(dual_quat.real) * weight;
+            dual += Vec4::from(dual_quat.dual) * weight;
+        }
+
+        let length = real.length();
+        if length <= f32::EPSILON {
+            return None;
+        }
+
+        Some(DualQuat {
+            real: Quat::from_vec4(real / length),
+            dual: Quat::from_vec4(dual / length),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dual_quat::DualQuat;
+    use crate::transform::Transform;
+    use glam::{vec3, Quat};
+    use std::f32::consts::{FRAC_PI_2, PI};
+
+    #[test]
+    fn test_matches_transform() {
+        let transform = Transform::from_xyz(1.0, 2.0, 3.0).with_rotation(Quat::from_rotation_y(FRAC_PI_2));
+        let dual_quat = DualQuat::from_transform(transform);
+
+        assert!(dual_quat.translation().abs_diff_eq(vec3(1.0, 2.0, 3.0), 0.0001));
+        let point = vec3(1.0, 0.5, -2.0);
+        assert!(dual_quat
+            .transform_point(point)
+            .abs_diff_eq(transform.transform_point(point), 0.0001));
+    }
+
+    #[test]
+    fn test_blend_keeps_volume_when_twisting() {
+        // halfway between no twist and a half turn around x, a point off the axis stays at the same distance from it
+        let untwisted = DualQuat::from_transform(Transform::IDENTITY);
+        let twisted = DualQuat::from_transform(Transform::from_rotation(Quat::from_rotation_x(PI)));
+        let blended = DualQuat::blend(&[(untwisted, 0.5), (twisted, 0.5)]).unwrap();
+
+        let point = vec3(2.0, 0.0, 1.0);
+        let skinned = blended.transform_point(point);
+        assert!((vec3(0.0, skinned.y, skinned.z).length() - 1.0).abs() < 0.0001);
+        assert!((skinned.x - 2.0).abs() < 0.0001);
+
+        // a negated dual quaternion is the same transform
+        let negated = DualQuat {
+            real: -twisted.real,
+            dual: -twisted.dual,
+        };
+        let same = DualQuat::blend(&[(twisted, 0.5), (negated, 0.5)]).unwrap();
+        assert!(same.transform_point(point).abs_diff_eq(twisted.transform_point(point), 0.0001));
+    }
+}
@@ -8,6 +8,7 @@ pub enum Error {
     SceneError(String),
     MeshError(String),
     TextureError(String),
+    AnimationError(String),
     UnknownError(&'static str),
 }
 
@@ -0,0 +1,462 @@
+use crate::animator::Animator;
+use crate::error::Error;
+use crate::error::Error::AnimationError;
+use crate::skeleton::Skeleton;
+use crate::transform::Transform;
+use glam::{Quat, Vec3};
+use std::f32::consts::PI;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChainMethod {
+    /// Forward and backward reaching, moves the joints evenly
+    Fabrik,
+    /// Cyclic coordinate descent, bends the joints nearest the end the most
+    Ccd,
+}
+
+/// The solvers. Node indices are into the animator's skeleton and targets are in model space.
+#[derive(Debug, Clone)]
+pub enum IkSolver {
+    /// Analytic solver for a limb such as an arm or leg. The middle joint bends towards the pole.
+    TwoBone {
+        root: usize,
+        mid: usize,
+        end: usize,
+        target: Vec3,
+        pole: Vec3,
+    },
+    /// Iterative solver for a chain of joints from root to end effector.
+    Chain {
+        nodes: Vec<usize>,
+        target: Vec3,
+        method: ChainMethod,
+        /// Maximum rotation in radians of each joint away from its animated rotation
+        joint_limits: Vec<f32>,
+        iterations: u32,
+        tolerance: f32,
+    },
+    /// Turns a node so its local aim axis points at the target.
+    Aim { node: usize, target: Vec3, aim_axis: Vec3 },
+}
+
+/// An IK solver applied to the animated pose, blended in by weight.
+#[derive(Debug, Clone)]
+pub struct IkConstraint {
+    pub weight: f32,
+    pub solver: IkSolver,
+}
+
+impl IkConstraint {
+    pub fn two_bone(animator: &Animator, bone_names: [&str; 3], target: Vec3, pole: Vec3) -> Result<Self, Error> {
+        let [root, mid, end] = bone_names.map(|name| animator.skeleton.find_bone(name));
+        let (root, mid, end) = (root?, mid?, end?);
+
+        let is_limb = animator
+            .skeleton
+            .chain(root, end)
+            .is_some_and(|chain| chain.contains(&mid) && mid != root && mid != end);
+        if !is_limb {
+            return Err(AnimationError(format!("ik bones are not a limb: {:?}", bone_names)));
+        }
+
+        Ok(IkConstraint {
+            weight: 1.0,
+            solver: IkSolver::TwoBone {
+                root,
+                mid,
+                end,
+                target,
+                pole,
+            },
+        })
+    }
+
+    /// A chain from the root bone down to the end bone.
+    pub fn chain(animator: &Animator, root_bone: &str, end_bone: &str, target: Vec3, method: ChainMethod) -> Result<Self, Error> {
+        let nodes = animator
+            .skeleton
+            .chain(animator.skeleton.find_bone(root_bone)?, animator.skeleton.find_bone(end_bone)?)
+            .filter(|nodes| nodes.len() > 1)
+            .ok_or_else(|| AnimationError(format!("ik bone: {} is not below: {}", end_bone, root_bone)))?;
+
+        Ok(IkConstraint {
+            weight: 1.0,
+            solver: IkSolver::Chain {
+                joint_limits: vec![PI; nodes.len() - 1],
+                nodes,
+                target,
+                method,
+                iterations: 10,
+                tolerance: 0.001,
+            },
+        })
+    }
+
+    pub fn aim(animator: &Animator, bone_name: &str, aim_axis: Vec3, target: Vec3) -> Result<Self, Error> {
+        Ok(IkConstraint {
+            weight: 1.0,
+            solver: IkSolver::Aim {
+                node: animator.skeleton.find_bone(bone_name)?,
+                target,
+                aim_axis: aim_axis.normalize(),
+            },
+        })
+    }
+
+    pub fn with_weight(mut self, weight: f32) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    /// Limits for each joint of a chain, from the root. Ignored by the other solvers.
+    pub fn with_joint_limits(mut self, limits: Vec<f32>) -> Self {
+        if let IkSolver::Chain { joint_limits, .. } = &mut self.solver {
+            for (joint_limit, limit) in joint_limits.iter_mut().zip(limits) {
+                *joint_limit = limit;
+            }
+        }
+        self
+    }
+
+    pub fn set_target(&mut self, new_target: Vec3) {
+        match &mut self.solver {
+            IkSolver::TwoBone { target, .. } | IkSolver::Chain { target, .. } | IkSolver::Aim { target, .. } => *target = new_target,
+        }
+    }
+
+    /// The nodes whose rotation the solver changes, parents first.
+    fn joints(&self) -> Vec<usize> {
+        match &self.solver {
+            IkSolver::TwoBone { root, mid, .. } => vec![*root, *mid],
+            IkSolver::Chain { nodes, .. } => nodes[..nodes.len() - 1].to_vec(),
+            IkSolver::Aim { node, .. } => vec![*node],
+        }
+    }
+
+    /// Solves the constraint, updating the local transforms and the model space node transforms below the joints.
+    pub fn apply(
+        &self,
+        skeleton: &Skeleton,
+        root_parent: Transform,
+        local_transforms: &mut [Transform],
+        node_transforms: &mut [Transform],
+    ) {
+        if self.weight <= 0.0 {
+            return;
+        }
+
+        let joints = self.joints();
+        let animated: Vec<Quat> = joints.iter().map(|joint| local_transforms[*joint].rotation).collect();
+
+        let mut pose = PoseEditor {
+            skeleton,
+            root_parent,
+            local_transforms,
+            node_transforms,
+        };
+
+        match &self.solver {
+            IkSolver::TwoBone {
+                root,
+                mid,
+                end,
+                target,
+                pole,
+            } => pose.solve_two_bone(*root, *mid, *end, *target, *pole),
+            IkSolver::Chain {
+                nodes,
+                target,
+                method,
+                joint_limits,
+                iterations,
+                tolerance,
+            } => {
+                for _ in 0..*iterations {
+                    if pose.position(nodes[nodes.len() - 1]).distance(*target) <= *tolerance {
+                        break;
+                    }
+                    match method {
+                        ChainMethod::Fabrik => pose.fabrik_iteration(nodes, *target, &animated, joint_limits),
+                        ChainMethod::Ccd => pose.ccd_iteration(nodes, *target, &animated, joint_limits),
+                    }
+                }
+            }
+            IkSolver::Aim { node, target, aim_axis } => {
+                let from = pose.node_transforms[*node].rotation * *aim_axis;
+                let to = *target - pose.position(*node);
+                pose.rotate_joint(*node, rotation_between(from, to));
+            }
+        }
+
+        if self.weight < 1.0 {
+            for (joint, animated_rotation) in joints.iter().zip(animated) {
+                let solved = pose.local_transforms[*joint].rotation;
+                pose.local_transforms[*joint].rotation = animated_rotation.slerp(solved, self.weight);
+            }
+            pose.update_subtree(joints[0]);
+        }
+    }
+}
+
+/// The shortest rotation turning one direction into another.
+pub(crate) fn rotation_between(from: Vec3, to: Vec3) -> Quat {
+    match (from.try_normalize(), to.try_normalize()) {
+        (Some(from), Some(to)) => Quat::from_rotation_arc(from, to),
+        _ => Quat::IDENTITY,
+    }
+}
+
+/// Edits joint rotations while keeping the model space transforms below them up to date.
+pub(crate) struct PoseEditor<'a> {
+    pub(crate) skeleton: &'a Skeleton,
+    /// Model space transform of the root node's parent
+    pub(crate) root_parent: Transform,
+    pub(crate) local_transforms: &'a mut [Transform],
+    pub(crate) node_transforms: &'a mut [Transform],
+}
+
+impl PoseEditor<'_> {
+    pub(crate) fn position(&self, node_index: usize) -> Vec3 {
+        self.node_transforms[node_index].translation
+    }
+
+    pub(crate) fn parent_transform(&self, node_index: usize) -> Transform {
+        match self.skeleton.nodes[node_index].parent {
+            Some(parent_index) => self.node_transforms[parent_index],
+            None => self.root_parent,
+        }
+    }
+
+    /// Rotates the joint by a model space rotation.
+    pub(crate) fn rotate_joint(&mut self, node_index: usize, rotation: Quat) {
+        let parent_rotation = self.parent_transform(node_index).rotation;
+        let local_rotation = parent_rotation.inverse() * rotation * parent_rotation;
+
+        let local_transform = &mut self.local_transforms[node_index];
+        local_transform.rotation = (local_rotation * local_transform.rotation).normalize();
+        self.update_subtree(node_index);
+    }
+
+    /// Rotates the joint, keeping it within limit radians of its animated rotation.
+    fn rotate_joint_limited(&mut self, node_index: usize, rotation: Quat, animated: Quat, limit: f32) {
+        self.rotate_joint(node_index, rotation);
+        if limit >= PI {
+            return;
+        }
+
+        let offset = animated.inverse() * self.local_transforms[node_index].rotation;
+        let (axis, mut angle) = offset.to_axis_angle();
+        if angle > PI {
+            angle -= 2.0 * PI;
+        }
+
+        if angle.abs() > limit {
+            self.local_transforms[node_index].rotation = animated * Quat::from_axis_angle(axis, limit * angle.signum());
+            self.update_subtree(node_index);
+        }
+    }
+
+    pub(crate) fn update_subtree(&mut self, node_index: usize) {
+        for index in self.skeleton.subtree(node_index) {
+            self.node_transforms[index] = self.parent_transform(index).mul_transform(self.local_transforms[index]);
+        }
+    }
+
+    fn solve_two_bone(&mut self, root: usize, mid: usize, end: usize, target: Vec3, pole: Vec3) {
+        let root_position = self.position(root);
+        let upper_length = self.position(mid).distance(root_position);
+        let lower_length = self.position(end).distance(self.position(mid));
+
+        let to_target = target - root_position;
+        let direction = match to_target.try_normalize() {
+            Some(direction) => direction,
+            None => return,
+        };
+
+        // keep the limb slightly bent so the solution is stable when the target is out of reach
+        let min_distance = (upper_length - lower_length).abs() + 0.0001;
+        let max_distance = upper_length + lower_length - 0.0001;
+        let distance = to_target.length().clamp(min_distance, max_distance);
+
+        // the middle joint bends in the plane through the target and the pole
+        let to_pole = pole - root_position;
+        let bend = (to_pole - direction * to_pole.dot(direction))
+            .try_normalize()
+            .or_else(|| {
+                let to_mid = self.position(mid) - root_position;
+                (to_mid - direction * to_mid.dot(direction)).try_normalize()
+            })
+            .unwrap_or_else(|| direction.any_orthonormal_vector());
+
+        let cos_root = ((upper_length * upper_length + distance * distance - lower_length * lower_length)
+            / (2.0 * upper_length * distance))
+            .clamp(-1.0, 1.0);
+        let sin_root = (1.0 - cos_root * cos_root).sqrt();
+
+        let mid_target = root_position + (direction * cos_root + bend * sin_root) * upper_length;
+        let end_target = root_position + direction * distance;
+
+        self.rotate_joint(
+            root,
+            rotation_between(self.position(mid) - root_position, mid_target - root_position),
+        );
+
+        let mid_position = self.position(mid);
+        self.rotate_joint(mid, rotation_between(self.position(end) - mid_position, end_target - mid_position));
+    }
+
+    fn fabrik_iteration(&mut self, nodes: &[usize], target: Vec3, animated: &[Quat], joint_limits: &[f32]) {
+        let mut positions: Vec<Vec3> = nodes.iter().map(|node| self.position(*node)).collect();
+        let lengths: Vec<f32> = positions.windows(2).map(|pair| pair[0].distance(pair[1])).collect();
+        let base = positions[0];
+        let last = positions.len() - 1;
+
+        // backward from the target, then forward from the fixed base
+        positions[last] = target;
+        for i in (0..last).rev() {
+            let direction = (positions[i] - positions[i + 1]).normalize_or_zero();
+            positions[i] = positions[i + 1] + direction * lengths[i];
+        }
+
+        positions[0] = base;
+        for i in 0..last {
+            let direction = (positions[i + 1] - positions[i]).normalize_or_zero();
+            positions[i + 1] = positions[i] + direction * lengths[i];
+        }
+
+        // turn each joint to point at its solved child position
+        for i in 0..last {
+            let joint_position = self.position(nodes[i]);
+            let rotation = rotation_between(self.position(nodes[i + 1]) - joint_position, positions[i + 1] - joint_position);
+            self.rotate_joint_limited(nodes[i], rotation, animated[i], joint_limits[i]);
+        }
+    }
+
+    fn ccd_iteration(&mut self, nodes: &[usize], target: Vec3, animated: &[Quat], joint_limits: &[f32]) {
+        let end = nodes[nodes.len() - 1];
+
+        for i in (0..nodes.len() - 1).rev() {
+            let joint_position = self.position(nodes[i]);
+            let rotation = rotation_between(self.position(end) - joint_position, target - joint_position);
+            self.rotate_joint_limited(nodes[i], rotation, animated[i], joint_limits[i]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::animator::Animator;
+    use crate::hash_map::HashMap;
+    use crate::ik::{ChainMethod, IkConstraint, IkSolver};
+    use crate::model_animation::{BoneData, NodeData};
+    use crate::transform::Transform;
+    use glam::{vec3, Mat4, Quat, Vec3};
+    use std::f32::consts::FRAC_PI_4;
+    use std::rc::Rc;
+
+    /// A chain of bones one unit apart along y.
+    fn chain_animator(bone_names: &[&str]) -> Animator {
+        let mut node: Option<NodeData> = None;
+        for name in bone_names.iter().rev() {
+            node = Some(NodeData {
+                name: Rc::from(*name),
+                transform: Transform::from_xyz(0.0, 1.0, 0.0),
+                children: node.into_iter().collect(),
+                meshes: Rc::new(vec![]),
+            });
+        }
+
+        let bone_data_map: HashMap<String, BoneData> = bone_names
+            .iter()
+            .enumerate()
+            .map(|(index, name)| (name.to_string(), BoneData::new(name, index as i32, Mat4::IDENTITY)))
+            .collect();
+
+        Animator::from_parts(node.unwrap(), Mat4::IDENTITY, bone_data_map.into(), vec![])
+    }
+
+    fn position(animator: &Animator, name: &str) -> Vec3 {
+        animator.node_transform(name).unwrap().translation
+    }
+
+    #[test]
+    fn test_two_bone_reaches_target_towards_pole() {
+        let mut animator = chain_animator(&["shoulder", "elbow", "wrist"]);
+        let target = vec3(1.2, 1.8, 0.0);
+
+        let constraint = IkConstraint::two_bone(&animator, ["shoulder", "elbow", "wrist"], target, vec3(0.0, 1.0, 5.0)).unwrap();
+        animator.ik_constraints.push(constraint);
+        animator.update_animation(0.0);
+
+        assert!(position(&animator, "wrist").abs_diff_eq(target, 0.001));
+        assert!(position(&animator, "elbow").z > 0.5);
+
+        assert!(IkConstraint::two_bone(&animator, ["elbow", "shoulder", "wrist"], target, Vec3::Z).is_err());
+        assert!(IkConstraint::two_bone(&animator, ["shoulder", "elbow", "hand"], target, Vec3::Z).is_err());
+    }
+
+    #[test]
+    fn test_chains_reach_target() {
+        let bones = ["spine", "chest", "neck", "head", "top"];
+        let target = vec3(2.0, 3.0, 1.0);
+
+        for method in [ChainMethod::Fabrik, ChainMethod::Ccd] {
+            let mut animator = chain_animator(&bones);
+            let mut constraint = IkConstraint::chain(&animator, "spine", "top", target, method).unwrap();
+            if let IkSolver::Chain { iterations, .. } = &mut constraint.solver {
+                *iterations = 50;
+            }
+            animator.ik_constraints.push(constraint);
+            animator.update_animation(0.0);
+
+            assert!(
+                position(&animator, "top").distance(target) < 0.01,
+                "{:?}: {}",
+                method,
+                position(&animator, "top")
+            );
+            // the chain keeps its bone lengths
+            assert!((position(&animator, "neck").distance(position(&animator, "head")) - 1.0).abs() < 0.0001);
+        }
+
+        assert!(IkConstraint::chain(&chain_animator(&bones), "head", "spine", target, ChainMethod::Ccd).is_err());
+        assert!(IkConstraint::chain(&chain_animator(&bones), "spine", "spine", target, ChainMethod::Fabrik).is_err());
+    }
+
+    #[test]
+    fn test_joint_limits() {
+        let bones = ["spine", "chest", "neck", "head"];
+        let mut animator = chain_animator(&bones);
+
+        let constraint = IkConstraint::chain(&animator, "spine", "head", vec3(3.0, 1.0, 0.0), ChainMethod::Ccd)
+            .unwrap()
+            .with_joint_limits(vec![0.2, 0.2, 0.2]);
+        animator.ik_constraints.push(constraint);
+        animator.update_animation(0.0);
+
+        let local_pose = animator.local_pose.borrow();
+        for node_index in 0..3 {
+            let (_, angle) = local_pose[node_index].rotation.to_axis_angle();
+            assert!(angle <= 0.2001, "joint {} turned {}", node_index, angle);
+        }
+    }
+
+    #[test]
+    fn test_weighted_aim() {
+        let mut animator = chain_animator(&["neck", "head"]);
+        let target = vec3(5.0, 2.0, 0.0);
+
+        let constraint = IkConstraint::aim(&animator, "head", Vec3::Y, target).unwrap();
+        animator.ik_constraints.push(constraint);
+        animator.update_animation(0.0);
+
+        let head = animator.node_transform("head").unwrap();
+        assert!((head.rotation * Vec3::Y).abs_diff_eq(Vec3::X, 0.0001));
+
+        // half weight turns halfway from the animated pose
+        animator.ik_constraints[0].weight = 0.5;
+        animator.update_animation(0.0);
+        let head = animator.node_transform("head").unwrap();
+        assert!(head.rotation.abs_diff_eq(Quat::from_rotation_z(-FRAC_PI_4), 0.0001));
+    }
+}
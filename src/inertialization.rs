@@ -0,0 +1,144 @@
+use crate::pose::Pose;
+use crate::transform::Transform;
+use glam::{Quat, Vec3};
+
+/// A transition that only samples the new animation. The difference between the pose being left and the new
+/// animation, and how fast it was changing, are recorded at the switch and decayed to zero over the transition
+/// by a critically damped spring.
+#[derive(Debug, Clone)]
+pub struct Inertialization {
+    offsets: Vec<NodeOffset>,
+    /// The spring's damping, which leaves less than a ten thousandth of the offset by the end of the transition
+    damping: f32,
+    duration: f32,
+    elapsed: f32,
+}
+
+/// A node's offset from the new animation and its velocity. Rotations are scaled axes.
+#[derive(Debug, Clone, Copy)]
+struct NodeOffset {
+    translation: Vec3,
+    translation_velocity: Vec3,
+    rotation: Vec3,
+    rotation_velocity: Vec3,
+    scale: Vec3,
+    scale_velocity: Vec3,
+}
+
+impl Inertialization {
+    /// `source` is the last pose shown and `source_previous` the one delta_time before it. `target` is the new
+    /// animation at the switch and `target_next` the new animation delta_time later. Without a delta_time the
+    /// offsets start at rest.
+    pub fn new(source: &Pose, source_previous: &Pose, target: &Pose, target_next: &Pose, delta_time: f32, duration: f32) -> Self {
+        let velocity_scale = if delta_time > 0.0 { 1.0 / delta_time } else { 0.0 };
+        let offsets = (0..source.len())
+            .map(|node_index| {
+                let (translation, rotation, scale) = transform_offset(source[node_index], target[node_index]);
+                let (source_translation, source_rotation, source_scale) = transform_offset(source[node_index], source_previous[node_index]);
+                let (target_translation, target_rotation, target_scale) = transform_offset(target_next[node_index], target[node_index]);
+
+                NodeOffset {
+                    translation,
+                    translation_velocity: (source_translation - target_translation) * velocity_scale,
+                    rotation,
+                    rotation_velocity: (source_rotation - target_rotation) * velocity_scale,
+                    scale,
+                    scale_velocity: (source_scale - target_scale) * velocity_scale,
+                }
+            })
+            .collect();
+
+        Inertialization {
+            offsets,
+            damping: 12.0 / duration,
+            duration,
+            elapsed: 0.0,
+        }
+    }
+
+    pub fn update(&mut self, delta_time: f32) {
+        self.elapsed += delta_time;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.elapsed >= self.duration
+    }
+
+    /// Adds the decayed offsets to the new animation's pose.
+    pub fn apply(&self, pose: &mut Pose) {
+        if self.is_finished() {
+            return;
+        }
+
+        for (transform, offset) in pose.transforms.iter_mut().zip(self.offsets.iter()) {
+            let decay = |offset: Vec3, velocity: Vec3| critically_damped(offset, velocity, self.damping, self.elapsed);
+
+            transform.translation += decay(offset.translation, offset.translation_velocity);
+            transform.rotation =
+                (Quat::from_scaled_axis(decay(offset.rotation, offset.rotation_velocity)) * transform.rotation).normalize();
+            transform.scale += decay(offset.scale, offset.scale_velocity);
+        }
+    }
+}
+
+/// The difference from one transform to another, with the rotation as the shortest scaled axis.
+fn transform_offset(from: Transform, to: Transform) -> (Vec3, Vec3, Vec3) {
+    let rotation = from.rotation * to.rotation.inverse();
+    let rotation = if rotation.w < 0.0 { -rotation } else { rotation };
+    (from.translation - to.translation, rotation.to_scaled_axis(), from.scale - to.scale)
+}
+
+/// A critically damped spring's offset from its rest position after time.
+fn critically_damped(offset: Vec3, velocity: Vec3, damping: f32, time: f32) -> Vec3 {
+    (offset + (velocity + offset * damping) * time) * (-damping * time).exp()
+}
+
+/// The last two poses output by the animator and the time between them, for the velocity at a switch.
+#[derive(Debug, Clone)]
+pub(crate) struct PoseHistory {
+    pub(crate) last: Pose,
+    pub(crate) previous: Pose,
+    /// Zero until two poses have been recorded
+    pub(crate) delta_time: f32,
+    recorded: bool,
+}
+
+impl PoseHistory {
+    pub(crate) fn new(pose: &Pose) -> Self {
+        PoseHistory {
+            last: pose.clone(),
+            previous: pose.clone(),
+            delta_time: 0.0,
+            recorded: false,
+        }
+    }
+
+    pub(crate) fn record(&mut self, pose: &Pose, delta_time: f32) {
+        std::mem::swap(&mut self.last, &mut self.previous);
+        self.last.transforms.copy_from_slice(&pose.transforms);
+        self.delta_time = if self.recorded { delta_time } else { 0.0 };
+        self.recorded = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::inertialization::critically_damped;
+    use glam::{vec3, Vec3};
+
+    #[test]
+    fn test_offset_decays_smoothly() {
+        let offset = vec3(2.0, 0.0, -1.0);
+        let velocity = vec3(-4.0, 1.0, 0.0);
+        let damping = 12.0;
+
+        // starts at the offset, moving at the velocity
+        assert_eq!(critically_damped(offset, velocity, damping, 0.0), offset);
+        let step = 0.0001;
+        let start_velocity = (critically_damped(offset, velocity, damping, step) - offset) / step;
+        assert!(start_velocity.abs_diff_eq(velocity, 0.05), "{}", start_velocity);
+
+        // and has all but gone by the end
+        assert!(critically_damped(offset, velocity, damping, 1.0).abs_diff_eq(Vec3::ZERO, 0.001));
+    }
+}
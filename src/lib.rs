@@ -8,22 +8,39 @@ use std::os::raw;
 #[allow(clippy::all)]
 pub mod gl;
 
+pub mod animation_compression;
+pub mod animation_file;
+pub mod animation_layer;
+pub mod animation_state_machine;
 pub mod animator;
+pub mod blend_space;
+pub mod bone_palette;
 pub mod camera;
+pub mod dual_quat;
 pub mod error;
 pub mod hash_map;
+pub mod ik;
+pub mod inertialization;
 pub mod macros;
 pub mod math;
 pub mod mesh;
 pub mod model;
 pub mod model_animation;
 pub mod model_mesh;
+pub mod morph;
 pub mod node_animation;
+pub mod pose;
+pub mod retarget;
+pub mod root_motion;
 pub mod shader;
+pub mod skeleton;
+pub mod skinning;
+pub mod spring_bone;
 pub mod sprite_model;
 pub mod texture;
 pub mod transform;
 pub mod utils;
+pub mod vertex_animation;
 
 type ShaderId = u32;
 
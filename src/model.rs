@@ -1,13 +1,21 @@
-use crate::animator::{AnimationClip, Animator, WeightedAnimation};
+use crate::animation_file::AnimationFile;
+use crate::animator::{AnimationClip, AnimationEvent, AnimationRepeat, Animator, SkinningMethod, TransitionMode, WeightedAnimation};
+use crate::blend_space::BlendSpace;
+use crate::bone_palette::BonePalette;
 use crate::error::Error;
-use crate::error::Error::{MeshError, SceneError};
+use crate::error::Error::{AnimationError, MeshError, SceneError};
 use crate::hash_map::HashMap;
-use crate::model_animation::{BoneData, BoneName};
+use crate::model_animation::{BoneData, BoneName, ModelAnimation};
 use crate::model_mesh::{ModelMesh, ModelVertex};
+use crate::morph::MorphTarget;
+use crate::pose::Pose;
+use crate::retarget::{retarget_animation, BoneMap};
 use crate::shader::Shader;
+use crate::skinning::{skin_model_mesh, SkinnedVertices};
 use crate::texture::{Texture, TextureConfig, TextureFilter, TextureType, TextureWrap};
 use crate::transform::Transform;
 use crate::utils::get_exists_filename;
+use crate::vertex_animation::{VertexAnimationBake, VertexAnimationFormat};
 use glam::*;
 use log::debug;
 use russimp::node::Node;
@@ -24,56 +32,222 @@ pub struct Model {
     pub name: Rc<str>,
     pub meshes: Rc<Vec<ModelMesh>>,
     pub animator: RefCell<Animator>,
+    /// The bone transforms uploaded for the vertex shader
+    pub bone_palette: Rc<BonePalette>,
 }
 
 impl Model {
     pub fn render(&self, shader: &Shader) {
         let animator = self.animator.borrow();
-        let final_bones = animator.final_bone_matrices.borrow();
         let final_nodes = animator.final_node_matrices.borrow();
 
-        for (i, bone_transform) in final_bones.iter().enumerate() {
-            shader.set_mat4(format!("finalBonesMatrices[{}]", i).as_str(), bone_transform);
-        }
+        let morph_weights = animator.morph_weights.borrow();
+
+        set_shader_bones(shader, &animator, &self.bone_palette);
 
         for mesh in self.meshes.iter() {
             shader.set_mat4("nodeTransform", &final_nodes[mesh.id as usize]);
+            mesh.apply_morph_weights(mesh_morph_weights(&morph_weights, mesh));
             mesh.render(shader);
         }
     }
 
     pub fn set_shader_bones_for_mesh(&self, shader: &Shader, mesh: &ModelMesh) {
         let animator = self.animator.borrow();
-        let final_bones = animator.final_bone_matrices.borrow();
         let final_nodes = animator.final_node_matrices.borrow();
 
-        for (i, bone_transform) in final_bones.iter().enumerate() {
-            shader.set_mat4(format!("finalBonesMatrices[{}]", i).as_str(), bone_transform);
-        }
+        set_shader_bones(shader, &animator, &self.bone_palette);
         shader.set_mat4("nodeTransform", &final_nodes[mesh.id as usize]);
+        mesh.apply_morph_weights(mesh_morph_weights(&animator.morph_weights.borrow(), mesh));
+    }
+
+    /// The mesh's vertices deformed by the current pose on the CPU, for picking, bounds and tests.
+    pub fn skin_mesh(&self, mesh: &ModelMesh) -> SkinnedVertices {
+        skin_model_mesh(&self.animator.borrow(), mesh)
+    }
+
+    /// Selects linear blend or dual quaternion skinning. Dual quaternion skinning needs a vertex
+    /// shader reading dual quaternions from the palette, like examples/sample_animation/anim_model_dual_quat.vert.
+    pub fn set_skinning_method(&self, skinning_method: SkinningMethod) {
+        self.animator.borrow_mut().set_skinning_method(skinning_method);
+    }
+
+    pub fn animation_names(&self) -> Vec<Rc<str>> {
+        self.animator.borrow().animation_names()
     }
 
-    pub fn update_animation(&self, delta_time: f32) {
-        self.animator.borrow_mut().update_animation(delta_time);
+    pub fn animation_clip(&self, animation_name: &str, repeat: AnimationRepeat) -> Option<Rc<AnimationClip>> {
+        self.animator.borrow().animation_clip(animation_name, repeat)
     }
 
-    pub fn play_clip(&self, clip: &Rc<AnimationClip>) {
-        self.animator.borrow_mut().play_clip(clip);
+    /// Loads the animations in an animation-only file, such as a Mixamo export, onto this model's skeleton.
+    /// A single animation is named `name`, several are named `name/animation name`. Files saved with
+    /// AnimationFile, with a `ron` or `anim` extension, are loaded without assimp.
+    pub fn add_animation_file(&self, name: &str, path: &str) -> Result<(), Error> {
+        let model_animations = if AnimationFile::is_animation_file(path) {
+            AnimationFile::load(path)?.animations
+        } else {
+            let scene = ModelBuilder::load_russimp_scene(path)?;
+            ModelAnimation::from_scene(&scene)
+        };
+        self.add_named_animations(name, path, model_animations)
     }
 
-    pub fn play_clip_with_transition(&self, clip: &Rc<AnimationClip>, transition_duration: Duration) {
-        self.animator.borrow_mut().play_clip_with_transition(clip, transition_duration);
+    /// Loads the animations in a file authored on a different skeleton, retargeting them onto this model's
+    /// skeleton with the bone map. Named as in add_animation_file.
+    pub fn add_retargeted_animation_file(&self, name: &str, path: &str, bone_map: &BoneMap) -> Result<(), Error> {
+        let scene = ModelBuilder::load_russimp_scene(path)?;
+
+        let mut bone_data_map: HashMap<BoneName, BoneData> = HashMap::new();
+        for bone in scene.meshes.iter().flat_map(|mesh| mesh.bones.iter()) {
+            let bone_index = bone_data_map.len() as i32;
+            bone_data_map
+                .entry(bone.name.clone())
+                .or_insert_with(|| BoneData::new(&bone.name, bone_index, bone.offset_matrix));
+        }
+        let source = Animator::new(&scene, bone_data_map.into());
+
+        let model_animations = {
+            let animator = self.animator.borrow();
+            source
+                .model_animations
+                .iter()
+                .map(|animation| retarget_animation(&source.skeleton, animation, &animator.skeleton, bone_map, &animation.name))
+                .collect::<Result<Vec<ModelAnimation>, Error>>()?
+        };
+
+        self.add_named_animations(name, path, model_animations)
+    }
+
+    fn add_named_animations(&self, name: &str, path: &str, model_animations: Vec<ModelAnimation>) -> Result<(), Error> {
+        if model_animations.is_empty() {
+            return Err(AnimationError(format!("no animations found in: {}", path)));
+        }
+
+        let single = model_animations.len() == 1;
+        let model_animations = model_animations
+            .into_iter()
+            .map(|mut model_animation| {
+                model_animation.name = if single {
+                    Rc::from(name)
+                } else {
+                    Rc::from(format!("{}/{}", name, model_animation.name))
+                };
+                model_animation
+            })
+            .collect();
+
+        self.animator.borrow_mut().add_animations(model_animations)
+    }
+
+    /// Takes the animation events queued since the last call, e.g. to chain an attack after the current one finishes.
+    /// The animator keeps only the newest MAX_QUEUED_EVENTS, so events that are never drained are dropped.
+    pub fn drain_animation_events(&self) -> Vec<AnimationEvent> {
+        self.animator.borrow().drain_events()
+    }
+
+    /// Advances the model's animation. Returns the root motion since the last update, when enabled on the animator.
+    pub fn update_animation(&self, delta_time: f32) -> Transform {
+        self.animator.borrow_mut().update_animation(delta_time)
+    }
+
+    /// Fails if the clip's animation isn't in the model, e.g. a misspelt `name/animation name`.
+    pub fn play_clip(&self, clip: &Rc<AnimationClip>) -> Result<(), Error> {
+        self.animator.borrow_mut().play_clip(clip)
+    }
+
+    pub fn set_animation_speed(&self, speed: f32) {
+        self.animator.borrow_mut().set_speed(speed);
+    }
+
+    pub fn play_clip_with_transition(&self, clip: &Rc<AnimationClip>, transition_duration: Duration) -> Result<(), Error> {
+        self.animator.borrow_mut().play_clip_with_transition(clip, transition_duration)
+    }
+
+    /// Selects crossfaded or inertialized transitions for play_clip_with_transition.
+    pub fn set_transition_mode(&self, transition_mode: TransitionMode) {
+        self.animator.borrow_mut().transition_mode = transition_mode;
     }
 
     pub fn play_weight_animations(&mut self, weighted_animation: &[WeightedAnimation], frame_time: f32) {
         self.animator.borrow_mut().play_weight_animations(weighted_animation, frame_time);
     }
 
+    pub fn apply_pose(&self, pose: &Pose) -> Result<(), Error> {
+        self.animator.borrow().apply_pose(pose)
+    }
+
+    /// Holds the named morph target of the named meshes at the weight, replacing its animation until cleared.
+    pub fn set_morph_weight(&self, mesh_name: &str, target_name: &str, weight: f32) -> Result<(), Error> {
+        let mut animator = self.animator.borrow_mut();
+        for (mesh_index, target_index) in self.find_morph_targets(mesh_name, target_name)? {
+            animator.set_morph_weight(mesh_index, target_index, weight);
+        }
+        Ok(())
+    }
+
+    pub fn clear_morph_weight(&self, mesh_name: &str, target_name: &str) -> Result<(), Error> {
+        let mut animator = self.animator.borrow_mut();
+        for (mesh_index, target_index) in self.find_morph_targets(mesh_name, target_name)? {
+            animator.clear_morph_weight(mesh_index, target_index);
+        }
+        Ok(())
+    }
+
+    /// Mesh and target index of the target in each mesh with the name. Meshes split by material share a name.
+    fn find_morph_targets(&self, mesh_name: &str, target_name: &str) -> Result<Vec<(u32, usize)>, Error> {
+        let found: Vec<(u32, usize)> = self
+            .meshes
+            .iter()
+            .filter(|mesh| mesh.name == mesh_name)
+            .filter_map(|mesh| {
+                mesh.morph_targets
+                    .iter()
+                    .position(|morph_target| morph_target.name == target_name)
+                    .map(|target_index| (mesh.id as u32, target_index))
+            })
+            .collect();
+
+        if found.is_empty() {
+            return Err(MeshError(format!("morph target not found: {} in mesh: {}", target_name, mesh_name)));
+        }
+        Ok(found)
+    }
+
+    /// Samples the clips into a vertex animation texture, see VertexAnimationBake::bake.
+    pub fn bake_vertex_animation(
+        &self,
+        clips: &[Rc<AnimationClip>],
+        frame_rate: f32,
+        format: VertexAnimationFormat,
+    ) -> Result<VertexAnimationBake, Error> {
+        VertexAnimationBake::bake(&self.animator.borrow(), &self.meshes, clips, frame_rate, format)
+    }
+
+    /// Updates the animation with the blend space in place of update_animation, returning the root motion.
+    pub fn update_blend_space(&self, blend_space: &mut BlendSpace, parameter: Vec2, delta_time: f32) -> Transform {
+        blend_space.update(&mut self.animator.borrow_mut(), parameter, delta_time)
+    }
+
     // pub fn get_mesh(&self, name: &str) -> Option<&ModelMesh> {
     //     self.meshes.borrow().iter().find(|mesh| mesh.name == name)
     // }
 }
 
+/// The animator's morph weights for the mesh, empty when it has none.
+fn mesh_morph_weights<'a>(morph_weights: &'a [Vec<f32>], mesh: &ModelMesh) -> &'a [f32] {
+    morph_weights.get(mesh.id as usize).map_or(&[], Vec::as_slice)
+}
+
+/// Uploads the bone matrices, or the dual quaternions for dual quaternion skinning, to the palette and binds it.
+fn set_shader_bones(shader: &Shader, animator: &Animator, bone_palette: &BonePalette) {
+    match animator.skinning_method {
+        SkinningMethod::Linear => bone_palette.upload_matrices(&animator.final_bone_matrices.borrow()),
+        SkinningMethod::DualQuaternion => bone_palette.upload_dual_quats(&animator.final_bone_dual_quats.borrow()),
+    }
+    bone_palette.bind(shader);
+}
+
 #[derive(Debug)]
 pub struct AddedTextures {
     mesh_name: String,
@@ -81,6 +255,12 @@ pub struct AddedTextures {
     texture_filename: String,
 }
 
+#[derive(Debug)]
+pub struct AddedAnimation {
+    name: String,
+    animation_filename: String,
+}
+
 #[derive(Debug)]
 pub struct ModelBuilder {
     pub name: String,
@@ -95,6 +275,7 @@ pub struct ModelBuilder {
     pub load_textures: bool,
     pub textures_cache: RefCell<Vec<Rc<Texture>>>,
     added_textures: Vec<AddedTextures>,
+    added_animations: Vec<AddedAnimation>,
     pub mesh_count: i32,
 }
 
@@ -115,6 +296,7 @@ impl ModelBuilder {
             flip_h: false,
             load_textures: true,
             added_textures: vec![],
+            added_animations: vec![],
             mesh_count: 0,
         }
     }
@@ -144,6 +326,16 @@ impl ModelBuilder {
         self
     }
 
+    /// Adds the animations from another file, matched to this model's skeleton by bone name.
+    pub fn add_animation_file(mut self, name: impl Into<String>, animation_filename: impl Into<String>) -> Self {
+        let added_animation = AddedAnimation {
+            name: name.into(),
+            animation_filename: animation_filename.into(),
+        };
+        self.added_animations.push(added_animation);
+        self
+    }
+
     pub fn build(mut self) -> Result<Model, Error> {
         let scene = ModelBuilder::load_russimp_scene(self.filepath.as_str())?;
 
@@ -157,8 +349,13 @@ impl ModelBuilder {
             name: Rc::from(self.name),
             meshes: Rc::from(self.meshes),
             animator: animator.into(),
+            bone_palette: Rc::new(BonePalette::new()),
         };
 
+        for added_animation in &self.added_animations {
+            model.add_animation_file(&added_animation.name, &added_animation.animation_filename)?;
+        }
+
         Ok(model)
     }
 
@@ -248,7 +445,13 @@ impl ModelBuilder {
 
         self.extract_bone_weights_for_vertices(&mut vertices, r_mesh);
 
-        let mesh = ModelMesh::new(self.mesh_count, &r_mesh.name, vertices, indices, textures);
+        let morph_targets: Vec<MorphTarget> = r_mesh
+            .anim_meshes
+            .iter()
+            .map(|anim_mesh| MorphTarget::from_anim_mesh(anim_mesh, r_mesh))
+            .collect();
+
+        let mesh = ModelMesh::new(self.mesh_count, &r_mesh.name, vertices, indices, textures).with_morph_targets(morph_targets);
         self.mesh_count += 1;
         Ok(mesh)
     }
@@ -1,13 +1,15 @@
-use crate::node_animation::NodeAnimation;
+use crate::morph::MorphAnimation;
+use crate::node_animation::{KeyframeBoundary, NodeAnimation};
 use crate::transform::Transform;
 use glam::Mat4;
 use log::debug;
 use russimp::animation::Animation;
 use russimp::scene::Scene;
+use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
 use std::rc::Rc;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NodeData {
     pub name: Rc<str>,
     pub transform: Transform,
@@ -15,9 +17,19 @@ pub struct NodeData {
     pub meshes: Rc<Vec<u32>>,
 }
 
+impl NodeData {
+    /// Finds this node or a descendant by name.
+    pub fn find_node(&self, name: &str) -> Option<&NodeData> {
+        if self.name.as_ref() == name {
+            return Some(self);
+        }
+        self.children.iter().find_map(|child| child.find_node(name))
+    }
+}
+
 pub type BoneName = String;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BoneData {
     pub name: Rc<str>,
     pub bone_index: i32,
@@ -34,48 +46,74 @@ impl BoneData {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModelAnimation {
+    pub name: Rc<str>,
     pub duration: f32,
     pub ticks_per_second: f32,
     pub node_animations: RefCell<Vec<NodeAnimation>>,
+    /// Morph target weight channels, by node name
+    pub morph_animations: Vec<MorphAnimation>,
 }
 
 impl Default for ModelAnimation {
     fn default() -> Self {
         ModelAnimation {
+            name: Rc::from(""),
             duration: 0.0,
             ticks_per_second: 0.0,
             node_animations: RefCell::new(vec![]),
+            morph_animations: vec![],
         }
     }
 }
 
 impl ModelAnimation {
-    pub fn new(scene: &Scene) -> Self {
-        if scene.animations.is_empty() {
-            return ModelAnimation::default();
-        }
-
-        let duration = scene.animations[0].duration as f32;
-        let ticks_per_second = scene.animations[0].ticks_per_second as f32;
+    /// Reads every animation in the scene, in scene order. Unnamed animations are named by their index.
+    pub fn from_scene(scene: &Scene) -> Vec<ModelAnimation> {
+        scene
+            .animations
+            .iter()
+            .enumerate()
+            .map(|(index, animation)| {
+                let name = if animation.name.is_empty() {
+                    format!("animation_{}", index)
+                } else {
+                    animation.name.clone()
+                };
+                ModelAnimation::new(&name, animation)
+            })
+            .collect()
+    }
 
-        debug!("animation - duration: {}   ticks_per_second: {}", &duration, &ticks_per_second);
+    pub fn new(name: &str, animation: &Animation) -> Self {
+        let duration = animation.duration as f32;
+        let ticks_per_second = animation.ticks_per_second as f32;
 
-        // debug!("root_node: {:#?}", &root_node);
-        // debug!("bone_data_map: {:#?}", &model.bone_data_map.borrow());
+        debug!(
+            "animation: {}   duration: {}   ticks_per_second: {}",
+            name, &duration, &ticks_per_second
+        );
 
         let mut model_animation = ModelAnimation {
-            // model: model.clone(),
+            name: Rc::from(name),
             duration,
             ticks_per_second,
             node_animations: vec![].into(),
+            morph_animations: animation.morph_mesh_channels.iter().map(MorphAnimation::new).collect(),
         };
 
-        model_animation.read_channel_node_animations(&scene.animations[0]);
+        model_animation.read_channel_node_animations(animation);
         model_animation
     }
 
+    /// Sets how every channel is sampled outside of its keyframe range.
+    pub fn set_keyframe_boundary(&self, boundary: KeyframeBoundary) {
+        for node_animation in self.node_animations.borrow_mut().iter_mut() {
+            node_animation.boundary = boundary;
+        }
+    }
+
     /// converts channel vec of Russimp::NodeAnims into vec of NodeAnimation
     fn read_channel_node_animations(&mut self, animation: &Animation) {
         for channel in &animation.channels {
@@ -1,10 +1,13 @@
 use crate::gl;
 use crate::gl::{GLsizei, GLsizeiptr, GLvoid};
+use crate::morph::{morph_vertices, write_morphed_vertices, MorphTarget};
 use crate::shader::Shader;
 use crate::texture::Texture;
 use glam::u32;
 use glam::*;
 use log::debug;
+use std::borrow::Cow;
+use std::cell::RefCell;
 use std::mem;
 use std::rc::Rc;
 
@@ -73,6 +76,11 @@ pub struct ModelMesh {
     pub vertices: Vec<ModelVertex>,
     pub indices: Vec<u32>,
     pub textures: Vec<Rc<Texture>>,
+    pub morph_targets: Vec<MorphTarget>,
+    /// Morph target weights the vertex buffer was last uploaded with
+    uploaded_morph_weights: RefCell<Vec<f32>>,
+    /// Scratch space for the morphed vertices uploaded to the vertex buffer
+    scratch_vertices: RefCell<Vec<ModelVertex>>,
     pub vao: u32,
     pub vbo: u32,
     pub ebo: u32,
@@ -86,6 +94,9 @@ impl ModelMesh {
             vertices,
             indices,
             textures,
+            morph_targets: vec![],
+            uploaded_morph_weights: vec![].into(),
+            scratch_vertices: vec![].into(),
             vao: 0,
             vbo: 0,
             ebo: 0,
@@ -94,16 +105,70 @@ impl ModelMesh {
         mesh
     }
 
-    pub fn render(&self, shader: &Shader) {
-        unsafe {
-            for (texture_unit, texture) in self.textures.iter().enumerate() {
-                gl::ActiveTexture(gl::TEXTURE0 + texture_unit as u32);
-                gl::BindTexture(gl::TEXTURE_2D, texture.id);
+    pub fn with_morph_targets(mut self, morph_targets: Vec<MorphTarget>) -> Self {
+        *self.uploaded_morph_weights.get_mut() = vec![0.0; morph_targets.len()];
+        self.morph_targets = morph_targets;
+        self
+    }
 
-                let uniform_name = texture.texture_type.to_string();
-                shader.set_int(&uniform_name, texture_unit as i32);
-            }
+    /// The weight of each morph target, using the default weight for targets without one in weights.
+    pub fn morph_target_weights(&self, weights: &[f32]) -> Vec<f32> {
+        (0..self.morph_targets.len())
+            .map(|index| self.morph_target_weight(weights, index))
+            .collect()
+    }
+
+    fn morph_target_weight(&self, weights: &[f32], index: usize) -> f32 {
+        weights.get(index).copied().unwrap_or(self.morph_targets[index].default_weight)
+    }
+
+    /// The vertices deformed by the morph targets at the weights, or the vertices as they are without morph targets.
+    pub fn morphed_vertices(&self, weights: &[f32]) -> Cow<'_, [ModelVertex]> {
+        if self.morph_targets.is_empty() {
+            return Cow::Borrowed(&self.vertices);
+        }
+        Cow::Owned(morph_vertices(
+            &self.vertices,
+            &self.morph_targets,
+            &self.morph_target_weights(weights),
+        ))
+    }
+
+    /// Morphs the vertices on the CPU and uploads them to the vertex buffer, when the weights have changed.
+    pub fn apply_morph_weights(&self, weights: &[f32]) {
+        if self.morph_targets.is_empty() {
+            return;
+        }
+
+        let mut uploaded_weights = self.uploaded_morph_weights.borrow_mut();
+        let unchanged = uploaded_weights
+            .iter()
+            .enumerate()
+            .all(|(index, uploaded_weight)| self.morph_target_weight(weights, index) == *uploaded_weight);
+        if unchanged {
+            return;
+        }
+        for (index, uploaded_weight) in uploaded_weights.iter_mut().enumerate() {
+            *uploaded_weight = self.morph_target_weight(weights, index);
+        }
+
+        let mut vertices = self.scratch_vertices.borrow_mut();
+        write_morphed_vertices(&self.vertices, &self.morph_targets, &uploaded_weights, &mut vertices);
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+            gl::BufferSubData(
+                gl::ARRAY_BUFFER,
+                0,
+                (vertices.len() * mem::size_of::<ModelVertex>()) as GLsizeiptr,
+                vertices.as_ptr() as *const GLvoid,
+            );
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+    }
 
+    pub fn render(&self, shader: &Shader) {
+        self.bind_textures(shader);
+        unsafe {
             gl::BindVertexArray(self.vao);
             gl::DrawElements(
                 gl::TRIANGLES,
@@ -115,6 +180,34 @@ impl ModelMesh {
         }
     }
 
+    /// Draws instance_count instances, for a shader reading per instance attributes set up on the vertex array.
+    pub fn render_instanced(&self, shader: &Shader, instance_count: usize) {
+        self.bind_textures(shader);
+        unsafe {
+            gl::BindVertexArray(self.vao);
+            gl::DrawElementsInstanced(
+                gl::TRIANGLES,
+                self.indices.len() as i32,
+                gl::UNSIGNED_INT,
+                std::ptr::null::<GLvoid>(),
+                instance_count as i32,
+            );
+            gl::BindVertexArray(0);
+        }
+    }
+
+    fn bind_textures(&self, shader: &Shader) {
+        unsafe {
+            for (texture_unit, texture) in self.textures.iter().enumerate() {
+                gl::ActiveTexture(gl::TEXTURE0 + texture_unit as u32);
+                gl::BindTexture(gl::TEXTURE_2D, texture.id);
+
+                let uniform_name = texture.texture_type.to_string();
+                shader.set_int(&uniform_name, texture_unit as i32);
+            }
+        }
+    }
+
     pub fn render_no_textures(&self) {
         unsafe {
             gl::BindVertexArray(self.vao);
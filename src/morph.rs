@@ -0,0 +1,231 @@
+use crate::model_mesh::ModelVertex;
+use glam::Vec3;
+use log::debug;
+use russimp::animation::MeshMorphAnim;
+use russimp::mesh::{AnimMesh, Mesh};
+use serde::{Deserialize, Serialize};
+use std::rc::Rc;
+
+/// A blend shape, as offsets from the base mesh's vertices. Attributes the shape doesn't change have no deltas.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MorphTarget {
+    pub name: String,
+    pub position_deltas: Vec<Vec3>,
+    pub normal_deltas: Vec<Vec3>,
+    pub tangent_deltas: Vec<Vec3>,
+    /// Weight used when no animation or manual weight drives the target
+    pub default_weight: f32,
+}
+
+impl MorphTarget {
+    /// Converts an assimp anim mesh, which holds the morphed attributes rather than offsets.
+    pub fn from_anim_mesh(anim_mesh: &AnimMesh, mesh: &Mesh) -> Self {
+        MorphTarget {
+            name: anim_mesh.name.clone(),
+            position_deltas: deltas(&anim_mesh.vertices, &mesh.vertices),
+            normal_deltas: deltas(&anim_mesh.normals, &mesh.normals),
+            tangent_deltas: deltas(&anim_mesh.tangents, &mesh.tangents),
+            default_weight: anim_mesh.weight,
+        }
+    }
+}
+
+fn deltas(morphed: &[Vec3], base: &[Vec3]) -> Vec<Vec3> {
+    if morphed.len() != base.len() {
+        return vec![];
+    }
+    morphed.iter().zip(base.iter()).map(|(morphed, base)| *morphed - *base).collect()
+}
+
+/// The weight of each of a mesh's morph targets at a tick. Targets without a weight in the key are at zero.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyMorphWeights {
+    pub weights: Vec<f32>,
+    pub time_stamp: f32,
+}
+
+impl KeyMorphWeights {
+    pub fn new(time_stamp: f32, weights: Vec<f32>) -> Self {
+        KeyMorphWeights { weights, time_stamp }
+    }
+}
+
+/// A channel animating the morph target weights of the meshes of the named node.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MorphAnimation {
+    pub name: Rc<str>,
+    pub keys: Vec<KeyMorphWeights>,
+}
+
+impl MorphAnimation {
+    pub fn new(channel: &MeshMorphAnim) -> Self {
+        let keys = channel
+            .keys
+            .iter()
+            .map(|key| {
+                let target_count = key.values.iter().map(|value| *value as usize + 1).max().unwrap_or(0);
+                let mut weights = vec![0.0; target_count];
+                for (value, weight) in key.values.iter().zip(key.weights.iter()) {
+                    weights[*value as usize] = *weight as f32;
+                }
+                KeyMorphWeights::new(key.time as f32, weights)
+            })
+            .collect();
+
+        debug!("morph channel: {}", channel.name);
+
+        MorphAnimation {
+            name: Rc::from(channel.name.as_str()),
+            keys,
+        }
+    }
+
+    /// Number of morph targets the channel has weights for.
+    pub fn target_count(&self) -> usize {
+        self.keys.iter().map(|key| key.weights.len()).max().unwrap_or(0)
+    }
+
+    /// Adds the weights at the tick, scaled by blend_weight, to the target weights.
+    /// Ticks outside the keys hold the first or last key.
+    pub fn accumulate_weights(&self, animation_time: f32, blend_weight: f32, weights: &mut [f32]) {
+        let next_index = self.keys.partition_point(|key| key.time_stamp <= animation_time);
+
+        let (from, to, factor) = match (next_index.checked_sub(1), self.keys.get(next_index)) {
+            (Some(index), Some(next)) => {
+                let key = &self.keys[index];
+                let factor = (animation_time - key.time_stamp) / (next.time_stamp - key.time_stamp);
+                (key, next, factor)
+            }
+            (Some(index), None) => (&self.keys[index], &self.keys[index], 0.0),
+            (None, Some(next)) => (next, next, 0.0),
+            (None, None) => return,
+        };
+
+        for (target_index, weight) in weights.iter_mut().enumerate() {
+            let from_weight = from.weights.get(target_index).copied().unwrap_or(0.0);
+            let to_weight = to.weights.get(target_index).copied().unwrap_or(0.0);
+            *weight += (from_weight + (to_weight - from_weight) * factor) * blend_weight;
+        }
+    }
+}
+
+/// The vertices with the weighted morph target deltas added. Normals and tangents are renormalized.
+pub fn morph_vertices(vertices: &[ModelVertex], morph_targets: &[MorphTarget], weights: &[f32]) -> Vec<ModelVertex> {
+    let mut morphed = vec![];
+    write_morphed_vertices(vertices, morph_targets, weights, &mut morphed);
+    morphed
+}
+
+/// Replaces morphed with the morphed vertices. All the weighted deltas are summed before the normals and tangents
+/// are renormalized, so the result doesn't depend on the order of the targets.
+pub fn write_morphed_vertices(vertices: &[ModelVertex], morph_targets: &[MorphTarget], weights: &[f32], morphed: &mut Vec<ModelVertex>) {
+    morphed.clear();
+    morphed.extend_from_slice(vertices);
+
+    let mut renormalize = false;
+    for (morph_target, weight) in morph_targets.iter().zip(weights.iter()) {
+        if *weight == 0.0 {
+            continue;
+        }
+
+        // fields are copied out rather than added to in place as the vertex struct is packed
+        for (vertex, delta) in morphed.iter_mut().zip(morph_target.position_deltas.iter()) {
+            let position = vertex.position;
+            vertex.position = position + *delta * *weight;
+        }
+        for (vertex, delta) in morphed.iter_mut().zip(morph_target.normal_deltas.iter()) {
+            let normal = vertex.normal;
+            vertex.normal = normal + *delta * *weight;
+        }
+        for (vertex, delta) in morphed.iter_mut().zip(morph_target.tangent_deltas.iter()) {
+            let tangent = vertex.tangent;
+            vertex.tangent = tangent + *delta * *weight;
+        }
+        renormalize |= !morph_target.normal_deltas.is_empty() || !morph_target.tangent_deltas.is_empty();
+    }
+
+    if renormalize {
+        for vertex in morphed.iter_mut() {
+            vertex.normal = vertex.normal.normalize_or_zero();
+            vertex.tangent = vertex.tangent.normalize_or_zero();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model_mesh::ModelVertex;
+    use crate::morph::{morph_vertices, KeyMorphWeights, MorphAnimation, MorphTarget};
+    use glam::{vec3, Vec3};
+    use russimp::animation::{MeshMorphAnim, MeshMorphKey};
+    use std::rc::Rc;
+
+    #[test]
+    fn test_morph_channel_weights() {
+        let channel = MeshMorphAnim {
+            name: "face".to_string(),
+            keys: vec![
+                MeshMorphKey {
+                    time: 0.0,
+                    values: vec![1],
+                    weights: vec![1.0],
+                },
+                MeshMorphKey {
+                    time: 10.0,
+                    values: vec![0, 2],
+                    weights: vec![0.5, 1.0],
+                },
+            ],
+        };
+        let morph_animation = MorphAnimation::new(&channel);
+        assert_eq!(morph_animation.target_count(), 3);
+
+        let mut weights = vec![0.0; 3];
+        morph_animation.accumulate_weights(5.0, 1.0, &mut weights);
+        assert_eq!(weights, vec![0.25, 0.5, 0.5]);
+
+        // held past the last key, and scaled by the blend weight
+        let mut weights = vec![0.0; 3];
+        morph_animation.accumulate_weights(20.0, 0.5, &mut weights);
+        assert_eq!(weights, vec![0.25, 0.0, 0.5]);
+
+        let empty = MorphAnimation {
+            name: Rc::from("empty"),
+            keys: vec![KeyMorphWeights::new(0.0, vec![])],
+        };
+        let mut weights = vec![0.0; 2];
+        empty.accumulate_weights(1.0, 1.0, &mut weights);
+        assert_eq!(weights, vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_morph_vertices() {
+        let mut vertex = ModelVertex::new();
+        vertex.position = vec3(1.0, 0.0, 0.0);
+        vertex.normal = Vec3::Y;
+
+        let smile = MorphTarget {
+            name: "smile".to_string(),
+            position_deltas: vec![vec3(0.0, 2.0, 0.0)],
+            normal_deltas: vec![vec3(1.0, -1.0, 0.0)],
+            ..Default::default()
+        };
+        let frown = MorphTarget {
+            name: "frown".to_string(),
+            position_deltas: vec![vec3(0.0, 0.0, 4.0)],
+            normal_deltas: vec![vec3(0.0, 0.0, 2.0)],
+            ..Default::default()
+        };
+
+        let morphed = morph_vertices(&[vertex], &[smile.clone(), frown.clone()], &[0.5, 0.25]);
+        let position = morphed[0].position;
+        let normal = morphed[0].normal;
+        assert_eq!(position, vec3(1.0, 1.0, 1.0));
+        assert!(normal.abs_diff_eq(Vec3::ONE.normalize(), 0.0001), "{}", normal);
+
+        // normalized once after all the deltas, so the order of the targets doesn't matter
+        let reordered = morph_vertices(&[vertex], &[frown, smile], &[0.25, 0.5]);
+        let reordered_normal = reordered[0].normal;
+        assert!(reordered_normal.abs_diff_eq(normal, 0.0001));
+    }
+}
@@ -1,36 +1,163 @@
+use crate::error::Error;
+use crate::error::Error::AnimationError;
 use crate::transform::Transform;
-use glam::{Quat, Vec3};
+use glam::{Quat, Vec3, Vec4};
 use log::debug;
 use russimp::animation::{NodeAnim, QuatKey, VectorKey};
+use serde::{Deserialize, Serialize};
+use std::ops::{Add, Mul};
 use std::rc::Rc;
 
-#[derive(Debug, Clone)]
+/// How the values between a keyframe and the next one are calculated. Cubic spline tangents
+/// are in units per tick, as in glTF, and are scaled by the time between keys when sampled.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum Interpolation<T> {
+    /// Hold the keyframe value until the next keyframe.
+    Step,
+    /// Lerp, or slerp for rotations, towards the next keyframe.
+    #[default]
+    Linear,
+    /// Hermite spline using this key's out tangent and the next key's in tangent.
+    CubicSpline { in_tangent: T, out_tangent: T },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct KeyPosition {
     pub position: Vec3,
     pub time_stamp: f32,
+    pub interpolation: Interpolation<Vec3>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct KeyRotation {
     pub orientation: Quat,
     pub time_stamp: f32,
+    pub interpolation: Interpolation<Quat>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct KeyScale {
     pub scale: Vec3,
     pub time_stamp: f32,
+    pub interpolation: Interpolation<Vec3>,
+}
+
+impl KeyPosition {
+    pub fn new(time_stamp: f32, position: Vec3) -> Self {
+        KeyPosition {
+            position,
+            time_stamp,
+            interpolation: Interpolation::Linear,
+        }
+    }
+}
+
+impl KeyRotation {
+    pub fn new(time_stamp: f32, orientation: Quat) -> Self {
+        KeyRotation {
+            orientation,
+            time_stamp,
+            interpolation: Interpolation::Linear,
+        }
+    }
+}
+
+/// A linearly interpolated rotation key stored as a smallest three quaternion.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuantizedKeyRotation {
+    pub orientation: SmallestThree,
+    pub time_stamp: f32,
+}
+
+/// A unit quaternion packed into 48 bits. The largest component is dropped, as it can be
+/// recalculated from the other three, which are each stored in 15 bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SmallestThree(pub [u16; 3]);
+
+const SMALLEST_THREE_MAX: f32 = std::f32::consts::FRAC_1_SQRT_2;
+const SMALLEST_THREE_STEPS: f32 = 32767.0;
+
+impl SmallestThree {
+    pub fn from_quat(rotation: Quat) -> Self {
+        let mut components = rotation.normalize().to_array();
+
+        let mut largest = 0;
+        for index in 1..4 {
+            if components[index].abs() > components[largest].abs() {
+                largest = index;
+            }
+        }
+
+        // q and -q are the same rotation, so the dropped component is always positive
+        if components[largest] < 0.0 {
+            components.iter_mut().for_each(|component| *component = -*component);
+        }
+
+        let mut packed = [0u16; 3];
+        for (word, index) in (0..4).filter(|index| *index != largest).enumerate() {
+            let normalized = (components[index] / SMALLEST_THREE_MAX).clamp(-1.0, 1.0) * 0.5 + 0.5;
+            packed[word] = (normalized * SMALLEST_THREE_STEPS).round() as u16;
+        }
+        packed[0] |= ((largest as u16) >> 1) << 15;
+        packed[1] |= ((largest as u16) & 1) << 15;
+
+        SmallestThree(packed)
+    }
+
+    pub fn to_quat(self) -> Quat {
+        let [a, b, c] = self.0;
+        let largest = (((a >> 15) << 1) | (b >> 15)) as usize;
+
+        let mut components = [0.0; 4];
+        let mut sum_of_squares = 0.0;
+        for (word, index) in (0..4).filter(|index| *index != largest).enumerate() {
+            let normalized = ([a, b, c][word] & 0x7fff) as f32 / SMALLEST_THREE_STEPS;
+            components[index] = (normalized * 2.0 - 1.0) * SMALLEST_THREE_MAX;
+            sum_of_squares += components[index] * components[index];
+        }
+        components[largest] = (1.0 - sum_of_squares).max(0.0).sqrt();
+
+        Quat::from_array(components).normalize()
+    }
 }
 
-#[derive(Debug, Clone)]
+impl KeyScale {
+    pub fn new(time_stamp: f32, scale: Vec3) -> Self {
+        KeyScale {
+            scale,
+            time_stamp,
+            interpolation: Interpolation::Linear,
+        }
+    }
+}
+
+/// How a channel is sampled at times before its first or after its last keyframe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum KeyframeBoundary {
+    /// Hold the first or last keyframe.
+    #[default]
+    Clamp,
+    /// Wrap the time around into the keyframe range.
+    Wrap,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NodeAnimation {
     pub name: Rc<str>,
     pub positions: Vec<KeyPosition>,
     pub rotations: Vec<KeyRotation>,
+    /// Rotation keys of compressed channels, used when there are no rotations
+    #[serde(default)]
+    pub quantized_rotations: Vec<QuantizedKeyRotation>,
     pub scales: Vec<KeyScale>,
+    #[serde(default)]
+    pub boundary: KeyframeBoundary,
 }
 
 impl NodeAnimation {
+    /// Converts an assimp channel. russimp's keys only carry a time and a value, and assimp keeps just the values
+    /// of glTF CUBICSPLINE samplers, dropping their tangents, so every imported key is Linear. Channels authored
+    /// otherwise are set with set_step_interpolation or set_cubic_spline_tangents after loading.
     pub fn new(name: &str, channel: &NodeAnim) -> Self {
         let positions: Vec<KeyPosition> = channel.position_keys.iter().map(|key| key.into()).collect();
         let rotations: Vec<KeyRotation> = channel.rotation_keys.iter().map(|key| key.into()).collect();
@@ -43,10 +170,62 @@ impl NodeAnimation {
             name,
             positions,
             rotations,
+            quantized_rotations: vec![],
             scales,
+            boundary: KeyframeBoundary::Clamp,
         }
     }
 
+    /// Holds each key's value until the next key, as glTF STEP samplers do.
+    pub fn set_step_interpolation(&mut self) {
+        self.positions.iter_mut().for_each(|key| key.interpolation = Interpolation::Step);
+        self.rotations.iter_mut().for_each(|key| key.interpolation = Interpolation::Step);
+        self.scales.iter_mut().for_each(|key| key.interpolation = Interpolation::Step);
+    }
+
+    /// Makes the channel a cubic spline with each key's (in, out) tangents, as a glTF CUBICSPLINE sampler stores
+    /// them. Properties given no tangents are left as they are. Fails, changing nothing, if a count doesn't match the keys.
+    pub fn set_cubic_spline_tangents(
+        &mut self,
+        position_tangents: &[(Vec3, Vec3)],
+        rotation_tangents: &[(Quat, Quat)],
+        scale_tangents: &[(Vec3, Vec3)],
+    ) -> Result<(), Error> {
+        for (property, tangent_count, key_count) in [
+            ("position", position_tangents.len(), self.positions.len()),
+            ("rotation", rotation_tangents.len(), self.rotations.len()),
+            ("scale", scale_tangents.len(), self.scales.len()),
+        ] {
+            if tangent_count != 0 && tangent_count != key_count {
+                return Err(AnimationError(format!(
+                    "channel: {} has {} {} keys but {} tangents",
+                    self.name, key_count, property, tangent_count
+                )));
+            }
+        }
+
+        for (key, (in_tangent, out_tangent)) in self.positions.iter_mut().zip(position_tangents.iter().copied()) {
+            key.interpolation = Interpolation::CubicSpline { in_tangent, out_tangent };
+        }
+        for (key, (in_tangent, out_tangent)) in self.rotations.iter_mut().zip(rotation_tangents.iter().copied()) {
+            key.interpolation = Interpolation::CubicSpline { in_tangent, out_tangent };
+        }
+        for (key, (in_tangent, out_tangent)) in self.scales.iter_mut().zip(scale_tangents.iter().copied()) {
+            key.interpolation = Interpolation::CubicSpline { in_tangent, out_tangent };
+        }
+        Ok(())
+    }
+
+    /// The times of all the channel's keys, unsorted and with repeats.
+    pub fn key_times(&self) -> impl Iterator<Item = f32> + '_ {
+        self.positions
+            .iter()
+            .map(|key| key.time_stamp)
+            .chain(self.rotations.iter().map(|key| key.time_stamp))
+            .chain(self.quantized_rotations.iter().map(|key| key.time_stamp))
+            .chain(self.scales.iter().map(|key| key.time_stamp))
+    }
+
     pub fn get_animation_transform(&self, animation_time: f32) -> Transform {
         Transform {
             translation: self.interpolate_position(animation_time),
@@ -56,117 +235,364 @@ impl NodeAnimation {
     }
 
     fn interpolate_position(&self, animation_time: f32) -> Vec3 {
-        if self.positions.len() == 1 {
-            return self.positions[0].position;
+        match self.positions.len() {
+            0 => return Vec3::ZERO,
+            1 => return self.positions[0].position,
+            _ => {}
         }
 
-        let p0_index = self.get_position_index(animation_time);
-        let p1_index = p0_index + 1;
-
-        let scale_factor = self.get_scale_factor(
-            self.positions[p0_index].time_stamp,
-            self.positions[p1_index].time_stamp,
-            animation_time,
-        );
+        let (p0_index, scale_factor) = self.get_key_frame(&self.positions, |key| key.time_stamp, animation_time);
+        let p0 = &self.positions[p0_index];
+        let p1 = &self.positions[p0_index + 1];
 
         // final_position
-         self.positions[p0_index]
-            .position
-            .lerp(self.positions[p1_index].position, scale_factor)
+        match p0.interpolation {
+            Interpolation::Step => step(p0.position, p1.position, scale_factor),
+            Interpolation::Linear => p0.position.lerp(p1.position, scale_factor),
+            Interpolation::CubicSpline { out_tangent, .. } => cubic_spline(
+                p0.position,
+                out_tangent,
+                p1.position,
+                in_tangent(&p1.interpolation, Vec3::ZERO),
+                scale_factor,
+                p1.time_stamp - p0.time_stamp,
+            ),
+        }
     }
 
     fn interpolate_rotation(&self, animation_time: f32) -> Quat {
-        if self.rotations.len() == 1 {
-            let rotation = self.rotations[0].orientation.normalize();
-            return rotation;
+        if self.rotations.is_empty() && !self.quantized_rotations.is_empty() {
+            return self.interpolate_quantized_rotation(animation_time);
         }
 
-        let p0_index = self.get_rotation_index(animation_time);
-        let p1_index = p0_index + 1;
+        match self.rotations.len() {
+            0 => return Quat::IDENTITY,
+            1 => return self.rotations[0].orientation.normalize(),
+            _ => {}
+        }
 
-        let scale_factor = self.get_scale_factor(
-            self.rotations[p0_index].time_stamp,
-            self.rotations[p1_index].time_stamp,
-            animation_time,
-        );
+        let (p0_index, scale_factor) = self.get_key_frame(&self.rotations, |key| key.time_stamp, animation_time);
+        let p0 = &self.rotations[p0_index];
+        let p1 = &self.rotations[p0_index + 1];
 
         // final_rotation
-        self.rotations[p0_index]
-            .orientation
-            .slerp(self.rotations[p1_index].orientation, scale_factor)
+        match p0.interpolation {
+            Interpolation::Step => step(p0.orientation, p1.orientation, scale_factor),
+            Interpolation::Linear => p0.orientation.slerp(p1.orientation, scale_factor),
+            Interpolation::CubicSpline { out_tangent, .. } => {
+                let rotation = cubic_spline(
+                    Vec4::from(p0.orientation),
+                    Vec4::from(out_tangent),
+                    Vec4::from(p1.orientation),
+                    Vec4::from(in_tangent(&p1.interpolation, Quat::from_xyzw(0.0, 0.0, 0.0, 0.0))),
+                    scale_factor,
+                    p1.time_stamp - p0.time_stamp,
+                );
+                Quat::from_vec4(rotation).normalize()
+            }
+        }
     }
 
-    fn interpolate_scaling(&self, animation_time: f32) -> Vec3 {
-        if self.scales.len() == 1 {
-            return self.scales[0].scale;
+    fn interpolate_quantized_rotation(&self, animation_time: f32) -> Quat {
+        if self.quantized_rotations.len() == 1 {
+            return self.quantized_rotations[0].orientation.to_quat();
         }
 
-        let p0_index = self.get_scale_index(animation_time);
-        let p1_index = p0_index + 1;
-
-        let scale_factor = self.get_scale_factor(self.scales[p0_index].time_stamp, self.scales[p1_index].time_stamp, animation_time);
-
-        // final_scale
-        self.scales[p0_index].scale.lerp(self.scales[p1_index].scale, scale_factor)
+        let (p0_index, scale_factor) = self.get_key_frame(&self.quantized_rotations, |key| key.time_stamp, animation_time);
+        let p0 = self.quantized_rotations[p0_index].orientation.to_quat();
+        let p1 = self.quantized_rotations[p0_index + 1].orientation.to_quat();
+        p0.slerp(p1, scale_factor)
     }
 
-    fn get_position_index(&self, animation_time: f32) -> usize {
-        for index in 0..self.positions.len() - 1 {
-            if animation_time < self.positions[index + 1].time_stamp {
-                return index;
-            }
+    fn interpolate_scaling(&self, animation_time: f32) -> Vec3 {
+        match self.scales.len() {
+            0 => return Vec3::ONE,
+            1 => return self.scales[0].scale,
+            _ => {}
         }
-        panic!("animation time out of bounds");
-    }
 
-    fn get_rotation_index(&self, animation_time: f32) -> usize {
-        for index in 0..self.rotations.len() - 1 {
-            if animation_time < self.rotations[index + 1].time_stamp {
-                return index;
-            }
+        let (p0_index, scale_factor) = self.get_key_frame(&self.scales, |key| key.time_stamp, animation_time);
+        let p0 = &self.scales[p0_index];
+        let p1 = &self.scales[p0_index + 1];
+
+        // final_scale
+        match p0.interpolation {
+            Interpolation::Step => step(p0.scale, p1.scale, scale_factor),
+            Interpolation::Linear => p0.scale.lerp(p1.scale, scale_factor),
+            Interpolation::CubicSpline { out_tangent, .. } => cubic_spline(
+                p0.scale,
+                out_tangent,
+                p1.scale,
+                in_tangent(&p1.interpolation, Vec3::ZERO),
+                scale_factor,
+                p1.time_stamp - p0.time_stamp,
+            ),
         }
-        panic!("animation time out of bounds");
     }
 
-    fn get_scale_index(&self, animation_time: f32) -> usize {
-        for index in 0..self.scales.len() - 1 {
-            if animation_time < self.scales[index + 1].time_stamp {
-                return index;
+    /// Binary searches for the keyframe at or before the animation time, applying the boundary policy.
+    /// Returns the index of the first of the pair of keys to interpolate and the factor between them.
+    /// There must be at least two keys.
+    fn get_key_frame<K>(&self, keys: &[K], time_stamp: impl Fn(&K) -> f32, animation_time: f32) -> (usize, f32) {
+        let first_time = time_stamp(&keys[0]);
+        let last_time = time_stamp(&keys[keys.len() - 1]);
+
+        let animation_time = match self.boundary {
+            KeyframeBoundary::Clamp => animation_time.max(first_time).min(last_time),
+            KeyframeBoundary::Wrap if last_time > first_time => {
+                first_time + (animation_time - first_time).rem_euclid(last_time - first_time)
             }
-        }
-        panic!("animation time out of bounds");
+            KeyframeBoundary::Wrap => first_time,
+        };
+
+        let keys_at_or_before = keys.partition_point(|key| time_stamp(key) <= animation_time);
+        let index = keys_at_or_before.saturating_sub(1).min(keys.len() - 2);
+
+        let scale_factor = self.get_scale_factor(time_stamp(&keys[index]), time_stamp(&keys[index + 1]), animation_time);
+        (index, scale_factor)
     }
 
     fn get_scale_factor(&self, last_timestamp: f32, next_timestamp: f32, animation_time: f32) -> f32 {
         let mid_way_length = animation_time - last_timestamp;
         let frames_diff = next_timestamp - last_timestamp;
-        mid_way_length / frames_diff
+        if frames_diff <= 0.0 {
+            return 0.0;
+        }
+        (mid_way_length / frames_diff).clamp(0.0, 1.0)
     }
 }
 
+/// The previous value holds until the next keyframe is reached.
+fn step<T>(v0: T, v1: T, scale_factor: f32) -> T {
+    if scale_factor >= 1.0 {
+        v1
+    } else {
+        v0
+    }
+}
+
+fn in_tangent<T: Copy>(interpolation: &Interpolation<T>, zero: T) -> T {
+    match interpolation {
+        Interpolation::CubicSpline { in_tangent, .. } => *in_tangent,
+        _ => zero,
+    }
+}
+
+/// Cubic Hermite spline as defined by the glTF spec, with t in 0..1 and key_duration in ticks.
+fn cubic_spline<T>(v0: T, out_tangent: T, v1: T, in_tangent: T, t: f32, key_duration: f32) -> T
+where
+    T: Copy + Add<Output = T> + Mul<f32, Output = T>,
+{
+    let t2 = t * t;
+    let t3 = t2 * t;
+    v0 * (2.0 * t3 - 3.0 * t2 + 1.0)
+        + out_tangent * ((t3 - 2.0 * t2 + t) * key_duration)
+        + v1 * (-2.0 * t3 + 3.0 * t2)
+        + in_tangent * ((t3 - t2) * key_duration)
+}
+
 impl From<&VectorKey> for KeyPosition {
     fn from(vector_key: &VectorKey) -> Self {
-        KeyPosition {
-            position: vector_key.value,
-            time_stamp: vector_key.time as f32,
-        }
+        KeyPosition::new(vector_key.time as f32, vector_key.value)
     }
 }
 
 impl From<&QuatKey> for KeyRotation {
     fn from(quad_key: &QuatKey) -> Self {
-        KeyRotation {
-            orientation: quad_key.value,
-            time_stamp: quad_key.time as f32,
-        }
+        KeyRotation::new(quad_key.time as f32, quad_key.value)
     }
 }
 
 impl From<&VectorKey> for KeyScale {
     fn from(vector_key: &VectorKey) -> Self {
-        KeyScale {
-            scale: vector_key.value,
-            time_stamp: vector_key.time as f32,
+        KeyScale::new(vector_key.time as f32, vector_key.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::node_animation::{Interpolation, KeyPosition, KeyRotation, KeyScale, KeyframeBoundary, NodeAnimation};
+    use glam::{vec3, Quat, Vec3};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use russimp::animation::{NodeAnim, QuatKey, VectorKey};
+    use std::rc::Rc;
+
+    fn random_node_animation(rng: &mut StdRng, boundary: KeyframeBoundary) -> NodeAnimation {
+        let mut random_times = || {
+            let count = rng.gen_range(0..8);
+            let mut times: Vec<f32> = (0..count).map(|_| rng.gen_range(0.0..100.0_f32).floor()).collect();
+            times.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            times
+        };
+
+        NodeAnimation {
+            name: Rc::from("node"),
+            positions: random_times()
+                .into_iter()
+                .map(|time_stamp| KeyPosition::new(time_stamp, vec3(time_stamp, 1.0, 2.0)))
+                .collect(),
+            rotations: random_times()
+                .into_iter()
+                .map(|time_stamp| KeyRotation::new(time_stamp, Quat::from_rotation_y(time_stamp.to_radians())))
+                .collect(),
+            quantized_rotations: vec![],
+            scales: random_times()
+                .into_iter()
+                .map(|time_stamp| KeyScale::new(time_stamp, Vec3::splat(1.0 + time_stamp)))
+                .collect(),
+            boundary,
+        }
+    }
+
+    #[test]
+    fn test_random_keys_and_times_never_panic() {
+        for boundary in [KeyframeBoundary::Clamp, KeyframeBoundary::Wrap] {
+            // seeded so that a failure can be reproduced from the seed it reports
+            for seed in 0..500 {
+                let mut rng = StdRng::seed_from_u64(seed);
+                let node_animation = random_node_animation(&mut rng, boundary);
+                for _ in 0..20 {
+                    let time = rng.gen_range(-200.0..300.0);
+                    let transform = node_animation.get_animation_transform(time);
+                    assert!(
+                        transform.translation.is_finite() && transform.rotation.is_finite() && transform.scale.is_finite(),
+                        "seed: {} {:?} time: {}",
+                        seed,
+                        boundary,
+                        time
+                    );
+                }
+            }
         }
     }
+
+    #[test]
+    fn test_boundary_policy() {
+        let mut node_animation = NodeAnimation {
+            name: Rc::from("node"),
+            positions: vec![KeyPosition::new(0.0, Vec3::ZERO), KeyPosition::new(10.0, vec3(10.0, 0.0, 0.0))],
+            rotations: vec![],
+            quantized_rotations: vec![],
+            scales: vec![],
+            boundary: KeyframeBoundary::Clamp,
+        };
+
+        assert_eq!(node_animation.get_animation_transform(5.0).translation.x, 5.0);
+        assert_eq!(node_animation.get_animation_transform(10.0).translation.x, 10.0);
+        assert_eq!(node_animation.get_animation_transform(12.0).translation.x, 10.0);
+        assert_eq!(node_animation.get_animation_transform(-3.0).translation.x, 0.0);
+
+        node_animation.boundary = KeyframeBoundary::Wrap;
+        assert_eq!(node_animation.get_animation_transform(12.0).translation.x, 2.0);
+        assert_eq!(node_animation.get_animation_transform(-3.0).translation.x, 7.0);
+    }
+
+    #[test]
+    fn test_step_interpolation() {
+        let mut positions = vec![
+            KeyPosition::new(0.0, Vec3::ZERO),
+            KeyPosition::new(10.0, vec3(10.0, 0.0, 0.0)),
+            KeyPosition::new(20.0, vec3(20.0, 0.0, 0.0)),
+        ];
+        positions.iter_mut().for_each(|key| key.interpolation = Interpolation::Step);
+
+        let node_animation = NodeAnimation {
+            name: Rc::from("node"),
+            positions,
+            rotations: vec![],
+            quantized_rotations: vec![],
+            scales: vec![],
+            boundary: KeyframeBoundary::Clamp,
+        };
+
+        assert_eq!(node_animation.get_animation_transform(9.9).translation.x, 0.0);
+        assert_eq!(node_animation.get_animation_transform(10.0).translation.x, 10.0);
+        assert_eq!(node_animation.get_animation_transform(19.0).translation.x, 10.0);
+        assert_eq!(node_animation.get_animation_transform(20.0).translation.x, 20.0);
+    }
+
+    #[test]
+    fn test_cubic_spline_interpolation() {
+        // Tangents of 1 unit per tick along a straight line reproduce linear motion,
+        // zero tangents ease in and out.
+        let spline_key = |time_stamp: f32, tangent: f32| KeyPosition {
+            position: vec3(time_stamp, 0.0, 0.0),
+            time_stamp,
+            interpolation: Interpolation::CubicSpline {
+                in_tangent: vec3(tangent, 0.0, 0.0),
+                out_tangent: vec3(tangent, 0.0, 0.0),
+            },
+        };
+
+        let mut node_animation = NodeAnimation {
+            name: Rc::from("node"),
+            positions: vec![spline_key(0.0, 1.0), spline_key(10.0, 1.0)],
+            rotations: vec![
+                KeyRotation {
+                    orientation: Quat::IDENTITY,
+                    time_stamp: 0.0,
+                    interpolation: Interpolation::CubicSpline {
+                        in_tangent: Quat::from_xyzw(0.0, 0.0, 0.0, 0.0),
+                        out_tangent: Quat::from_xyzw(0.0, 0.0, 0.0, 0.0),
+                    },
+                },
+                KeyRotation::new(10.0, Quat::from_rotation_y(1.0)),
+            ],
+            quantized_rotations: vec![],
+            scales: vec![],
+            boundary: KeyframeBoundary::Clamp,
+        };
+
+        for time in [0.0, 2.5, 5.0, 7.5, 10.0] {
+            let transform = node_animation.get_animation_transform(time);
+            assert!((transform.translation.x - time).abs() < 0.0001);
+            assert!(transform.rotation.is_normalized());
+        }
+
+        node_animation.positions = vec![spline_key(0.0, 0.0), spline_key(10.0, 0.0)];
+        assert!(node_animation.get_animation_transform(1.0).translation.x < 1.0);
+        assert!((node_animation.get_animation_transform(5.0).translation.x - 5.0).abs() < 0.0001);
+        assert!(node_animation.get_animation_transform(9.0).translation.x > 9.0);
+    }
+
+    #[test]
+    fn test_imported_channel_interpolation() {
+        let channel = NodeAnim {
+            name: "piston".to_string(),
+            position_keys: vec![
+                VectorKey {
+                    time: 0.0,
+                    value: Vec3::ZERO,
+                },
+                VectorKey {
+                    time: 10.0,
+                    value: vec3(10.0, 0.0, 0.0),
+                },
+            ],
+            rotation_keys: vec![QuatKey {
+                time: 0.0,
+                value: Quat::IDENTITY,
+            }],
+            scaling_keys: vec![],
+        };
+
+        let mut node_animation = NodeAnimation::new(&channel.name, &channel);
+        assert_eq!(node_animation.positions[0].interpolation, Interpolation::Linear);
+        assert_eq!(node_animation.get_animation_transform(4.0).translation.x, 4.0);
+
+        // a STEP sampler holds until the next key
+        node_animation.set_step_interpolation();
+        assert_eq!(node_animation.get_animation_transform(4.0).translation.x, 0.0);
+        assert_eq!(node_animation.get_animation_transform(10.0).translation.x, 10.0);
+
+        // zero CUBICSPLINE tangents ease in and out
+        let tangents = [(Vec3::ZERO, Vec3::ZERO); 2];
+        node_animation.set_cubic_spline_tangents(&tangents, &[], &[]).unwrap();
+        let x = node_animation.get_animation_transform(2.0).translation.x;
+        assert!(x > 0.0 && x < 2.0, "{}", x);
+        assert_eq!(node_animation.rotations[0].interpolation, Interpolation::Step);
+
+        assert!(node_animation.set_cubic_spline_tangents(&tangents[..1], &[], &[]).is_err());
+    }
 }
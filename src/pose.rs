@@ -0,0 +1,413 @@
+use crate::model_animation::ModelAnimation;
+use crate::node_animation::NodeAnimation;
+use crate::skeleton::Skeleton;
+use crate::transform::Transform;
+use glam::{Mat4, Quat, Vec3, Vec4};
+use std::cmp::Ordering;
+use std::ops::{Index, IndexMut};
+
+/// The transform of each skeleton node relative to its parent, in skeleton order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pose {
+    pub transforms: Vec<Transform>,
+}
+
+impl Pose {
+    /// The skeleton's rest pose.
+    pub fn rest(skeleton: &Skeleton) -> Self {
+        Pose {
+            transforms: skeleton.nodes.iter().map(|node| node.transform).collect(),
+        }
+    }
+
+    /// Samples the animation at the tick. Nodes without a channel keep their rest transform.
+    pub fn sample(skeleton: &Skeleton, model_animation: &ModelAnimation, tick: f32) -> Self {
+        let node_animations = model_animation.node_animations.borrow();
+        let channel_binding = skeleton.bind_channels(&node_animations);
+
+        let mut pose = Pose::rest(skeleton);
+        pose.sample_channels(skeleton, &node_animations, &channel_binding, tick);
+        pose
+    }
+
+    /// Samples into this pose using channels already bound to the skeleton by `Skeleton::bind_channels`.
+    pub fn sample_channels(
+        &mut self,
+        skeleton: &Skeleton,
+        node_animations: &[NodeAnimation],
+        channel_binding: &[Option<usize>],
+        tick: f32,
+    ) {
+        for (node_index, node) in skeleton.nodes.iter().enumerate() {
+            self.transforms[node_index] = match channel_binding[node_index] {
+                Some(channel) => node_animations[channel].get_animation_transform(tick),
+                None => node.transform,
+            };
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.transforms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transforms.is_empty()
+    }
+
+    /// Blends from a towards b by weight.
+    pub fn blend(a: &Pose, b: &Pose, weight: f32) -> Pose {
+        let mut pose = a.clone();
+        pose.blend_with(b, weight);
+        pose
+    }
+
+    /// Blends this pose towards other by weight, in place.
+    pub fn blend_with(&mut self, other: &Pose, weight: f32) {
+        for (transform, other) in self.transforms.iter_mut().zip(other.transforms.iter()) {
+            *transform = transform.mul_transform_weighted(*other, weight);
+        }
+    }
+
+    /// Blends any number of weighted poses. The weights are normalized and the result doesn't depend
+    /// on the order of the poses. None when no pose has a positive weight.
+    pub fn blend_weighted(poses: &[(&Pose, f32)]) -> Option<Pose> {
+        let (first, _) = poses.iter().find(|(_, weight)| *weight > 0.0)?;
+        let mut pose = (*first).clone();
+        pose.set_blend_weighted(poses);
+        Some(pose)
+    }
+
+    /// Like blend_weighted, writing into this pose. Left unchanged when no pose has a positive weight.
+    pub fn set_blend_weighted(&mut self, poses: &[(&Pose, f32)]) {
+        for (node_index, transform) in self.transforms.iter_mut().enumerate() {
+            if let Some(blended) = blend_transforms(poses.iter().map(|(pose, weight)| (pose[node_index], *weight))) {
+                *transform = blended;
+            }
+        }
+    }
+
+    /// Like set_blend_weighted, for poses and their weights held in separate buffers, paired in order.
+    pub fn set_blend_poses(&mut self, poses: &[Pose], weights: &[f32]) {
+        for (node_index, transform) in self.transforms.iter_mut().enumerate() {
+            let weighted = poses.iter().zip(weights.iter()).map(|(pose, weight)| (pose[node_index], *weight));
+            if let Some(blended) = blend_transforms(weighted) {
+                *transform = blended;
+            }
+        }
+    }
+
+    /// The change of each node from the reference pose to this pose, for use with additive.
+    pub fn difference(&self, reference: &Pose) -> Pose {
+        Pose {
+            transforms: self
+                .transforms
+                .iter()
+                .zip(reference.transforms.iter())
+                .map(|(transform, reference)| transform_difference(*reference, *transform))
+                .collect(),
+        }
+    }
+
+    /// Applies a difference pose on top of a, scaled by weight.
+    pub fn additive(a: &Pose, delta: &Pose, weight: f32) -> Pose {
+        Pose {
+            transforms: a
+                .transforms
+                .iter()
+                .zip(delta.transforms.iter())
+                .map(|(base, delta)| add_transform(*base, *delta, weight))
+                .collect(),
+        }
+    }
+
+    /// Transforms of each node in model space, accumulated down the hierarchy from the root's parent transform.
+    pub fn to_model_space(&self, skeleton: &Skeleton, root_parent: Transform) -> Vec<Transform> {
+        let mut model_transforms = vec![Transform::IDENTITY; self.len()];
+        self.write_model_space(skeleton, root_parent, &mut model_transforms);
+        model_transforms
+    }
+
+    /// Like to_model_space, writing into an existing buffer.
+    pub fn write_model_space(&self, skeleton: &Skeleton, root_parent: Transform, model_transforms: &mut [Transform]) {
+        for (node_index, node) in skeleton.nodes.iter().enumerate() {
+            let parent_transform = match node.parent {
+                Some(parent_index) => model_transforms[parent_index],
+                None => root_parent,
+            };
+            model_transforms[node_index] = parent_transform.mul_transform(self.transforms[node_index]);
+        }
+    }
+
+    /// Matrices moving each bone from its bind pose to this pose, indexed by bone index.
+    pub fn to_skinning_matrices(&self, skeleton: &Skeleton, root_parent: Transform) -> Vec<Mat4> {
+        let bone_count = skeleton
+            .nodes
+            .iter()
+            .filter_map(|node| node.bone_index)
+            .max()
+            .map_or(0, |bone_index| bone_index + 1);
+
+        let mut skinning_matrices = vec![Mat4::IDENTITY; bone_count];
+        write_skinning_matrices(skeleton, &self.to_model_space(skeleton, root_parent), &mut skinning_matrices);
+        skinning_matrices
+    }
+}
+
+impl Index<usize> for Pose {
+    type Output = Transform;
+
+    fn index(&self, node_index: usize) -> &Transform {
+        &self.transforms[node_index]
+    }
+}
+
+impl IndexMut<usize> for Pose {
+    fn index_mut(&mut self, node_index: usize) -> &mut Transform {
+        &mut self.transforms[node_index]
+    }
+}
+
+/// Writes the skinning matrix of each bone from the model space node transforms.
+pub fn write_skinning_matrices(skeleton: &Skeleton, model_transforms: &[Transform], skinning_matrices: &mut [Mat4]) {
+    for (node, model_transform) in skeleton.nodes.iter().zip(model_transforms.iter()) {
+        if let Some(bone_index) = node.bone_index {
+            skinning_matrices[bone_index] = model_transform.mul_transform(node.offset_transform).compute_matrix();
+        }
+    }
+}
+
+/// Weighted average of transforms, independent of their order. The weights are normalized, translations and
+/// scales are averaged and rotations are averaged with nlerp after moving them into the same hemisphere as
+/// the heaviest rotation. None when no transform has a positive weight.
+pub fn blend_transforms<I>(transforms: I) -> Option<Transform>
+where
+    I: Iterator<Item = (Transform, f32)> + Clone,
+{
+    let weighted = transforms.filter(|(_, weight)| *weight > 0.0);
+
+    let total_weight: f32 = weighted.clone().map(|(_, weight)| weight).sum();
+    if total_weight <= 0.0 {
+        return None;
+    }
+
+    // ties are broken by the rotation's value rather than its position, so the order doesn't matter
+    let (reference, _) = weighted
+        .clone()
+        .map(|(transform, weight)| (Vec4::from(transform.rotation), weight))
+        .max_by(|(a, a_weight), (b, b_weight)| a_weight.total_cmp(b_weight).then_with(|| compare_vec4(*a, *b)))?;
+
+    let mut translation = Vec3::ZERO;
+    let mut rotation = Vec4::ZERO;
+    let mut scale = Vec3::ZERO;
+
+    for (transform, weight) in weighted {
+        let weight = weight / total_weight;
+        translation += transform.translation * weight;
+        scale += transform.scale * weight;
+
+        // q and -q are the same rotation, but only those in the same hemisphere can be averaged
+        let quat = Vec4::from(transform.rotation);
+        rotation += if quat.dot(reference) < 0.0 { -quat } else { quat } * weight;
+    }
+
+    let rotation = if rotation.length_squared() > f32::EPSILON {
+        Quat::from_vec4(rotation.normalize())
+    } else {
+        Quat::from_vec4(reference)
+    };
+
+    Some(Transform {
+        translation,
+        rotation,
+        scale,
+    })
+}
+
+fn compare_vec4(a: Vec4, b: Vec4) -> Ordering {
+    a.to_array()
+        .iter()
+        .zip(b.to_array().iter())
+        .map(|(a, b)| a.total_cmp(b))
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+/// The change from the reference transform to the transform.
+pub fn transform_difference(reference: Transform, transform: Transform) -> Transform {
+    Transform {
+        translation: transform.translation - reference.translation,
+        rotation: (reference.rotation.inverse() * transform.rotation).normalize(),
+        scale: transform.scale / reference.scale,
+    }
+}
+
+/// Adds a difference from transform_difference to the base transform, scaled by weight.
+pub fn add_transform(base: Transform, delta: Transform, weight: f32) -> Transform {
+    Transform {
+        translation: base.translation + delta.translation * weight,
+        rotation: (base.rotation * Quat::IDENTITY.slerp(delta.rotation, weight)).normalize(),
+        scale: base.scale * Vec3::ONE.lerp(delta.scale, weight),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hash_map::HashMap;
+    use crate::model_animation::{BoneData, ModelAnimation, NodeData};
+    use crate::node_animation::{KeyPosition, KeyRotation, KeyScale, KeyframeBoundary, NodeAnimation};
+    use crate::pose::{blend_transforms, Pose};
+    use crate::skeleton::Skeleton;
+    use crate::transform::Transform;
+    use glam::{vec3, EulerRot, Mat4, Quat, Vec3};
+    use std::f32::consts::FRAC_PI_2;
+    use std::rc::Rc;
+
+    /// hips with a spine one unit above it, both bones.
+    fn test_skeleton() -> Skeleton {
+        let root_node = NodeData {
+            name: Rc::from("hips"),
+            transform: Transform::IDENTITY,
+            children: vec![NodeData {
+                name: Rc::from("spine"),
+                transform: Transform::from_xyz(0.0, 1.0, 0.0),
+                children: vec![],
+                meshes: Rc::new(vec![]),
+            }],
+            meshes: Rc::new(vec![]),
+        };
+
+        let mut bone_data_map = HashMap::new();
+        bone_data_map.insert("hips".to_string(), BoneData::new("hips", 0, Mat4::IDENTITY));
+        bone_data_map.insert(
+            "spine".to_string(),
+            BoneData::new("spine", 1, Mat4::from_translation(vec3(0.0, -1.0, 0.0))),
+        );
+        Skeleton::new(&root_node, &bone_data_map)
+    }
+
+    /// Turns the hips a quarter turn around z over 10 ticks.
+    fn turn_animation() -> ModelAnimation {
+        ModelAnimation {
+            name: Rc::from("turn"),
+            duration: 10.0,
+            ticks_per_second: 10.0,
+            node_animations: vec![NodeAnimation {
+                name: Rc::from("hips"),
+                positions: vec![KeyPosition::new(0.0, Vec3::ZERO)],
+                rotations: vec![
+                    KeyRotation::new(0.0, Quat::IDENTITY),
+                    KeyRotation::new(10.0, Quat::from_rotation_z(FRAC_PI_2)),
+                ],
+                quantized_rotations: vec![],
+                scales: vec![KeyScale::new(0.0, Vec3::ONE)],
+                boundary: KeyframeBoundary::Clamp,
+            }]
+            .into(),
+            morph_animations: vec![],
+        }
+    }
+
+    #[test]
+    fn test_sample_and_model_space() {
+        let skeleton = test_skeleton();
+        let pose = Pose::sample(&skeleton, &turn_animation(), 10.0);
+
+        // the spine has no channel so keeps its rest transform
+        assert_eq!(pose[1], Transform::from_xyz(0.0, 1.0, 0.0));
+
+        let model_transforms = pose.to_model_space(&skeleton, Transform::IDENTITY);
+        assert!(model_transforms[1].translation.abs_diff_eq(vec3(-1.0, 0.0, 0.0), 0.0001));
+
+        // the rest pose skins to identity, the turned pose moves a vertex at the spine with it
+        let rest_matrices = Pose::rest(&skeleton).to_skinning_matrices(&skeleton, Transform::IDENTITY);
+        assert_eq!(rest_matrices.len(), 2);
+        assert!(rest_matrices[1].abs_diff_eq(Mat4::IDENTITY, 0.0001));
+
+        let skinning_matrices = pose.to_skinning_matrices(&skeleton, Transform::IDENTITY);
+        let vertex = skinning_matrices[1].transform_point3(vec3(0.0, 1.0, 0.0));
+        assert!(vertex.abs_diff_eq(vec3(-1.0, 0.0, 0.0), 0.0001));
+    }
+
+    fn assert_same_transform(a: Transform, b: Transform) {
+        assert!(a.translation.abs_diff_eq(b.translation, 0.0001), "{:?} {:?}", a, b);
+        assert!(a.rotation.abs_diff_eq(b.rotation, 0.0001), "{:?} {:?}", a, b);
+        assert!(a.scale.abs_diff_eq(b.scale, 0.0001), "{:?} {:?}", a, b);
+    }
+
+    #[test]
+    fn test_weighted_blend_is_order_independent() {
+        let transforms = [
+            (Transform::from_xyz(1.0, 0.0, 0.0).with_rotation(Quat::from_rotation_y(0.4)), 2.0),
+            // the same hemisphere as the others once negated
+            (Transform::from_xyz(0.0, 4.0, 0.0).with_rotation(-Quat::from_rotation_y(-0.8)), 1.0),
+            (
+                Transform::from_xyz(0.0, 0.0, 2.0)
+                    .with_rotation(Quat::from_rotation_x(0.3))
+                    .with_scale(Vec3::splat(2.0)),
+                1.0,
+            ),
+            (Transform::from_xyz(100.0, 0.0, 0.0), 0.0),
+        ];
+
+        let expected = blend_transforms(transforms.iter().copied()).unwrap();
+        assert!(expected.translation.abs_diff_eq(vec3(0.5, 1.0, 0.5), 0.0001));
+        assert!(expected.scale.abs_diff_eq(Vec3::splat(1.25), 0.0001));
+        assert!(expected.rotation.is_normalized());
+        // the y rotations average to nothing rather than twisting through the negated quaternion
+        let (y, _, _) = expected.rotation.to_euler(EulerRot::YXZ);
+        assert!(y.abs() < 0.01, "{}", y);
+
+        let orders = [[0, 1, 2, 3], [3, 2, 1, 0], [1, 0, 3, 2], [2, 3, 0, 1], [1, 2, 0, 3], [2, 0, 3, 1]];
+        for order in orders {
+            let permuted = blend_transforms(order.iter().map(|index| transforms[*index])).unwrap();
+            assert_same_transform(permuted, expected);
+        }
+
+        // scaling all the weights doesn't change the result
+        let scaled = blend_transforms(transforms.iter().map(|(transform, weight)| (*transform, weight * 7.0))).unwrap();
+        assert_same_transform(scaled, expected);
+
+        assert!(blend_transforms([(Transform::IDENTITY, 0.0)].into_iter()).is_none());
+    }
+
+    #[test]
+    fn test_blend_weighted_poses() {
+        let skeleton = test_skeleton();
+        let animation = turn_animation();
+        let start = Pose::sample(&skeleton, &animation, 0.0);
+        let middle = Pose::sample(&skeleton, &animation, 5.0);
+        let end = Pose::sample(&skeleton, &animation, 10.0);
+
+        let blended = Pose::blend_weighted(&[(&start, 1.0), (&middle, 2.0), (&end, 1.0)]).unwrap();
+        let reversed = Pose::blend_weighted(&[(&end, 1.0), (&middle, 2.0), (&start, 1.0)]).unwrap();
+        for node_index in 0..blended.len() {
+            assert_same_transform(blended[node_index], reversed[node_index]);
+        }
+        assert!(blended[0].rotation.abs_diff_eq(middle[0].rotation, 0.0001));
+        assert!(Pose::blend_weighted(&[(&start, 0.0)]).is_none());
+    }
+
+    #[test]
+    fn test_blend_and_additive() {
+        let skeleton = test_skeleton();
+        let animation = turn_animation();
+        let start = Pose::sample(&skeleton, &animation, 0.0);
+        let end = Pose::sample(&skeleton, &animation, 10.0);
+
+        let halfway = Pose::blend(&start, &end, 0.5);
+        assert!(halfway[0].rotation.abs_diff_eq(Quat::from_rotation_z(FRAC_PI_2 * 0.5), 0.0001));
+        assert_eq!(Pose::blend(&start, &end, 0.0), start);
+
+        // adding the difference between two poses to the first gives the second
+        let delta = end.difference(&start);
+        let added = Pose::additive(&start, &delta, 1.0);
+        assert!(added[0].rotation.abs_diff_eq(end[0].rotation, 0.0001));
+        assert_eq!(added[1], start[1]);
+
+        let mut raised = start.clone();
+        raised[1].translation.y = 3.0;
+        let half_raise = Pose::additive(&end, &raised.difference(&start), 0.5);
+        assert!(half_raise[1].translation.abs_diff_eq(vec3(0.0, 2.0, 0.0), 0.0001));
+        assert!(half_raise[0].rotation.abs_diff_eq(end[0].rotation, 0.0001));
+    }
+}
@@ -0,0 +1,388 @@
+use crate::error::Error;
+use crate::error::Error::AnimationError;
+use crate::hash_map::HashMap;
+use crate::model_animation::ModelAnimation;
+use crate::node_animation::{KeyPosition, KeyRotation, KeyScale, NodeAnimation};
+use crate::skeleton::Skeleton;
+use crate::transform::Transform;
+use serde::{Deserialize, Serialize};
+use std::rc::Rc;
+
+/// Maps the bones of a source skeleton onto a target skeleton, usually authored as RON.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BoneMap {
+    /// Source bone name to target bone name
+    pub bones: HashMap<String, String>,
+    /// Source bones whose animated translation is kept, usually just the hips. The other
+    /// bones keep the target's rest translation so the target's proportions are preserved.
+    #[serde(default)]
+    pub translation_bones: Vec<String>,
+    /// Scales the kept translation by the ratio of the target's to the source's leg length,
+    /// measured as the rest height of the first translation bone.
+    #[serde(default)]
+    pub scale_translation: bool,
+}
+
+impl BoneMap {
+    pub fn from_ron(ron_bone_map: &str) -> Result<Self, Error> {
+        ron::from_str(ron_bone_map).map_err(|e| AnimationError(format!("bone map: {}", e)))
+    }
+
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let ron_bone_map = std::fs::read_to_string(path)?;
+        BoneMap::from_ron(&ron_bone_map)
+    }
+}
+
+/// Converts an animation of the source skeleton into one the target skeleton can play.
+///
+/// Each mapped bone gets the same model space rotation away from its rest pose as its source bone,
+/// which compensates for bones that are oriented differently in the two rigs. The rest poses are the
+/// bind poses from the bones' offset transforms. Source channels without a mapping are dropped.
+pub fn retarget_animation(
+    source: &Skeleton,
+    animation: &ModelAnimation,
+    target: &Skeleton,
+    bone_map: &BoneMap,
+    name: &str,
+) -> Result<ModelAnimation, Error> {
+    // for each target node, the source node it copies
+    let mut source_for_target: Vec<Option<usize>> = vec![None; target.len()];
+    let mut unknown = vec![];
+
+    for (source_name, target_name) in bone_map.bones.iter() {
+        match (source.find_node(source_name), target.find_node(target_name)) {
+            (Some(source_index), Some(target_index)) => source_for_target[target_index] = Some(source_index),
+            (source_index, _) => unknown.push(if source_index.is_none() { source_name } else { target_name }),
+        }
+    }
+
+    if !unknown.is_empty() {
+        unknown.sort();
+        return Err(AnimationError(format!("bone map bones not found: {:?}", unknown)));
+    }
+
+    let mut translation_nodes = vec![];
+    for bone_name in bone_map.translation_bones.iter() {
+        match source.find_node(bone_name) {
+            Some(node_index) => translation_nodes.push(node_index),
+            None => return Err(AnimationError(format!("bone map translation bone not found: {}", bone_name))),
+        }
+    }
+
+    let source_rest = rest_pose(source);
+    let target_rest = rest_pose(target);
+
+    let translation_scale = match translation_nodes.first() {
+        Some(source_index) if bone_map.scale_translation => {
+            let target_index = source_for_target.iter().position(|index| *index == Some(*source_index));
+            let source_height = source_rest[*source_index].translation.y;
+            let target_height = target_index.map_or(0.0, |target_index| target_rest[target_index].translation.y);
+            if source_height.abs() > 1e-6 && target_height.abs() > 1e-6 {
+                target_height / source_height
+            } else {
+                1.0
+            }
+        }
+        _ => 1.0,
+    };
+
+    let node_animations = animation.node_animations.borrow();
+    let channel_binding = source.bind_channels(&node_animations);
+    let key_times = key_times(&node_animations, &channel_binding);
+
+    let mut target_channels: Vec<(usize, NodeAnimation)> = source_for_target
+        .iter()
+        .enumerate()
+        .filter(|(_, source_index)| source_index.is_some())
+        .map(|(target_index, _)| {
+            let rest = target.nodes[target_index].transform;
+            let channel = NodeAnimation {
+                name: target.nodes[target_index].name.clone(),
+                positions: vec![],
+                rotations: vec![],
+                quantized_rotations: vec![],
+                scales: vec![KeyScale::new(0.0, rest.scale)],
+                boundary: node_animations.first().map(|channel| channel.boundary).unwrap_or_default(),
+            };
+            (target_index, channel)
+        })
+        .collect();
+
+    let mut source_pose = vec![Transform::IDENTITY; source.len()];
+    let mut target_pose = vec![Transform::IDENTITY; target.len()];
+
+    for tick in key_times {
+        // the source pose in model space
+        for (node_index, node) in source.nodes.iter().enumerate() {
+            let local = match channel_binding[node_index] {
+                Some(channel) => node_animations[channel].get_animation_transform(tick),
+                None => node.transform,
+            };
+            source_pose[node_index] = match node.parent {
+                Some(parent_index) => source_pose[parent_index].mul_transform(local),
+                None => local,
+            };
+        }
+
+        // the target pose in model space, built down the hierarchy
+        for (node_index, node) in target.nodes.iter().enumerate() {
+            let parent = node.parent.map_or(Transform::IDENTITY, |parent_index| target_pose[parent_index]);
+            let mut local = node.transform;
+
+            if let Some(source_index) = source_for_target[node_index] {
+                let source_delta = source_pose[source_index].rotation * source_rest[source_index].rotation.inverse();
+                let rotation = source_delta * target_rest[node_index].rotation;
+                local.rotation = (parent.rotation.inverse() * rotation).normalize();
+
+                if translation_nodes.contains(&source_index) {
+                    let offset = (source_pose[source_index].translation - source_rest[source_index].translation) * translation_scale;
+                    let position = target_rest[node_index].translation + offset;
+                    local.translation = parent.rotation.inverse() * (position - parent.translation) / parent.scale;
+                }
+            }
+
+            target_pose[node_index] = parent.mul_transform(local);
+
+            if let Some((_, channel)) = target_channels.iter_mut().find(|(target_index, _)| *target_index == node_index) {
+                channel.rotations.push(KeyRotation::new(tick, local.rotation));
+                if channel.positions.is_empty() || translation_nodes.contains(&source_for_target[node_index].unwrap()) {
+                    channel.positions.push(KeyPosition::new(tick, local.translation));
+                }
+            }
+        }
+    }
+
+    Ok(ModelAnimation {
+        name: Rc::from(name),
+        duration: animation.duration,
+        ticks_per_second: animation.ticks_per_second,
+        node_animations: target_channels.into_iter().map(|(_, channel)| channel).collect::<Vec<_>>().into(),
+        morph_animations: vec![],
+    })
+}
+
+/// Model space rest pose of each node. Bones use their bind pose from the offset transform.
+fn rest_pose(skeleton: &Skeleton) -> Vec<Transform> {
+    let mut rest: Vec<Transform> = Vec::with_capacity(skeleton.len());
+
+    for node in skeleton.nodes.iter() {
+        let transform = match (node.bone_index, node.parent) {
+            (Some(_), _) => Transform::from_matrix(node.offset_transform.compute_matrix().inverse()),
+            (None, Some(parent_index)) => rest[parent_index].mul_transform(node.transform),
+            (None, None) => node.transform,
+        };
+        rest.push(transform);
+    }
+    rest
+}
+
+/// The sorted times of all the keys of the bound channels.
+fn key_times(node_animations: &[NodeAnimation], channel_binding: &[Option<usize>]) -> Vec<f32> {
+    let mut times: Vec<f32> = channel_binding
+        .iter()
+        .flatten()
+        .flat_map(|channel| node_animations[*channel].key_times())
+        .collect();
+
+    times.sort_by(|a, b| a.total_cmp(b));
+    times.dedup();
+    if times.is_empty() {
+        times.push(0.0);
+    }
+    times
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::animator::{AnimationRepeat, Animator};
+    use crate::error::Error;
+    use crate::hash_map::HashMap;
+    use crate::model_animation::{BoneData, ModelAnimation, NodeData};
+    use crate::node_animation::{KeyPosition, KeyRotation, KeyframeBoundary, NodeAnimation, QuantizedKeyRotation, SmallestThree};
+    use crate::retarget::{retarget_animation, BoneMap};
+    use crate::skeleton::Skeleton;
+    use crate::transform::Transform;
+    use glam::{vec3, Mat4, Quat};
+    use std::f32::consts::{FRAC_PI_2, FRAC_PI_4};
+    use std::rc::Rc;
+
+    const BONE_MAP: &str = r#"(
+        bones: { "Hips": "mixamorig:Hips", "Spine": "mixamorig:Spine" },
+        translation_bones: ["Hips"],
+        scale_translation: true,
+    )"#;
+
+    /// Bone data with offsets that are the inverse of each bone's model space rest transform.
+    fn bone_data_map(root_node: &NodeData) -> HashMap<String, BoneData> {
+        let mut bone_data_map = HashMap::new();
+        let mut stack = vec![(root_node, Transform::IDENTITY)];
+        while let Some((node, parent)) = stack.pop() {
+            let rest = parent.mul_transform(node.transform);
+            let index = bone_data_map.len() as i32;
+            bone_data_map.insert(
+                node.name.to_string(),
+                BoneData::new(&node.name, index, rest.compute_matrix().inverse()),
+            );
+            stack.extend(node.children.iter().map(|child| (child, rest)));
+        }
+        bone_data_map
+    }
+
+    fn hips_and_spine(hips: &str, hips_height: f32, spine: &str, spine_transform: Transform) -> NodeData {
+        NodeData {
+            name: Rc::from(hips),
+            transform: Transform::from_xyz(0.0, hips_height, 0.0),
+            children: vec![NodeData {
+                name: Rc::from(spine),
+                transform: spine_transform,
+                children: vec![],
+                meshes: Rc::new(vec![]),
+            }],
+            meshes: Rc::new(vec![]),
+        }
+    }
+
+    #[test]
+    fn test_retarget_to_different_rest_pose_and_size() {
+        let source_root = hips_and_spine("Hips", 1.0, "Spine", Transform::from_xyz(0.0, 0.5, 0.0));
+        let source = Skeleton::new(&source_root, &bone_data_map(&source_root));
+
+        // the target is twice the size and its spine bone points along x
+        let target_spine = Transform::from_xyz(0.0, 1.0, 0.0).with_rotation(Quat::from_rotation_z(FRAC_PI_2));
+        let target_root = hips_and_spine("mixamorig:Hips", 2.0, "mixamorig:Spine", target_spine);
+        let target_bones = bone_data_map(&target_root);
+        let mut target = Animator::from_parts(target_root, Mat4::IDENTITY, target_bones.into(), vec![]);
+
+        // the source moves forward and bends its spine forward
+        let animation = ModelAnimation {
+            name: Rc::from("lean"),
+            duration: 10.0,
+            ticks_per_second: 10.0,
+            node_animations: vec![
+                NodeAnimation {
+                    name: Rc::from("Hips"),
+                    positions: vec![
+                        KeyPosition::new(0.0, vec3(0.0, 1.0, 0.0)),
+                        KeyPosition::new(10.0, vec3(0.0, 1.0, 1.0)),
+                    ],
+                    rotations: vec![],
+                    quantized_rotations: vec![],
+                    scales: vec![],
+                    boundary: KeyframeBoundary::Clamp,
+                },
+                NodeAnimation {
+                    name: Rc::from("Spine"),
+                    positions: vec![KeyPosition::new(0.0, vec3(0.0, 0.5, 0.0))],
+                    rotations: vec![
+                        KeyRotation::new(0.0, Quat::IDENTITY),
+                        KeyRotation::new(10.0, Quat::from_rotation_x(FRAC_PI_4)),
+                    ],
+                    quantized_rotations: vec![],
+                    scales: vec![],
+                    boundary: KeyframeBoundary::Clamp,
+                },
+                NodeAnimation {
+                    name: Rc::from("Tail"),
+                    positions: vec![],
+                    rotations: vec![],
+                    quantized_rotations: vec![],
+                    scales: vec![],
+                    boundary: KeyframeBoundary::Clamp,
+                },
+            ]
+            .into(),
+            morph_animations: vec![],
+        };
+
+        let bone_map = BoneMap::from_ron(BONE_MAP).unwrap();
+        let retargeted = retarget_animation(&source, &animation, &target.skeleton, &bone_map, "lean").unwrap();
+        assert_eq!(retargeted.node_animations.borrow().len(), 2);
+
+        target.add_animation(retargeted).unwrap();
+        let clip = target.animation_clip("lean", AnimationRepeat::Once).unwrap();
+        target.play_clip(&clip).unwrap();
+        target.update_animation(1.0);
+
+        // twice the height moves twice as far
+        let hips = target.node_transform("mixamorig:Hips").unwrap();
+        assert!(hips.translation.abs_diff_eq(vec3(0.0, 2.0, 2.0), 0.0001));
+
+        // the spine turns the same way in model space, from its own rest pose
+        let spine = target.node_transform("mixamorig:Spine").unwrap();
+        let expected = Quat::from_rotation_x(FRAC_PI_4) * Quat::from_rotation_z(FRAC_PI_2);
+        assert!(spine.rotation.abs_diff_eq(expected, 0.0001));
+        assert!(spine.translation.abs_diff_eq(vec3(0.0, 3.0, 2.0), 0.0001));
+    }
+
+    #[test]
+    fn test_retarget_quantized_rotations() {
+        let source_root = hips_and_spine("Hips", 1.0, "Spine", Transform::from_xyz(0.0, 0.5, 0.0));
+        let source = Skeleton::new(&source_root, &bone_data_map(&source_root));
+        let target_root = hips_and_spine("mixamorig:Hips", 1.0, "mixamorig:Spine", Transform::from_xyz(0.0, 0.5, 0.0));
+        let target_bones = bone_data_map(&target_root);
+        let mut target = Animator::from_parts(target_root, Mat4::IDENTITY, target_bones.into(), vec![]);
+
+        // the spine nods forward and back, with only the rotations keyed in between
+        let nod = [
+            (0.0, Quat::IDENTITY),
+            (5.0, Quat::from_rotation_x(FRAC_PI_4)),
+            (10.0, Quat::IDENTITY),
+        ];
+        let animation = ModelAnimation {
+            name: Rc::from("nod"),
+            duration: 10.0,
+            ticks_per_second: 10.0,
+            node_animations: vec![NodeAnimation {
+                name: Rc::from("Spine"),
+                positions: vec![KeyPosition::new(0.0, vec3(0.0, 0.5, 0.0))],
+                rotations: vec![],
+                quantized_rotations: nod
+                    .iter()
+                    .map(|(time_stamp, rotation)| QuantizedKeyRotation {
+                        orientation: SmallestThree::from_quat(*rotation),
+                        time_stamp: *time_stamp,
+                    })
+                    .collect(),
+                scales: vec![],
+                boundary: KeyframeBoundary::Clamp,
+            }]
+            .into(),
+            morph_animations: vec![],
+        };
+
+        let bone_map = BoneMap::from_ron(BONE_MAP).unwrap();
+        let retargeted = retarget_animation(&source, &animation, &target.skeleton, &bone_map, "nod").unwrap();
+        target.add_animation(retargeted).unwrap();
+        let clip = target.animation_clip("nod", AnimationRepeat::Once).unwrap();
+        target.play_clip(&clip).unwrap();
+        target.update_animation(0.5);
+
+        let spine = target.node_transform("mixamorig:Spine").unwrap();
+        assert!(
+            spine.rotation.abs_diff_eq(Quat::from_rotation_x(FRAC_PI_4), 0.001),
+            "{}",
+            spine.rotation
+        );
+    }
+
+    #[test]
+    fn test_bone_map_errors() {
+        let source_root = hips_and_spine("Hips", 1.0, "Spine", Transform::IDENTITY);
+        let source = Skeleton::new(&source_root, &HashMap::new());
+        let animation = ModelAnimation::default();
+
+        let mut bone_map = BoneMap::from_ron(BONE_MAP).unwrap();
+        bone_map.bones.insert("Head".to_string(), "mixamorig:Head".to_string());
+
+        let result = retarget_animation(&source, &animation, &source, &bone_map, "lean");
+        match result {
+            Err(Error::AnimationError(message)) => {
+                assert!(message.contains("Head") && message.contains("mixamorig:Hips"), "{}", message)
+            }
+            _ => panic!("expected bone map error"),
+        }
+
+        assert!(BoneMap::from_ron("(bones: [])").is_err());
+    }
+}
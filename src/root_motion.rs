@@ -0,0 +1,97 @@
+use crate::transform::Transform;
+use glam::{Quat, Vec3};
+use std::f32::consts::PI;
+
+/// Which parts of a node's animation are taken out of the pose and returned as movement.
+#[derive(Debug, Clone)]
+pub struct RootMotion {
+    /// Index of the skeleton node the motion is extracted from, usually the hips or root bone
+    pub node_index: usize,
+    /// 1.0 for each translation axis that is extracted, 0.0 for those left in the pose
+    pub translation_axes: Vec3,
+    /// Extracts the rotation about the y axis
+    pub extract_yaw: bool,
+}
+
+impl RootMotion {
+    /// The extracted part of the node's local transform.
+    pub fn extract(&self, transform: Transform) -> Transform {
+        let rotation = if self.extract_yaw {
+            yaw_rotation(transform.rotation)
+        } else {
+            Quat::IDENTITY
+        };
+
+        Transform {
+            translation: transform.translation * self.translation_axes,
+            rotation,
+            scale: Vec3::ONE,
+        }
+    }
+
+    /// The node's local transform with the extracted part taken out, so extracted * removed = transform.
+    pub fn remove(&self, transform: Transform) -> Transform {
+        relative_motion(self.extract(transform), transform)
+    }
+}
+
+/// The motion from one extracted transform to another, in the frame of the first.
+pub fn relative_motion(from: Transform, to: Transform) -> Transform {
+    let inverse_rotation = from.rotation.inverse();
+
+    Transform {
+        translation: inverse_rotation * (to.translation - from.translation),
+        rotation: (inverse_rotation * to.rotation).normalize(),
+        scale: to.scale,
+    }
+}
+
+/// Weighted average of root motion deltas, blending the yaw as an angle.
+pub fn blend_motion(deltas: impl Iterator<Item = (Transform, f32)>) -> Transform {
+    let mut translation = Vec3::ZERO;
+    let mut yaw = 0.0;
+
+    for (delta, weight) in deltas {
+        translation += delta.translation * weight;
+        yaw += yaw_angle(delta.rotation) * weight;
+    }
+
+    Transform::from_translation(translation).with_rotation(Quat::from_rotation_y(yaw))
+}
+
+/// The motion repeated count times, composed by squaring so that many repeats stay cheap.
+pub fn repeat_motion(motion: Transform, count: u32) -> Transform {
+    let mut repeated = Transform::IDENTITY;
+    let mut power = motion;
+    let mut count = count;
+
+    while count > 0 {
+        if count & 1 == 1 {
+            repeated = repeated.mul_transform(power);
+        }
+        power = power.mul_transform(power);
+        count >>= 1;
+    }
+    repeated
+}
+
+/// The twist of the rotation about the y axis.
+fn yaw_rotation(rotation: Quat) -> Quat {
+    Quat::from_rotation_y(yaw_angle(rotation))
+}
+
+/// Angle of the rotation's twist about the y axis, from -PI to PI.
+fn yaw_angle(rotation: Quat) -> f32 {
+    if rotation.y == 0.0 && rotation.w == 0.0 {
+        return 0.0;
+    }
+
+    let angle = 2.0 * rotation.y.atan2(rotation.w);
+    if angle > PI {
+        angle - 2.0 * PI
+    } else if angle < -PI {
+        angle + 2.0 * PI
+    } else {
+        angle
+    }
+}
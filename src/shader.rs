@@ -4,8 +4,8 @@ use glam::*;
 
 use std::fs::File;
 use std::io::prelude::*;
-use std::io::Error;
-use std::path::Path;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
 use std::ptr;
 
 use crate::ShaderId;
@@ -257,11 +257,46 @@ impl Shader {
     }
 }
 
+/// Reads a shader source file, replacing `#include "file"` lines with the named file, relative to the including file.
+/// Fails if a file includes itself, directly or through other files.
 fn read_file(filename: &str) -> Result<String, Error> {
+    read_included_file(Path::new(filename), &mut vec![])
+}
+
+/// `include_stack` holds the files being included into, outermost first.
+fn read_included_file(path: &Path, include_stack: &mut Vec<PathBuf>) -> Result<String, Error> {
     let mut content: String = Default::default();
-    let mut file = File::open(Path::new(filename))?;
+    let mut file = File::open(path)?;
     file.read_to_string(&mut content)?;
-    Ok(content)
+
+    if !content.contains("#include") {
+        return Ok(content);
+    }
+
+    let canonical_path = path.canonicalize()?;
+    if include_stack.contains(&canonical_path) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("shader include cycle at: {}", path.display()),
+        ));
+    }
+    include_stack.push(canonical_path);
+
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let mut source = String::with_capacity(content.len());
+    for line in content.lines() {
+        match line.trim().strip_prefix("#include") {
+            Some(include) => {
+                let include_path = directory.join(include.trim().trim_matches('"'));
+                source.push_str(&read_included_file(&include_path, include_stack)?);
+            }
+            None => source.push_str(line),
+        }
+        source.push('\n');
+    }
+
+    include_stack.pop();
+    Ok(source)
 }
 
 fn check_compile_errors(shader_id: u32, check_type: &str) -> Result<(), String> {
@@ -293,3 +328,32 @@ fn check_compile_errors(shader_id: u32, check_type: &str) -> Result<(), String>
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::shader::read_file;
+
+    #[test]
+    fn test_include() {
+        let directory = std::env::temp_dir().join(format!("shader_include_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("bones.glsl"), "uniform samplerBuffer bonePalette;").unwrap();
+        std::fs::write(
+            directory.join("skin.vert"),
+            "#version 330 core\n#include \"bones.glsl\"\nvoid main() {}\n",
+        )
+        .unwrap();
+
+        let source = read_file(&directory.join("skin.vert").to_string_lossy()).unwrap();
+        assert_eq!(source, "#version 330 core\nuniform samplerBuffer bonePalette;\nvoid main() {}\n");
+
+        // including a file twice is fine, including a file into itself is not
+        std::fs::write(directory.join("twice.vert"), "#include \"bones.glsl\"\n#include \"bones.glsl\"\n").unwrap();
+        assert!(read_file(&directory.join("twice.vert").to_string_lossy()).is_ok());
+        std::fs::write(directory.join("a.glsl"), "#include \"b.glsl\"\n").unwrap();
+        std::fs::write(directory.join("b.glsl"), "#include \"a.glsl\"\n").unwrap();
+        assert!(read_file(&directory.join("a.glsl").to_string_lossy()).is_err());
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,103 @@
+use crate::error::Error;
+use crate::error::Error::AnimationError;
+use crate::hash_map::HashMap;
+use crate::model_animation::{BoneData, BoneName, NodeData};
+use crate::node_animation::NodeAnimation;
+use crate::transform::Transform;
+use std::ops::Range;
+use std::rc::Rc;
+
+/// A node of the flattened hierarchy with its bone binding resolved.
+#[derive(Debug, Clone)]
+pub struct SkeletonNode {
+    pub name: Rc<str>,
+    /// Index of the parent node, always lower than this node's index. None for the root.
+    pub parent: Option<usize>,
+    /// The node's rest transform relative to its parent.
+    pub transform: Transform,
+    pub meshes: Rc<Vec<u32>>,
+    pub bone_index: Option<usize>,
+    pub offset_transform: Transform,
+}
+
+/// The NodeData tree flattened into an array in depth first order, so parents are
+/// always evaluated before their children without recursion or name lookups.
+#[derive(Debug, Clone)]
+pub struct Skeleton {
+    pub nodes: Vec<SkeletonNode>,
+}
+
+impl Skeleton {
+    pub fn new(root_node: &NodeData, bone_data_map: &HashMap<BoneName, BoneData>) -> Self {
+        let mut skeleton = Skeleton { nodes: vec![] };
+        skeleton.add_node(root_node, None, bone_data_map);
+        skeleton
+    }
+
+    fn add_node(&mut self, node_data: &NodeData, parent: Option<usize>, bone_data_map: &HashMap<BoneName, BoneData>) {
+        let bone_data = bone_data_map.get(node_data.name.as_ref());
+
+        let index = self.nodes.len();
+        self.nodes.push(SkeletonNode {
+            name: node_data.name.clone(),
+            parent,
+            transform: node_data.transform,
+            meshes: node_data.meshes.clone(),
+            bone_index: bone_data.map(|bone_data| bone_data.bone_index as usize),
+            offset_transform: bone_data.map_or(Transform::IDENTITY, |bone_data| bone_data.offset_transform),
+        });
+
+        for child in node_data.children.iter() {
+            self.add_node(child, Some(index), bone_data_map);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn find_node(&self, name: &str) -> Option<usize> {
+        self.nodes.iter().position(|node| node.name.as_ref() == name)
+    }
+
+    /// Index of the named node, failing if there is no such node or it isn't a bone.
+    pub fn find_bone(&self, name: &str) -> Result<usize, Error> {
+        self.find_node(name)
+            .filter(|node_index| self.nodes[*node_index].bone_index.is_some())
+            .ok_or_else(|| AnimationError(format!("bone not found: {}", name)))
+    }
+
+    /// Indices of the node and all its descendants, which are contiguous in depth first order.
+    pub fn subtree(&self, node_index: usize) -> Range<usize> {
+        let end = (node_index + 1..self.nodes.len())
+            .find(|index| self.nodes[*index].parent.is_none_or(|parent| parent < node_index))
+            .unwrap_or(self.nodes.len());
+        node_index..end
+    }
+
+    /// The nodes from an ancestor down to a descendant, or None if end isn't below root.
+    pub fn chain(&self, root_index: usize, end_index: usize) -> Option<Vec<usize>> {
+        let mut chain = vec![end_index];
+        let mut node_index = end_index;
+
+        while node_index != root_index {
+            node_index = self.nodes[node_index].parent?;
+            chain.push(node_index);
+        }
+
+        chain.reverse();
+        Some(chain)
+    }
+
+    /// For each node, the index of the channel in node_animations that animates it.
+    pub fn bind_channels(&self, node_animations: &[NodeAnimation]) -> Vec<Option<usize>> {
+        self.nodes
+            .iter()
+            .map(|node| node_animations.iter().position(|node_animation| node_animation.name == node.name))
+            .collect()
+    }
+}
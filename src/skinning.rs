@@ -0,0 +1,253 @@
+use crate::animator::{Animator, SkinningMethod};
+use crate::dual_quat::DualQuat;
+use crate::model_mesh::{ModelMesh, ModelVertex};
+use glam::{Mat3, Mat4, Vec3};
+use std::thread;
+
+/// The animator's final bone transforms, in the form for the skinning method in use.
+#[derive(Debug, Clone, Copy)]
+pub enum BoneTransforms<'a> {
+    Matrices(&'a [Mat4]),
+    DualQuats(&'a [DualQuat]),
+}
+
+impl BoneTransforms<'_> {
+    fn len(&self) -> usize {
+        match self {
+            BoneTransforms::Matrices(matrices) => matrices.len(),
+            BoneTransforms::DualQuats(dual_quats) => dual_quats.len(),
+        }
+    }
+}
+
+/// Deformed vertex positions and normals, in the same order as the mesh's vertices.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SkinnedVertices {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+}
+
+/// Deforms a vertex the way the skinning vertex shaders do. Vertices without bones are moved by the node matrix,
+/// and those with a bone id outside the bone transforms are left where they are.
+pub fn skin_vertex(vertex: &ModelVertex, bones: BoneTransforms, node_matrix: &Mat4) -> (Vec3, Vec3) {
+    // copied out as the vertex struct is packed
+    let position = vertex.position;
+    let normal = vertex.normal;
+    let bone_ids = vertex.bone_ids;
+    let bone_weights = vertex.bone_weights;
+
+    let influences = bone_ids
+        .iter()
+        .zip(bone_weights.iter())
+        .filter(|(bone_id, weight)| **bone_id >= 0 && **weight != 0.0);
+
+    if influences.clone().any(|(bone_id, _)| *bone_id as usize >= bones.len()) {
+        return (position, normal);
+    }
+
+    let skinned = match bones {
+        BoneTransforms::Matrices(matrices) => influences.clone().next().map(|_| {
+            influences.fold((Vec3::ZERO, Vec3::ZERO), |(skinned_position, skinned_normal), (bone_id, weight)| {
+                let matrix = &matrices[*bone_id as usize];
+                (
+                    skinned_position + matrix.transform_point3(position) * *weight,
+                    skinned_normal + matrix.transform_vector3(normal) * *weight,
+                )
+            })
+        }),
+        BoneTransforms::DualQuats(dual_quats) => {
+            let weighted: Vec<(DualQuat, f32)> = influences
+                .map(|(bone_id, weight)| (dual_quats[*bone_id as usize], *weight))
+                .collect();
+            DualQuat::blend(&weighted).map(|dual_quat| (dual_quat.transform_point(position), dual_quat.transform_vector(normal)))
+        }
+    };
+
+    match skinned {
+        Some((skinned_position, skinned_normal)) => (skinned_position, skinned_normal.normalize_or_zero()),
+        None => (
+            node_matrix.transform_point3(position),
+            (Mat3::from_mat4(*node_matrix) * normal).normalize_or_zero(),
+        ),
+    }
+}
+
+/// Deforms all the vertices.
+pub fn skin_vertices(vertices: &[ModelVertex], bones: BoneTransforms, node_matrix: &Mat4) -> SkinnedVertices {
+    let mut skinned = SkinnedVertices {
+        positions: vec![Vec3::ZERO; vertices.len()],
+        normals: vec![Vec3::ZERO; vertices.len()],
+    };
+    skin_vertices_into(vertices, bones, node_matrix, &mut skinned.positions, &mut skinned.normals);
+    skinned
+}
+
+/// Deforms the vertices into existing buffers, which must be at least as long as vertices.
+pub fn skin_vertices_into(
+    vertices: &[ModelVertex],
+    bones: BoneTransforms,
+    node_matrix: &Mat4,
+    positions: &mut [Vec3],
+    normals: &mut [Vec3],
+) {
+    for ((vertex, position), normal) in vertices.iter().zip(positions.iter_mut()).zip(normals.iter_mut()) {
+        (*position, *normal) = skin_vertex(vertex, bones, node_matrix);
+    }
+}
+
+/// Deforms the vertices split across threads, for large meshes.
+pub fn skin_vertices_parallel(vertices: &[ModelVertex], bones: BoneTransforms, node_matrix: &Mat4, thread_count: usize) -> SkinnedVertices {
+    let mut skinned = SkinnedVertices {
+        positions: vec![Vec3::ZERO; vertices.len()],
+        normals: vec![Vec3::ZERO; vertices.len()],
+    };
+
+    let chunk_size = vertices.len().div_ceil(thread_count.max(1)).max(1);
+
+    thread::scope(|scope| {
+        let chunks = vertices
+            .chunks(chunk_size)
+            .zip(skinned.positions.chunks_mut(chunk_size))
+            .zip(skinned.normals.chunks_mut(chunk_size));
+
+        for ((vertices, positions), normals) in chunks {
+            scope.spawn(move || skin_vertices_into(vertices, bones, node_matrix, positions, normals));
+        }
+    });
+
+    skinned
+}
+
+/// The mesh's vertices morphed and skinned by the animator's current pose, with its skinning method.
+pub fn skin_model_mesh(animator: &Animator, mesh: &ModelMesh) -> SkinnedVertices {
+    let node_matrix = animator.final_node_matrices.borrow()[mesh.id as usize];
+    let morph_weights = animator.morph_weights.borrow();
+    let vertices = mesh.morphed_vertices(morph_weights.get(mesh.id as usize).map_or(&[], Vec::as_slice));
+
+    match animator.skinning_method {
+        SkinningMethod::Linear => skin_vertices(
+            &vertices,
+            BoneTransforms::Matrices(&animator.final_bone_matrices.borrow()),
+            &node_matrix,
+        ),
+        SkinningMethod::DualQuaternion => skin_vertices(
+            &vertices,
+            BoneTransforms::DualQuats(&animator.final_bone_dual_quats.borrow()),
+            &node_matrix,
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::animator::{AnimationRepeat, Animator, SkinningMethod};
+    use crate::hash_map::HashMap;
+    use crate::model_animation::{BoneData, ModelAnimation, NodeData};
+    use crate::model_mesh::ModelVertex;
+    use crate::node_animation::{KeyPosition, KeyRotation, KeyScale, KeyframeBoundary, NodeAnimation};
+    use crate::skinning::{skin_vertices, skin_vertices_parallel, BoneTransforms};
+    use crate::transform::Transform;
+    use glam::{vec3, Mat4, Quat, Vec3};
+    use std::f32::consts::FRAC_PI_2;
+    use std::rc::Rc;
+
+    fn vertex(position: Vec3, bones: &[(i32, f32)]) -> ModelVertex {
+        let mut vertex = ModelVertex::new();
+        vertex.position = position;
+        vertex.normal = Vec3::Y;
+        for (bone_id, weight) in bones {
+            vertex.set_bone_data(*bone_id, *weight);
+        }
+        vertex
+    }
+
+    #[test]
+    fn test_skin_vertices() {
+        let bone_matrices = [Mat4::from_translation(vec3(2.0, 0.0, 0.0)), Mat4::from_rotation_z(FRAC_PI_2)];
+        let node_matrix = Mat4::from_translation(vec3(0.0, 0.0, 5.0));
+
+        let vertices = [
+            vertex(vec3(0.0, 1.0, 0.0), &[(0, 0.5), (1, 0.5)]),
+            vertex(vec3(0.0, 1.0, 0.0), &[]),
+            vertex(vec3(0.0, 1.0, 0.0), &[(7, 1.0)]),
+        ];
+
+        let skinned = skin_vertices(&vertices, BoneTransforms::Matrices(&bone_matrices), &node_matrix);
+
+        // halfway between (2, 1, 0) and (-1, 0, 0)
+        assert!(skinned.positions[0].abs_diff_eq(vec3(0.5, 0.5, 0.0), 0.0001));
+        assert!(skinned.normals[0].abs_diff_eq(vec3(-1.0, 1.0, 0.0).normalize(), 0.0001));
+        assert_eq!(skinned.positions[1], vec3(0.0, 1.0, 5.0));
+        assert_eq!(skinned.positions[2], vec3(0.0, 1.0, 0.0));
+
+        let many: Vec<ModelVertex> = (0..1000).map(|index| vertices[index % 3]).collect();
+        let serial = skin_vertices(&many, BoneTransforms::Matrices(&bone_matrices), &node_matrix);
+        let parallel = skin_vertices_parallel(&many, BoneTransforms::Matrices(&bone_matrices), &node_matrix, 4);
+        assert_eq!(serial, parallel);
+    }
+
+    #[test]
+    fn test_skin_animated_model_without_gpu() {
+        // a bone one unit up the y axis, turning a quarter turn around z over 10 ticks
+        let root_node = NodeData {
+            name: Rc::from("root"),
+            transform: Transform::IDENTITY,
+            children: vec![NodeData {
+                name: Rc::from("arm"),
+                transform: Transform::from_xyz(0.0, 1.0, 0.0),
+                children: vec![],
+                meshes: Rc::new(vec![]),
+            }],
+            meshes: Rc::new(vec![0]),
+        };
+
+        let mut bone_data_map = HashMap::new();
+        bone_data_map.insert(
+            "arm".to_string(),
+            BoneData::new("arm", 0, Mat4::from_translation(vec3(0.0, -1.0, 0.0))),
+        );
+
+        let raise = ModelAnimation {
+            name: Rc::from("raise"),
+            duration: 10.0,
+            ticks_per_second: 10.0,
+            node_animations: vec![NodeAnimation {
+                name: Rc::from("arm"),
+                positions: vec![KeyPosition::new(0.0, vec3(0.0, 1.0, 0.0))],
+                rotations: vec![
+                    KeyRotation::new(0.0, Quat::IDENTITY),
+                    KeyRotation::new(10.0, Quat::from_rotation_z(FRAC_PI_2)),
+                ],
+                quantized_rotations: vec![],
+                scales: vec![KeyScale::new(0.0, Vec3::ONE)],
+                boundary: KeyframeBoundary::Clamp,
+            }]
+            .into(),
+            morph_animations: vec![],
+        };
+
+        let mut animator = Animator::from_parts(root_node, Mat4::IDENTITY, bone_data_map.into(), vec![raise]);
+        let clip = animator.animation_clip("raise", AnimationRepeat::Once).unwrap();
+        animator.play_clip(&clip).unwrap();
+        animator.set_skinning_method(SkinningMethod::DualQuaternion);
+        animator.update_animation(2.0);
+
+        // the hand, one unit along the arm, ends up pointing along -x
+        let hand = [vertex(vec3(0.0, 2.0, 0.0), &[(0, 1.0)])];
+        let node_matrix = animator.final_node_matrices.borrow()[0];
+
+        let linear = skin_vertices(
+            &hand,
+            BoneTransforms::Matrices(&animator.final_bone_matrices.borrow()),
+            &node_matrix,
+        );
+        assert!(linear.positions[0].abs_diff_eq(vec3(-1.0, 1.0, 0.0), 0.0001));
+
+        let dual_quat = skin_vertices(
+            &hand,
+            BoneTransforms::DualQuats(&animator.final_bone_dual_quats.borrow()),
+            &node_matrix,
+        );
+        assert!(dual_quat.positions[0].abs_diff_eq(vec3(-1.0, 1.0, 0.0), 0.0001));
+    }
+}
@@ -0,0 +1,312 @@
+use crate::animator::Animator;
+use crate::error::Error;
+use crate::error::Error::AnimationError;
+use crate::ik::{rotation_between, PoseEditor};
+use crate::skeleton::Skeleton;
+use crate::transform::Transform;
+use glam::{Quat, Vec3};
+
+/// Length of each simulation step. Updates run as many whole steps as fit in the elapsed time,
+/// so the motion is the same at any frame rate.
+pub const SPRING_BONE_TIME_STEP: f32 = 1.0 / 60.0;
+
+/// Elapsed time beyond this many steps in one update is dropped, so a long frame doesn't stall.
+const MAX_STEPS_PER_UPDATE: u32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpringBoneSettings {
+    /// How strongly the bones return to their animated direction, in units per second
+    pub stiffness: f32,
+    /// Fraction of the velocity lost each step, from 0 to 1
+    pub drag: f32,
+    /// Model space pull on the bone tips, in units per second
+    pub gravity: Vec3,
+    /// Radius of the bone tips when pushed out of colliders
+    pub hit_radius: f32,
+}
+
+impl Default for SpringBoneSettings {
+    fn default() -> Self {
+        SpringBoneSettings {
+            stiffness: 1.0,
+            drag: 0.4,
+            gravity: Vec3::ZERO,
+            hit_radius: 0.02,
+        }
+    }
+}
+
+/// A sphere that the tips of spring bones are kept out of, moving with a node.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpringCollider {
+    pub node: usize,
+    /// Center of the sphere in the node's space
+    pub offset: Vec3,
+    pub radius: f32,
+}
+
+impl SpringCollider {
+    pub fn new(animator: &Animator, bone_name: &str, offset: Vec3, radius: f32) -> Result<Self, Error> {
+        Ok(SpringCollider {
+            node: animator.skeleton.find_bone(bone_name)?,
+            offset,
+            radius,
+        })
+    }
+
+    fn center(&self, node_transforms: &[Transform]) -> Vec3 {
+        node_transforms[self.node].transform_point(self.offset)
+    }
+}
+
+/// A chain of bones swinging as damped springs on top of the animation, for hair, tails and cloth strips.
+/// Simulated in the animator's model space after IK.
+#[derive(Debug, Clone)]
+pub struct SpringBoneChain {
+    /// Node indices from the root bone to the end bone. Every bone but the end one swings.
+    pub nodes: Vec<usize>,
+    pub settings: SpringBoneSettings,
+    pub colliders: Vec<SpringCollider>,
+    /// Tip of each swinging bone after the last step, in model space. Empty until the first update.
+    tips: Vec<Vec3>,
+    /// Tips from the step before, the velocity being the difference
+    previous_tips: Vec<Vec3>,
+    /// Elapsed time not yet simulated
+    accumulated_time: f32,
+    /// Scratch space for the animated local rotation of each swinging bone
+    animated: Vec<Quat>,
+}
+
+impl SpringBoneChain {
+    /// A chain from the root bone down to the end bone.
+    pub fn new(animator: &Animator, root_bone: &str, end_bone: &str, settings: SpringBoneSettings) -> Result<Self, Error> {
+        let nodes = animator
+            .skeleton
+            .chain(animator.skeleton.find_bone(root_bone)?, animator.skeleton.find_bone(end_bone)?)
+            .filter(|nodes| nodes.len() > 1)
+            .ok_or_else(|| AnimationError(format!("spring bone: {} is not below: {}", end_bone, root_bone)))?;
+
+        Ok(SpringBoneChain {
+            nodes,
+            settings,
+            colliders: vec![],
+            tips: vec![],
+            previous_tips: vec![],
+            accumulated_time: 0.0,
+            animated: vec![],
+        })
+    }
+
+    pub fn with_colliders(mut self, colliders: Vec<SpringCollider>) -> Self {
+        self.colliders = colliders;
+        self
+    }
+
+    /// Restarts the simulation from the animated pose, e.g. after the model is teleported.
+    pub fn reset(&mut self) {
+        self.tips.clear();
+        self.previous_tips.clear();
+        self.accumulated_time = 0.0;
+    }
+
+    /// Runs the simulation steps that fit in the elapsed time, then turns the bones towards their tips,
+    /// updating the local transforms and the model space node transforms below them.
+    pub fn update(
+        &mut self,
+        delta_time: f32,
+        skeleton: &Skeleton,
+        root_parent: Transform,
+        local_transforms: &mut [Transform],
+        node_transforms: &mut [Transform],
+    ) {
+        let mut animated = std::mem::take(&mut self.animated);
+        animated.clear();
+        animated.extend(
+            self.nodes[..self.nodes.len() - 1]
+                .iter()
+                .map(|joint| local_transforms[*joint].rotation),
+        );
+
+        let mut pose = PoseEditor {
+            skeleton,
+            root_parent,
+            local_transforms,
+            node_transforms,
+        };
+
+        if self.tips.is_empty() {
+            self.tips = self.nodes[1..].iter().map(|node| pose.position(*node)).collect();
+            self.previous_tips = self.tips.clone();
+        }
+
+        let max_time = SPRING_BONE_TIME_STEP * MAX_STEPS_PER_UPDATE as f32;
+        self.accumulated_time = (self.accumulated_time + delta_time).min(max_time);
+        while self.accumulated_time >= SPRING_BONE_TIME_STEP {
+            self.accumulated_time -= SPRING_BONE_TIME_STEP;
+            self.step(&mut pose, &animated);
+        }
+        self.animated = animated;
+
+        // the pose is sampled afresh each update, so the bones are turned even when no step ran
+        for (index, joint) in self.nodes[..self.nodes.len() - 1].iter().enumerate() {
+            let head = pose.position(*joint);
+            let rotation = rotation_between(pose.position(self.nodes[index + 1]) - head, self.tips[index] - head);
+            pose.rotate_joint(*joint, rotation);
+        }
+    }
+
+    /// Moves each tip by its velocity and the forces, keeps it at the bone's length and out of the
+    /// colliders, then turns the bone towards it before moving on to the bones below.
+    fn step(&mut self, pose: &mut PoseEditor, animated: &[Quat]) {
+        let settings = self.settings;
+
+        for (index, joint) in self.nodes[..self.nodes.len() - 1].iter().enumerate() {
+            let child = self.nodes[index + 1];
+            let head = pose.position(*joint);
+            let length = pose.position(child).distance(head);
+
+            let parent_rotation = pose.parent_transform(*joint).rotation;
+            let animated_direction = (parent_rotation * animated[index] * pose.local_transforms[child].translation)
+                .try_normalize()
+                .unwrap_or(Vec3::Y);
+
+            let tip = self.tips[index];
+            let velocity = (tip - self.previous_tips[index]) * (1.0 - settings.drag);
+            let mut next = tip + velocity + (animated_direction * settings.stiffness + settings.gravity) * SPRING_BONE_TIME_STEP;
+            next = head + (next - head).try_normalize().unwrap_or(animated_direction) * length;
+
+            for collider in self.colliders.iter() {
+                let center = collider.center(pose.node_transforms);
+                let radius = collider.radius + settings.hit_radius;
+                if next.distance(center) < radius {
+                    next = push_out(next, head, length, center, radius);
+                }
+            }
+
+            self.previous_tips[index] = tip;
+            self.tips[index] = next;
+            pose.rotate_joint(*joint, rotation_between(pose.position(child) - head, next - head));
+        }
+    }
+}
+
+/// The closest point to the tip that is still the bone's length from its head but outside the sphere,
+/// which lies on the circle where the two spheres meet. When they don't meet the tip is pushed straight out.
+fn push_out(tip: Vec3, head: Vec3, length: f32, center: Vec3, radius: f32) -> Vec3 {
+    let to_center = center - head;
+    let distance = to_center.length();
+    let along = (distance * distance + length * length - radius * radius) / (2.0 * distance);
+    let circle_radius_squared = length * length - along * along;
+
+    if distance <= f32::EPSILON || circle_radius_squared < 0.0 {
+        return center + (tip - center).try_normalize().unwrap_or(Vec3::Y) * radius;
+    }
+
+    let axis = to_center / distance;
+    let to_tip = tip - head;
+    let across = (to_tip - axis * to_tip.dot(axis))
+        .try_normalize()
+        .unwrap_or_else(|| axis.any_orthonormal_vector());
+    head + axis * along + across * circle_radius_squared.sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::animator::Animator;
+    use crate::hash_map::HashMap;
+    use crate::model_animation::{BoneData, NodeData};
+    use crate::spring_bone::{SpringBoneChain, SpringBoneSettings, SpringCollider};
+    use crate::transform::Transform;
+    use glam::{vec3, Mat4, Vec3};
+    use std::rc::Rc;
+
+    const BONES: [&str; 4] = ["spine", "chest", "neck", "head"];
+
+    /// A chain of bones one unit apart along y, with a spring chain from the chest to the head.
+    fn spring_animator(settings: SpringBoneSettings) -> Animator {
+        let mut node: Option<NodeData> = None;
+        for name in BONES.iter().rev() {
+            node = Some(NodeData {
+                name: Rc::from(*name),
+                transform: Transform::from_xyz(0.0, 1.0, 0.0),
+                children: node.into_iter().collect(),
+                meshes: Rc::new(vec![]),
+            });
+        }
+
+        let bone_data_map: HashMap<String, BoneData> = BONES
+            .iter()
+            .enumerate()
+            .map(|(index, name)| (name.to_string(), BoneData::new(name, index as i32, Mat4::IDENTITY)))
+            .collect();
+
+        let mut animator = Animator::from_parts(node.unwrap(), Mat4::IDENTITY, bone_data_map.into(), vec![]);
+        let chain = SpringBoneChain::new(&animator, "chest", "head", settings).unwrap();
+        animator.spring_bones.push(chain);
+        animator
+    }
+
+    fn position(animator: &Animator, name: &str) -> Vec3 {
+        animator.node_transform(name).unwrap().translation
+    }
+
+    #[test]
+    fn test_same_motion_at_any_frame_rate() {
+        let settings = SpringBoneSettings {
+            stiffness: 0.5,
+            gravity: vec3(1.0, -5.0, 0.0),
+            ..Default::default()
+        };
+
+        let mut fast = spring_animator(settings);
+        let mut slow = spring_animator(settings);
+        for frame in 0..120 {
+            fast.update_animation(1.0 / 60.0);
+            if frame % 2 == 1 {
+                slow.update_animation(1.0 / 30.0);
+            }
+        }
+
+        let head = position(&fast, "head");
+        assert!(head.abs_diff_eq(position(&slow, "head"), 0.0001));
+
+        // hanging below the chest, with the bones keeping their length
+        assert!(head.y < position(&fast, "chest").y);
+        assert!((position(&fast, "neck").distance(head) - 1.0).abs() < 0.0001);
+
+        // the skinning matrices follow the simulated pose
+        let head_matrix = fast.final_bone_matrices.borrow()[3];
+        assert!(head_matrix.transform_point3(Vec3::ZERO).abs_diff_eq(head, 0.0001));
+    }
+
+    #[test]
+    fn test_colliders_push_tips_out() {
+        let settings = SpringBoneSettings {
+            stiffness: 0.0,
+            gravity: vec3(5.0, 0.0, 0.0),
+            ..Default::default()
+        };
+
+        let mut animator = spring_animator(settings);
+        let collider = SpringCollider::new(&animator, "spine", vec3(1.5, 1.0, 0.0), 0.8).unwrap();
+        animator.spring_bones[0].colliders.push(collider);
+
+        for _ in 0..120 {
+            animator.update_animation(1.0 / 60.0);
+        }
+
+        let center = vec3(1.5, 2.0, 0.0);
+        for name in ["neck", "head"] {
+            assert!(
+                position(&animator, name).distance(center) >= 0.819,
+                "{}: {}",
+                name,
+                position(&animator, name)
+            );
+        }
+        assert!(position(&animator, "neck").x > 0.3);
+
+        assert!(SpringBoneChain::new(&animator, "head", "chest", settings).is_err());
+        assert!(SpringCollider::new(&animator, "tail", Vec3::ZERO, 1.0).is_err());
+    }
+}
@@ -1,11 +1,12 @@
 use glam::{Affine3A, Mat3, Mat4, Quat, Vec3};
+use serde::{Deserialize, Serialize};
 use std::ops::Mul;
 
 ///
 /// From Bevy crates/bevy_transform/src/components/transform.rs
 ///
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
 pub struct Transform {
     pub translation: Vec3,
     pub rotation: Quat,
@@ -0,0 +1,465 @@
+use crate::animator::{AnimationClip, AnimationRepeat, Animator};
+use crate::error::Error;
+use crate::error::Error::AnimationError;
+use crate::gl;
+use crate::gl::{GLint, GLsizei, GLsizeiptr, GLuint, GLvoid};
+use crate::model::Model;
+use crate::model_mesh::ModelMesh;
+use crate::shader::Shader;
+use crate::skinning::skin_model_mesh;
+use glam::{Mat4, Vec3, Vec4};
+use serde::{Deserialize, Serialize};
+use std::mem;
+use std::rc::Rc;
+
+/// Width in texels of a vertex animation texture. The data runs on from the end of one row to the start of the next.
+pub const VERTEX_ANIMATION_TEXTURE_WIDTH: usize = 4096;
+
+/// Texture unit the baked texture is bound to, below the bone palette's.
+pub const VERTEX_ANIMATION_TEXTURE_UNIT: u32 = 14;
+
+/// Texel indices and counts in the header are floats, which hold whole numbers exactly up to this.
+const MAX_HEADER_VALUE: usize = 1 << 24;
+
+/// Attribute locations of the per instance data, after the mesh's vertex attributes.
+const INSTANCE_TRANSFORM_LOCATION: u32 = 7;
+const INSTANCE_CLIP_LOCATION: u32 = 11;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VertexAnimationFormat {
+    /// Four texels for each bone's skinning matrix followed by four for each mesh's node matrix.
+    /// Small, with the skinning done in the vertex shader.
+    BoneMatrices,
+    /// Two texels for each vertex of every mesh, the skinned position and normal. Larger, but there is no skinning to do.
+    VertexPositions,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BakedClip {
+    pub name: String,
+    /// Index of the first texel of the clip's first frame
+    pub first_texel: usize,
+    pub frame_count: usize,
+    /// Length in seconds of one play through, there and back for PingPong clips
+    pub duration: f32,
+    /// Once clips hold their last frame, the others wrap around
+    pub looping: bool,
+}
+
+/// Clips sampled at a fixed frame rate into a float texture, for drawing crowds without an animator per character.
+/// The texture starts with a header texel of clip count, texels per frame, frame rate and bone count, then a texel per
+/// clip of its first texel, frame count, duration and whether it loops, followed by the frames.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VertexAnimationBake {
+    pub format: VertexAnimationFormat,
+    pub frame_rate: f32,
+    /// Texels in each frame
+    pub frame_stride: usize,
+    pub bone_count: usize,
+    pub clips: Vec<BakedClip>,
+    /// For VertexPositions, the index within a frame of each mesh's first vertex, by mesh id
+    pub mesh_offsets: Vec<usize>,
+    /// Padded to whole rows of VERTEX_ANIMATION_TEXTURE_WIDTH
+    pub texels: Vec<Vec4>,
+}
+
+impl VertexAnimationBake {
+    /// Plays each clip on a copy of the animator, recording a frame every 1 / frame_rate seconds. The frames are the
+    /// animator's output, so they include its layers, IK and spring bones, with any root motion removed.
+    pub fn bake(
+        animator: &Animator,
+        meshes: &[ModelMesh],
+        clips: &[Rc<AnimationClip>],
+        frame_rate: f32,
+        format: VertexAnimationFormat,
+    ) -> Result<Self, Error> {
+        if frame_rate <= 0.0 {
+            return Err(AnimationError(format!("invalid bake frame rate: {}", frame_rate)));
+        }
+
+        let mut animator = animator.clone();
+        let bone_count = animator.final_bone_matrices.borrow().len();
+
+        let mut mesh_offsets = vec![0; meshes.iter().map(|mesh| mesh.id as usize + 1).max().unwrap_or(0)];
+        let mut vertex_count = 0;
+        for mesh in meshes.iter() {
+            mesh_offsets[mesh.id as usize] = vertex_count;
+            vertex_count += mesh.vertices.len();
+        }
+
+        let frame_stride = match format {
+            VertexAnimationFormat::BoneMatrices => (bone_count + animator.final_node_matrices.borrow().len()) * 4,
+            VertexAnimationFormat::VertexPositions => vertex_count * 2,
+        };
+
+        let mut texels = vec![Vec4::ZERO; 1 + clips.len()];
+        let mut baked_clips = vec![];
+
+        for clip in clips.iter() {
+            let name = clip.animation_name.clone().unwrap_or_default();
+
+            animator.transitions.borrow_mut().clear();
+            animator.play_clip(clip)?;
+
+            let mut duration = (clip.end_tick - clip.start_tick).abs() / animator.current_animation.tick_rate().abs();
+            if let AnimationRepeat::PingPong = clip.repeat {
+                duration *= 2.0;
+            }
+            if !duration.is_finite() {
+                return Err(AnimationError(format!("animation has no tick rate: {}", name)));
+            }
+
+            let frame_count = (duration * frame_rate).ceil() as usize + 1;
+            let first_texel = texels.len();
+
+            for frame in 0..frame_count {
+                animator.update_animation(if frame == 0 { 0.0 } else { 1.0 / frame_rate });
+                write_frame(&animator, meshes, format, &mut texels);
+            }
+
+            baked_clips.push(BakedClip {
+                name,
+                first_texel,
+                frame_count,
+                duration,
+                looping: !matches!(clip.repeat, AnimationRepeat::Once),
+            });
+        }
+
+        if texels.len() > MAX_HEADER_VALUE {
+            return Err(AnimationError(format!(
+                "vertex animation bake has too many texels: {} above: {}",
+                texels.len(),
+                MAX_HEADER_VALUE
+            )));
+        }
+
+        texels[0] = Vec4::new(clips.len() as f32, frame_stride as f32, frame_rate, bone_count as f32);
+        for (index, baked_clip) in baked_clips.iter().enumerate() {
+            texels[1 + index] = Vec4::new(
+                baked_clip.first_texel as f32,
+                baked_clip.frame_count as f32,
+                baked_clip.duration,
+                if baked_clip.looping { 1.0 } else { 0.0 },
+            );
+        }
+        texels.resize(
+            texels.len().div_ceil(VERTEX_ANIMATION_TEXTURE_WIDTH) * VERTEX_ANIMATION_TEXTURE_WIDTH,
+            Vec4::ZERO,
+        );
+
+        Ok(VertexAnimationBake {
+            format,
+            frame_rate,
+            frame_stride,
+            bone_count,
+            clips: baked_clips,
+            mesh_offsets,
+            texels,
+        })
+    }
+
+    /// Rows in the texture.
+    pub fn height(&self) -> usize {
+        self.texels.len() / VERTEX_ANIMATION_TEXTURE_WIDTH
+    }
+
+    pub fn frame(&self, clip_index: usize, frame_index: usize) -> &[Vec4] {
+        let start = self.clips[clip_index].first_texel + frame_index * self.frame_stride;
+        &self.texels[start..start + self.frame_stride]
+    }
+
+    /// A bone's skinning matrix in a BoneMatrices bake.
+    pub fn bone_matrix(&self, clip_index: usize, frame_index: usize, bone_index: usize) -> Mat4 {
+        let columns = &self.frame(clip_index, frame_index)[bone_index * 4..bone_index * 4 + 4];
+        Mat4::from_cols(columns[0], columns[1], columns[2], columns[3])
+    }
+
+    /// A skinned vertex position in a VertexPositions bake.
+    pub fn vertex_position(&self, clip_index: usize, frame_index: usize, mesh_id: usize, vertex_index: usize) -> Vec3 {
+        let index = (self.mesh_offsets[mesh_id] + vertex_index) * 2;
+        self.frame(clip_index, frame_index)[index].truncate()
+    }
+}
+
+fn write_frame(animator: &Animator, meshes: &[ModelMesh], format: VertexAnimationFormat, texels: &mut Vec<Vec4>) {
+    match format {
+        VertexAnimationFormat::BoneMatrices => {
+            let final_bones = animator.final_bone_matrices.borrow();
+            let final_nodes = animator.final_node_matrices.borrow();
+            for matrix in final_bones.iter().chain(final_nodes.iter()) {
+                texels.extend([matrix.x_axis, matrix.y_axis, matrix.z_axis, matrix.w_axis]);
+            }
+        }
+        VertexAnimationFormat::VertexPositions => {
+            for mesh in meshes.iter() {
+                let skinned = skin_model_mesh(animator, mesh);
+                for (position, normal) in skinned.positions.iter().zip(skinned.normals.iter()) {
+                    texels.extend([position.extend(1.0), normal.extend(0.0)]);
+                }
+            }
+        }
+    }
+}
+
+/// One character in a crowd. The clip and the time offset into it are all that is animated per instance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(C)]
+pub struct VertexAnimationInstance {
+    pub transform: Mat4,
+    pub clip_index: f32,
+    pub time_offset: f32,
+}
+
+impl VertexAnimationInstance {
+    pub fn new(transform: Mat4, clip_index: usize, time_offset: f32) -> Self {
+        VertexAnimationInstance {
+            transform,
+            clip_index: clip_index as f32,
+            time_offset,
+        }
+    }
+}
+
+/// Draws instances of a model animated by a baked texture, with one draw call per mesh. The per instance data is
+/// added to the meshes' vertex arrays, for shaders like examples/sample_animation/vat_bones.vert.
+#[derive(Debug)]
+pub struct VertexAnimationRenderer {
+    meshes: Rc<Vec<ModelMesh>>,
+    mesh_offsets: Vec<usize>,
+    texture: GLuint,
+    instance_buffer: GLuint,
+    instance_count: usize,
+}
+
+impl VertexAnimationRenderer {
+    /// Errors if the bake is taller than the largest texture the driver supports.
+    pub fn new(model: &Model, bake: &VertexAnimationBake) -> Result<Self, Error> {
+        let mut max_texture_size: GLint = 0;
+        unsafe {
+            gl::GetIntegerv(gl::MAX_TEXTURE_SIZE, &mut max_texture_size);
+        }
+        if bake.height() > max_texture_size as usize {
+            return Err(AnimationError(format!(
+                "vertex animation texture height: {} is above the maximum texture size: {}",
+                bake.height(),
+                max_texture_size
+            )));
+        }
+
+        let mut texture: GLuint = 0;
+        let mut instance_buffer: GLuint = 0;
+        let stride = mem::size_of::<VertexAnimationInstance>() as GLsizei;
+
+        unsafe {
+            gl::GenTextures(1, &mut texture);
+            gl::BindTexture(gl::TEXTURE_2D, texture);
+            gl::TexImage2D(
+                gl::TEXTURE_2D,
+                0,
+                gl::RGBA32F as GLint,
+                VERTEX_ANIMATION_TEXTURE_WIDTH as GLsizei,
+                bake.height() as GLsizei,
+                0,
+                gl::RGBA,
+                gl::FLOAT,
+                bake.texels.as_ptr() as *const GLvoid,
+            );
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+
+            gl::GenBuffers(1, &mut instance_buffer);
+            gl::BindBuffer(gl::ARRAY_BUFFER, instance_buffer);
+
+            for mesh in model.meshes.iter() {
+                gl::BindVertexArray(mesh.vao);
+
+                // the instance transform, a column per location
+                for column in 0..4 {
+                    let location = INSTANCE_TRANSFORM_LOCATION + column;
+                    gl::EnableVertexAttribArray(location);
+                    gl::VertexAttribPointer(
+                        location,
+                        4,
+                        gl::FLOAT,
+                        gl::FALSE,
+                        stride,
+                        (column as usize * mem::size_of::<Vec4>()) as *const GLvoid,
+                    );
+                    gl::VertexAttribDivisor(location, 1);
+                }
+
+                // clip index and time offset
+                gl::EnableVertexAttribArray(INSTANCE_CLIP_LOCATION);
+                gl::VertexAttribPointer(
+                    INSTANCE_CLIP_LOCATION,
+                    2,
+                    gl::FLOAT,
+                    gl::FALSE,
+                    stride,
+                    mem::offset_of!(VertexAnimationInstance, clip_index) as *const GLvoid,
+                );
+                gl::VertexAttribDivisor(INSTANCE_CLIP_LOCATION, 1);
+            }
+
+            gl::BindVertexArray(0);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+
+        Ok(VertexAnimationRenderer {
+            meshes: model.meshes.clone(),
+            mesh_offsets: bake.mesh_offsets.clone(),
+            texture,
+            instance_buffer,
+            instance_count: 0,
+        })
+    }
+
+    pub fn set_instances(&mut self, instances: &[VertexAnimationInstance]) {
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.instance_buffer);
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                mem::size_of_val(instances) as GLsizeiptr,
+                instances.as_ptr() as *const GLvoid,
+                gl::DYNAMIC_DRAW,
+            );
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+        self.instance_count = instances.len();
+    }
+
+    /// Draws every instance at its clip's time plus its offset.
+    pub fn render(&self, shader: &Shader, time: f32) {
+        if self.instance_count == 0 {
+            return;
+        }
+
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0 + VERTEX_ANIMATION_TEXTURE_UNIT);
+            gl::BindTexture(gl::TEXTURE_2D, self.texture);
+        }
+        shader.set_int("vatTexture", VERTEX_ANIMATION_TEXTURE_UNIT as i32);
+        shader.set_float("vatTime", time);
+
+        for mesh in self.meshes.iter() {
+            shader.set_int("vatMeshId", mesh.id);
+            shader.set_int(
+                "vatMeshOffset",
+                self.mesh_offsets.get(mesh.id as usize).copied().unwrap_or(0) as i32,
+            );
+            mesh.render_instanced(shader, self.instance_count);
+        }
+    }
+}
+
+impl Drop for VertexAnimationRenderer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.texture);
+            gl::DeleteBuffers(1, &self.instance_buffer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::animator::{AnimationClip, AnimationRepeat, Animator};
+    use crate::hash_map::HashMap;
+    use crate::model_animation::{BoneData, ModelAnimation, NodeData};
+    use crate::node_animation::{KeyPosition, KeyRotation, KeyScale, KeyframeBoundary, NodeAnimation};
+    use crate::transform::Transform;
+    use crate::vertex_animation::{VertexAnimationBake, VertexAnimationFormat, VERTEX_ANIMATION_TEXTURE_WIDTH};
+    use glam::{vec3, vec4, Mat4, Quat, Vec3};
+    use std::f32::consts::FRAC_PI_2;
+    use std::rc::Rc;
+
+    /// An arm bone turning a quarter turn around z over 10 ticks at 10 ticks per second.
+    fn arm_animator() -> Animator {
+        let root_node = NodeData {
+            name: Rc::from("root"),
+            transform: Transform::IDENTITY,
+            children: vec![NodeData {
+                name: Rc::from("arm"),
+                transform: Transform::from_xyz(0.0, 1.0, 0.0),
+                children: vec![],
+                meshes: Rc::new(vec![]),
+            }],
+            meshes: Rc::new(vec![0]),
+        };
+
+        let mut bone_data_map = HashMap::new();
+        bone_data_map.insert(
+            "arm".to_string(),
+            BoneData::new("arm", 0, Mat4::from_translation(vec3(0.0, -1.0, 0.0))),
+        );
+
+        let raise = ModelAnimation {
+            name: Rc::from("raise"),
+            duration: 10.0,
+            ticks_per_second: 10.0,
+            node_animations: vec![NodeAnimation {
+                name: Rc::from("arm"),
+                positions: vec![KeyPosition::new(0.0, vec3(0.0, 1.0, 0.0))],
+                rotations: vec![
+                    KeyRotation::new(0.0, Quat::IDENTITY),
+                    KeyRotation::new(10.0, Quat::from_rotation_z(FRAC_PI_2)),
+                ],
+                quantized_rotations: vec![],
+                scales: vec![KeyScale::new(0.0, Vec3::ONE)],
+                boundary: KeyframeBoundary::Clamp,
+            }]
+            .into(),
+            morph_animations: vec![],
+        };
+
+        Animator::from_parts(root_node, Mat4::IDENTITY, bone_data_map.into(), vec![raise])
+    }
+
+    #[test]
+    fn test_bake_matches_animator() {
+        let animator = arm_animator();
+        let once = Rc::new(AnimationClip::from_animation("raise", 0.0, 10.0, AnimationRepeat::Once));
+        let ping_pong = Rc::new(AnimationClip::from_animation("raise", 0.0, 10.0, AnimationRepeat::PingPong));
+
+        let bake = VertexAnimationBake::bake(
+            &animator,
+            &[],
+            &[once.clone(), ping_pong.clone()],
+            10.0,
+            VertexAnimationFormat::BoneMatrices,
+        )
+        .unwrap();
+
+        // one bone and one mesh node matrix per frame, 11 frames for the second long clip and 21 there and back
+        assert_eq!(bake.frame_stride, 8);
+        assert_eq!(bake.clips[0].frame_count, 11);
+        assert_eq!(bake.clips[1].frame_count, 21);
+        assert_eq!(bake.clips[1].duration, 2.0);
+        assert!(!bake.clips[0].looping && bake.clips[1].looping);
+        assert_eq!(bake.texels.len() % VERTEX_ANIMATION_TEXTURE_WIDTH, 0);
+        assert_eq!(bake.texels[0], vec4(2.0, 8.0, 10.0, 1.0));
+        assert_eq!(bake.texels[2], vec4(bake.clips[1].first_texel as f32, 21.0, 2.0, 1.0));
+
+        for (clip_index, clip, frame, time) in [(0, &once, 5, 0.5), (0, &once, 10, 1.0), (1, &ping_pong, 15, 1.5)] {
+            let mut expected = arm_animator();
+            expected.play_clip(clip).unwrap();
+            expected.update_animation(0.0);
+            expected.update_animation(time);
+
+            let baked = bake.bone_matrix(clip_index, frame, 0);
+            assert!(
+                baked.abs_diff_eq(expected.final_bone_matrices.borrow()[0], 0.0001),
+                "clip {} frame {}",
+                clip_index,
+                frame
+            );
+        }
+
+        let missing = Rc::new(AnimationClip::from_animation("wave", 0.0, 10.0, AnimationRepeat::Once));
+        assert!(VertexAnimationBake::bake(&animator, &[], &[missing], 10.0, VertexAnimationFormat::BoneMatrices).is_err());
+    }
+}